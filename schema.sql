@@ -1,20 +1,42 @@
+DROP TABLE IF EXISTS lorawan_devices;
+DROP TABLE IF EXISTS events;
+DROP TABLE IF EXISTS commands;
+DROP TABLE IF EXISTS alerts;
+DROP TABLE IF EXISTS alert_rules;
+DROP TABLE IF EXISTS telemetry;
+DROP TABLE IF EXISTS qc_flags;
+DROP TABLE IF EXISTS measurements;
+DROP TABLE IF EXISTS calibrations;
+DROP TABLE IF EXISTS installations;
+DROP TABLE IF EXISTS sensors;
 DROP TABLE IF EXISTS data;
 DROP TABLE IF EXISTS history;
 DROP TABLE IF EXISTS trips;
 DROP TABLE IF EXISTS paths;
+DROP TABLE IF EXISTS devices;
+DROP TABLE IF EXISTS quantities;
 DROP TYPE IF EXISTS layer;
+DROP TYPE IF EXISTS alert_condition;
+DROP TYPE IF EXISTS event_kind;
+DROP TYPE IF EXISTS fix_type;
 
 CREATE TYPE layer AS ENUM ('surface', 'middle', 'sea bed');
+CREATE TYPE alert_condition AS ENUM ('temperature_above', 'temperature_below', 'battery_below', 'silent');
+CREATE TYPE event_kind AS ENUM ('off_path', 'geofence');
+CREATE TYPE fix_type AS ENUM ('none', 'gps', 'dgps', 'pps', 'rtk', 'float_rtk', 'estimated', 'manual', 'simulation');
 
-CREATE TABLE IF NOT EXISTS history (
-  time TIMESTAMPTZ PRIMARY KEY,
-  location JSON NOT NULL
+CREATE TABLE IF NOT EXISTS devices (
+  id TEXT PRIMARY KEY,
+  secret TEXT NOT NULL
 );
 
 CREATE TABLE IF NOT EXISTS paths (
   uuid UUID PRIMARY KEY,
   name NAME NOT NULL,
-  path JSON[] NOT NULL
+  path JSON[] NOT NULL,
+  corridor FLOAT8 NOT NULL DEFAULT 20,
+  area JSON[],
+  return_home BOOLEAN NOT NULL DEFAULT FALSE
 );
 
 CREATE TABLE IF NOT EXISTS trips (
@@ -23,6 +45,28 @@ CREATE TABLE IF NOT EXISTS trips (
   path UUID NOT NULL REFERENCES paths
 );
 
+CREATE TABLE IF NOT EXISTS history (
+  id SERIAL PRIMARY KEY,
+  time TIMESTAMPTZ NOT NULL,
+  location JSON NOT NULL,
+  device TEXT NOT NULL REFERENCES devices,
+  trip UUID REFERENCES trips,
+  deviation FLOAT8,
+  outside BOOLEAN NOT NULL DEFAULT FALSE,
+  heading FLOAT8,
+  hdop FLOAT8,
+  satellites INTEGER,
+  fix fix_type,
+  poor BOOLEAN NOT NULL DEFAULT FALSE,
+  speed FLOAT8,
+  course FLOAT8
+);
+
+-- A device has a single fix at a time in each trip, the copies of a restored trip have their own
+CREATE UNIQUE INDEX IF NOT EXISTS history_trip_fix ON history (trip, device, time)
+  WHERE trip IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS history_fix ON history (device, time) WHERE trip IS NULL;
+
 CREATE TABLE IF NOT EXISTS data (
   id SERIAL PRIMARY KEY,
   temperature FLOAT8 NOT NULL,
@@ -30,5 +74,134 @@ CREATE TABLE IF NOT EXISTS data (
   depth FLOAT8 NOT NULL,
   layer layer NOT NULL,
   time TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
-  trip UUID NOT NULL REFERENCES trips
+  trip UUID NOT NULL REFERENCES trips,
+  device TEXT REFERENCES devices
+);
+
+CREATE TABLE IF NOT EXISTS quantities (
+  name TEXT PRIMARY KEY,
+  unit TEXT NOT NULL,
+  minimum FLOAT8,
+  maximum FLOAT8,
+  spike FLOAT8
+);
+
+INSERT INTO quantities (name, unit, minimum, maximum, spike) VALUES
+  ('temperature', '°C', -2, 40, 2),
+  ('ph', 'pH', 0, 14, 0.5),
+  ('dissolved_oxygen', 'mg/L', 0, 20, 2),
+  ('conductivity', 'mS/cm', 0, 70, 5),
+  ('salinity', 'PSU', 0, 42, 2),
+  ('turbidity', 'NTU', 0, 1000, 50);
+
+CREATE TABLE IF NOT EXISTS measurements (
+  data INTEGER NOT NULL REFERENCES data ON DELETE CASCADE,
+  quantity TEXT NOT NULL REFERENCES quantities,
+  value FLOAT8 NOT NULL,
+  PRIMARY KEY (data, quantity)
+);
+
+CREATE TABLE IF NOT EXISTS sensors (
+  serial TEXT PRIMARY KEY,
+  quantity TEXT NOT NULL REFERENCES quantities,
+  minimum FLOAT8,
+  maximum FLOAT8
+);
+
+CREATE TABLE IF NOT EXISTS installations (
+  sensor TEXT NOT NULL REFERENCES sensors ON DELETE CASCADE,
+  device TEXT NOT NULL REFERENCES devices ON DELETE CASCADE,
+  installed_from TIMESTAMPTZ NOT NULL,
+  installed_to TIMESTAMPTZ,
+  PRIMARY KEY (sensor, installed_from),
+  CHECK (installed_to > installed_from)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS installations_current ON installations (sensor)
+  WHERE installed_to IS NULL;
+
+CREATE TABLE IF NOT EXISTS calibrations (
+  sensor TEXT NOT NULL REFERENCES sensors ON DELETE CASCADE,
+  valid_from TIMESTAMPTZ NOT NULL,
+  coefficients FLOAT8[] NOT NULL,
+  PRIMARY KEY (sensor, valid_from)
+);
+
+-- Calibrates a raw value with the offset, gain and higher order coefficients of a calibration,
+-- as the server does, and returns it as it is without a calibration
+CREATE OR REPLACE FUNCTION calibrate(coefficients FLOAT8[], raw FLOAT8) RETURNS FLOAT8 AS $$
+  SELECT COALESCE(coefficients[1], 0) + COALESCE(coefficients[2], 1) * raw + COALESCE((
+    SELECT SUM(c * raw ^ (i + 1)) FROM UNNEST(coefficients[3:]) WITH ORDINALITY AS u(c, i)
+  ), 0)
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE TABLE IF NOT EXISTS qc_flags (
+  data INTEGER NOT NULL REFERENCES data ON DELETE CASCADE,
+  test TEXT NOT NULL,
+  flag SMALLINT NOT NULL,
+  PRIMARY KEY (data, test)
+);
+
+-- Combines the flags of all the quality control tests into the flag of a reading, as the server
+-- does: the worst evaluated flag, or not evaluated without one
+CREATE OR REPLACE FUNCTION qc_flag(flags SMALLINT[]) RETURNS SMALLINT AS $$
+  SELECT COALESCE(MAX(flag), 2::SMALLINT) FROM UNNEST(flags) AS flag WHERE flag <> 2
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE TABLE IF NOT EXISTS telemetry (
+  id SERIAL PRIMARY KEY,
+  device TEXT NOT NULL REFERENCES devices,
+  time TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  battery FLOAT8 NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS alert_rules (
+  id SERIAL PRIMARY KEY,
+  name TEXT NOT NULL,
+  condition alert_condition NOT NULL,
+  threshold FLOAT8 NOT NULL,
+  device TEXT REFERENCES devices ON DELETE CASCADE,
+  path UUID REFERENCES paths ON DELETE CASCADE,
+  webhook TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS alerts (
+  id SERIAL PRIMARY KEY,
+  rule INTEGER NOT NULL REFERENCES alert_rules ON DELETE CASCADE,
+  device TEXT NOT NULL REFERENCES devices ON DELETE CASCADE,
+  time TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  value FLOAT8 NOT NULL,
+  resolved_at TIMESTAMPTZ,
+  delivered_at TIMESTAMPTZ,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_error TEXT
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS alerts_open ON alerts (rule, device) WHERE resolved_at IS NULL;
+
+CREATE TABLE IF NOT EXISTS commands (
+  id SERIAL PRIMARY KEY,
+  device TEXT NOT NULL REFERENCES devices ON DELETE CASCADE,
+  command TEXT NOT NULL,
+  arguments JSON NOT NULL DEFAULT '{}',
+  time TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  delivered_at TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS events (
+  id SERIAL PRIMARY KEY,
+  kind event_kind NOT NULL,
+  device TEXT REFERENCES devices ON DELETE CASCADE,
+  trip UUID NOT NULL REFERENCES trips ON DELETE CASCADE,
+  time TIMESTAMPTZ NOT NULL,
+  location JSON NOT NULL,
+  deviation FLOAT8,
+  command INTEGER REFERENCES commands ON DELETE SET NULL
+);
+
+CREATE TABLE IF NOT EXISTS lorawan_devices (
+  dev_eui TEXT PRIMARY KEY,
+  device TEXT NOT NULL REFERENCES devices ON DELETE CASCADE,
+  f_cnt BIGINT,
+  received_at TIMESTAMPTZ
 );
@@ -0,0 +1,207 @@
+//! Module for Actix services for the aggregated time series of the temperatures.
+
+use actix_web::{
+    get,
+    web::{scope, Data, Json, Query, ServiceConfig},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::{
+    data::{Layer, QualityType, ValuesType},
+    qc,
+    quantities::TEMPERATURE,
+    selection::Selection,
+};
+
+#[derive(OpenApi)]
+#[openapi(paths(get_aggregate), components(schemas(BucketSize, Bucket)))]
+/// The OpenAPI specification of the aggregation API resources.
+pub struct AggregateApi;
+
+/// Configuration function for the aggregation API resources.
+pub fn aggregate_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(scope("/aggregate").service(get_aggregate));
+}
+
+#[derive(Deserialize, ToSchema, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+/// The size of the time buckets, aligned in UTC.
+enum BucketSize {
+    /// Buckets of a minute.
+    Minute,
+    #[default]
+    /// Buckets of an hour.
+    Hour,
+    /// Buckets of a day.
+    Day,
+}
+
+impl BucketSize {
+    /// The field to truncate the times to.
+    fn field(self) -> &'static str {
+        match self {
+            Self::Minute => "minute",
+            Self::Hour => "hour",
+            Self::Day => "day",
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+/// The query specification for the aggregation.
+struct AggregateQuery {
+    #[serde(default)]
+    #[param(inline)]
+    /// The size of the time buckets.
+    bucket: BucketSize,
+    #[serde(default)]
+    #[param(example = "layer,device")]
+    /// The comma separated fields to group the buckets by, any of `layer`, `trip`, `path`
+    /// and `device`.
+    group_by: String,
+}
+
+#[derive(Default, Debug)]
+/// The fields the buckets are grouped by.
+struct Grouping {
+    layer: bool,
+    trip: bool,
+    path: bool,
+    device: bool,
+}
+
+impl Grouping {
+    /// Parses the comma separated fields.
+    fn parse(fields: &str) -> Result<Self> {
+        let mut grouping = Self::default();
+        for field in fields.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            match field {
+                "layer" => grouping.layer = true,
+                "trip" => grouping.trip = true,
+                "path" => grouping.path = true,
+                "device" => grouping.device = true,
+                _ => {
+                    return Err(ApiError::BadRequest(format!(
+                        "Cannot Group By {field}, Expected layer, trip, path Or device"
+                    )))
+                }
+            }
+        }
+        Ok(grouping)
+    }
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+/// The statistics of the temperatures in a time bucket.
+struct Bucket {
+    #[serde(with = "time::serde::rfc3339")]
+    /// The start of the bucket.
+    time: OffsetDateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The layer of the readings, if grouped by layer.
+    layer: Option<Layer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The trip of the readings, if grouped by trip.
+    trip: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The path of the readings, if grouped by path.
+    path: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The device of the readings, if grouped by device.
+    device: Option<String>,
+    /// The number of readings.
+    count: i64,
+    /// The lowest temperature.
+    minimum: f64,
+    /// The highest temperature.
+    maximum: f64,
+    /// The mean temperature.
+    mean: f64,
+}
+
+#[utoipa::path(
+    context_path = "/api/aggregate",
+    tag = "aggregate",
+    params(Selection, AggregateQuery),
+    responses(
+        (status = 200, description = "The statistics of the temperatures, oldest bucket first.", body = [Bucket]),
+        (status = 400, description = "The parameters are not valid.", body = ErrorBody),
+        (status = 404, description = "The trip or path does not exist.", body = ErrorBody),
+    )
+)]
+#[get("")]
+/// Gets the minimum, maximum, mean and count of the temperatures bucketed by time.
+///
+/// The readings are calibrated and aggregated in the database so they are never loaded by the
+/// server, which keeps long deployments cheap to chart.
+async fn get_aggregate(
+    selection: Query<Selection>,
+    query: Query<AggregateQuery>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    let grouping = Grouping::parse(&query.group_by)?;
+    selection.check(&state.pool).await?;
+    let buckets = sqlx::query_as!(
+        Bucket,
+        r#"WITH readings AS (
+  SELECT data.time, data.layer, data.trip, trips.path, data.device,
+   calibrate(calibration.coefficients, data.temperature) AS temperature
+  FROM data
+  JOIN trips ON trips.uuid = data.trip
+  LEFT JOIN LATERAL (
+    SELECT calibrations.coefficients FROM installations
+    JOIN sensors ON sensors.serial = installations.sensor
+    JOIN calibrations ON calibrations.sensor = installations.sensor
+    WHERE $5 AND installations.device = data.device AND sensors.quantity = $6
+     AND installations.installed_from <= data.time
+     AND (installations.installed_to IS NULL OR data.time < installations.installed_to)
+     AND calibrations.valid_from <= data.time
+    ORDER BY calibrations.valid_from DESC
+    LIMIT 1
+  ) AS calibration ON TRUE
+  WHERE ($1::UUID IS NULL OR data.trip = $1)
+   AND ($2::UUID IS NULL OR trips.path = $2)
+   AND ($3::TIMESTAMPTZ IS NULL OR data.time >= $3)
+   AND ($4::TIMESTAMPTZ IS NULL OR data.time < $4)
+   AND (NOT $7
+    OR qc_flag(ARRAY(SELECT qc_flags.flag FROM qc_flags WHERE qc_flags.data = data.id)) = $13)
+)
+SELECT date_trunc($8, time, 'UTC') AS "time!",
+ CASE WHEN $9 THEN layer END AS "layer: Layer",
+ CASE WHEN $10 THEN trip END AS trip,
+ CASE WHEN $11 THEN path END AS path,
+ CASE WHEN $12 THEN device END AS device,
+ COUNT(*) AS "count!", MIN(temperature) AS "minimum!", MAX(temperature) AS "maximum!",
+ AVG(temperature) AS "mean!"
+FROM readings
+GROUP BY 1, 2, 3, 4, 5
+ORDER BY 1, 2, 3, 4, 5"#,
+        selection.trip,
+        selection.path,
+        selection.from,
+        selection.to,
+        selection.values == ValuesType::Calibrated,
+        TEMPERATURE,
+        selection.quality == QualityType::Good,
+        query.bucket.field(),
+        grouping.layer,
+        grouping.trip,
+        grouping.path,
+        grouping.device,
+        qc::PASS
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(buckets))
+}
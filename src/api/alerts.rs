@@ -0,0 +1,875 @@
+//! Module for the alerts raised on the data, GPS and telemetry of the devices.
+//!
+//! The rules are evaluated as the devices report, and by a periodic check for silent devices.
+//! An alert is raised when the condition of a rule starts to hold for a device, and is resolved
+//! when it stops holding, so a rule notifies its webhook once per incident.
+
+use std::{
+    collections::HashSet,
+    net::{IpAddr, ToSocketAddrs},
+    time::Duration,
+};
+
+use actix_web::{
+    delete, get, post,
+    web::{self, scope, Data, Json, Path, Query, ServiceConfig},
+    HttpRequest, Responder,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use time::OffsetDateTime;
+use tokio::sync::mpsc::{error::TrySendError, Receiver, Sender};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::{check_admin, quantities::TEMPERATURE, sensors::Calibrations};
+
+/// The environment variable of the webhook hosts allowed on internal networks, comma separated.
+const WEBHOOK_ALLOWED_HOSTS_ENV: &str = "WEBHOOK_ALLOWED_HOSTS";
+/// The time to wait for a webhook to respond.
+pub const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// The number of alerts which can wait for their delivery to start.
+pub const ALERT_QUEUE_SIZE: usize = 1024;
+/// The number of times the delivery of an alert is attempted.
+const MAX_ATTEMPTS: i32 = 5;
+/// The delay before retrying a failed delivery, doubled after every attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// The interval between the checks for silent devices.
+const SILENCE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// How old a reading can be and still raise alerts, older readings are logged afterwards.
+const LIVE_DELAY: time::Duration = time::Duration::minutes(5);
+
+/// The sending half of the queue of alerts to deliver, by ID.
+pub type AlertSender = Sender<i32>;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_rules, add_rule, delete_rule, get_alerts),
+    components(schemas(AlertCondition, AlertRule, AlertRuleInput, AlertValues))
+)]
+/// The OpenAPI specification of the alerts API resources.
+pub struct AlertsApi;
+
+/// Configuration function for the alerts API resources.
+pub fn alerts_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/alerts")
+            .service(get_rules)
+            .service(add_rule)
+            .service(delete_rule)
+            .service(get_alerts),
+    );
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "alert_condition", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+/// The conditions alerts are raised on.
+pub enum AlertCondition {
+    /// The temperature in °C is above the threshold.
+    TemperatureAbove,
+    /// The temperature in °C is below the threshold.
+    TemperatureBelow,
+    /// The charge of the battery in percent is below the threshold.
+    BatteryBelow,
+    /// The device has not reported for longer than the threshold in seconds.
+    Silent,
+}
+
+impl AlertCondition {
+    /// Whether the condition holds for an observation, `None` if it does not apply to it.
+    fn holds(self, threshold: f64, observation: &Observation) -> Option<bool> {
+        match (self, observation) {
+            (Self::TemperatureAbove, Observation::Temperature { value, .. }) => {
+                Some(*value > threshold)
+            }
+            (Self::TemperatureBelow, Observation::Temperature { value, .. }) => {
+                Some(*value < threshold)
+            }
+            (Self::BatteryBelow, Observation::Battery(value)) => Some(*value < threshold),
+            (Self::Silent, Observation::Silence(seconds)) => Some(*seconds > threshold),
+            _ => None,
+        }
+    }
+
+    /// Whether the condition is on the temperature readings.
+    fn is_temperature(self) -> bool {
+        matches!(self, Self::TemperatureAbove | Self::TemperatureBelow)
+    }
+}
+
+/// An observation of a device the rules are evaluated on.
+pub enum Observation {
+    /// A raw temperature reading, calibrated before it is evaluated.
+    Temperature {
+        /// The trip the reading is collected in.
+        trip: Uuid,
+        /// The time the reading is measured.
+        time: OffsetDateTime,
+        /// The temperature measured.
+        value: f64,
+    },
+    /// The charge of the battery in percent.
+    Battery(f64),
+    /// The number of seconds since the device last reported.
+    Silence(f64),
+}
+
+impl Observation {
+    /// The value recorded in the alerts raised on the observation.
+    fn value(&self) -> f64 {
+        match self {
+            Self::Temperature { value, .. } => *value,
+            Self::Battery(value) | Self::Silence(value) => *value,
+        }
+    }
+}
+
+#[derive(FromRow, Debug)]
+/// The part of a rule needed to evaluate it.
+struct RuleCondition {
+    id: i32,
+    condition: AlertCondition,
+    threshold: f64,
+}
+
+/// Evaluates the rules of a device on an observation, the errors are only logged so reporting
+/// never fails because of the alerts.
+pub async fn observe(state: &AppState, device: &str, observation: Observation) {
+    if let Err(e) = evaluate(state, device, observation).await {
+        tracing::error!(device, "Alert Evaluation Failed: {e:?}");
+    }
+}
+
+/// Whether a reading measured at a time is recent enough to raise alerts.
+pub fn is_live(time: OffsetDateTime) -> bool {
+    OffsetDateTime::now_utc() - time <= LIVE_DELAY
+}
+
+/// Resolves the alerts of a device being silent, as the device just reported.
+pub async fn seen(state: &AppState, device: &str) {
+    observe(state, device, Observation::Silence(0.0)).await;
+}
+
+/// Raises or resolves the alerts of the rules applying to an observation.
+async fn evaluate(state: &AppState, device: &str, mut observation: Observation) -> Result<()> {
+    let trip = match &mut observation {
+        Observation::Temperature { trip, time, value } => {
+            let devices = [device.to_string()];
+            *value = Calibrations::for_devices(&state.pool, &devices)
+                .await?
+                .apply(Some(device), TEMPERATURE, *time, *value);
+            Some(*trip)
+        }
+        _ => None,
+    };
+    let rules = sqlx::query_as!(
+        RuleCondition,
+        r#"SELECT id, condition AS "condition: AlertCondition", threshold FROM alert_rules
+WHERE (device IS NULL OR device = $1)
+ AND (path IS NULL OR path = (SELECT path FROM trips WHERE uuid = $2))"#,
+        device,
+        trip
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    for rule in rules {
+        match rule.condition.holds(rule.threshold, &observation) {
+            Some(true) => raise(state, rule.id, device, observation.value()).await?,
+            Some(false) => resolve(&state.pool, rule.id, device).await?,
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Raises an alert unless the rule already has an open alert for the device.
+async fn raise(state: &AppState, rule: i32, device: &str, value: f64) -> Result<()> {
+    let id = sqlx::query_scalar!(
+        "INSERT INTO alerts (rule, device, value) VALUES ($1, $2, $3)
+ON CONFLICT (rule, device) WHERE resolved_at IS NULL DO NOTHING
+RETURNING id",
+        rule,
+        device,
+        value
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+    if let Some(id) = id {
+        tracing::warn!(alert = id, rule, device, value, "Alert Raised");
+        state.metrics.inserted("alerts", 1);
+        match state.alerts.try_send(id) {
+            Ok(()) => {}
+            // The alert stays undelivered in the database, and is queued again on the restart
+            Err(TrySendError::Full(_)) => tracing::error!(alert = id, "Alert Queue Is Full"),
+            Err(TrySendError::Closed(_)) => {
+                tracing::error!(alert = id, "Alert Delivery Is Not Running")
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resolves the open alert of a rule for a device, if any.
+async fn resolve(pool: &PgPool, rule: i32, device: &str) -> Result<()> {
+    let resolved = sqlx::query!(
+        "UPDATE alerts SET resolved_at = CURRENT_TIMESTAMP
+WHERE rule = $1 AND device = $2 AND resolved_at IS NULL",
+        rule,
+        device
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if resolved > 0 {
+        tracing::info!(rule, device, "Alert Resolved");
+    }
+    Ok(())
+}
+
+/// Periodically raises the alerts of the devices which stopped reporting.
+///
+/// Devices which never reported are not considered silent.
+pub async fn check_silence(state: Data<AppState>) {
+    let mut interval = tokio::time::interval(SILENCE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let devices = sqlx::query!(
+            r#"SELECT id AS "id!", EXTRACT(EPOCH FROM CURRENT_TIMESTAMP - last_seen)::FLOAT8 AS "silence!"
+FROM (
+  SELECT devices.id, GREATEST(
+   (SELECT MAX(time) FROM data WHERE data.device = devices.id),
+   (SELECT MAX(time) FROM history WHERE history.device = devices.id),
+   (SELECT MAX(time) FROM telemetry WHERE telemetry.device = devices.id)
+  ) AS last_seen
+  FROM devices
+  WHERE EXISTS(SELECT 1 FROM alert_rules WHERE alert_rules.condition = 'silent'
+   AND (alert_rules.device IS NULL OR alert_rules.device = devices.id))
+) AS devices
+WHERE last_seen IS NOT NULL"#
+        )
+        .fetch_all(&state.pool)
+        .await;
+        match devices {
+            Ok(devices) => {
+                for device in devices {
+                    observe(&state, &device.id, Observation::Silence(device.silence)).await;
+                }
+            }
+            Err(e) => tracing::error!("Silence Check Failed: {e:?}"),
+        }
+    }
+}
+
+#[derive(Serialize, FromRow, Debug)]
+/// The body posted to the webhook of a rule when an alert is raised.
+struct Notification {
+    #[serde(skip)]
+    /// The URL of the webhook.
+    webhook: String,
+    #[serde(skip)]
+    /// The number of deliveries already attempted.
+    attempts: i32,
+    /// The ID of the alert.
+    id: i32,
+    /// The ID of the rule.
+    rule: i32,
+    /// The name of the rule.
+    name: String,
+    /// The condition of the rule.
+    condition: AlertCondition,
+    /// The threshold of the rule.
+    threshold: f64,
+    /// The device the alert is raised for.
+    device: String,
+    /// The value which raised the alert.
+    value: f64,
+    #[serde(with = "time::serde::rfc3339")]
+    /// The time the alert is raised.
+    time: OffsetDateTime,
+}
+
+/// Delivers the alerts queued to the webhooks of their rules.
+///
+/// The alerts whose delivery was queued or waiting for a retry when the server stopped are
+/// delivered first.
+pub async fn deliver_alerts(pool: PgPool, client: reqwest::Client, mut alerts: Receiver<i32>) {
+    let mut requeued = match undelivered(&pool).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            tracing::error!("Undelivered Alerts Not Queued: {e:?}");
+            HashSet::new()
+        }
+    };
+    if !requeued.is_empty() {
+        tracing::info!(count = requeued.len(), "Undelivered Alerts Queued");
+    }
+    for id in &requeued {
+        tokio::spawn(deliver(pool.clone(), client.clone(), *id, RETRY_DELAY));
+    }
+    while let Some(id) = alerts.recv().await {
+        // An alert raised while the undelivered alerts were read is only delivered once
+        if !requeued.remove(&id) {
+            tokio::spawn(deliver(pool.clone(), client.clone(), id, RETRY_DELAY));
+        }
+    }
+}
+
+/// Gets the alerts which are not delivered and have attempts left.
+async fn undelivered(pool: &PgPool) -> Result<HashSet<i32>> {
+    let ids = sqlx::query_scalar!(
+        "SELECT id FROM alerts WHERE delivered_at IS NULL AND attempts < $1",
+        MAX_ATTEMPTS
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(ids.into_iter().collect())
+}
+
+/// Posts an alert to the webhook of its rule, retrying with an exponential backoff from a delay
+/// until it has been attempted `MAX_ATTEMPTS` times.
+async fn deliver(pool: PgPool, client: reqwest::Client, id: i32, retry_delay: Duration) {
+    let notification = sqlx::query_as!(
+        Notification,
+        r#"SELECT alert_rules.webhook, alerts.attempts, alerts.id, alerts.rule, alert_rules.name,
+ alert_rules.condition AS "condition: AlertCondition", alert_rules.threshold, alerts.device,
+ alerts.value, alerts.time
+FROM alerts
+JOIN alert_rules ON alert_rules.id = alerts.rule
+WHERE alerts.id = $1"#,
+        id
+    )
+    .fetch_one(&pool)
+    .await;
+    let notification = match notification {
+        Ok(notification) => notification,
+        Err(e) => {
+            tracing::error!(alert = id, "Alert Delivery Failed: {e:?}");
+            return;
+        }
+    };
+
+    // Checked again as the host may resolve elsewhere since the rule was created
+    if let Err(e) = check_webhook(&notification.webhook).await {
+        tracing::error!(alert = id, "Alert Delivery Refused: {e}");
+        let updated = sqlx::query!(
+            "UPDATE alerts SET last_error = $2 WHERE id = $1",
+            id,
+            e.to_string()
+        )
+        .execute(&pool)
+        .await;
+        if let Err(e) = updated {
+            tracing::error!(alert = id, "Alert Delivery Status Not Stored: {e:?}");
+        }
+        return;
+    }
+
+    let mut delay = retry_delay;
+    for attempt in notification.attempts + 1..=MAX_ATTEMPTS {
+        // Redirects are not followed, so they fail as any other response which is not a success
+        let error = match client
+            .post(&notification.webhook)
+            .json(&notification)
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => None,
+            Ok(response) => Some(format!("Webhook Responded {}", response.status())),
+            Err(e) => Some(e.to_string()),
+        };
+        let updated = sqlx::query!(
+            "UPDATE alerts SET attempts = $2, last_error = $3,
+ delivered_at = CASE WHEN $3::TEXT IS NULL THEN CURRENT_TIMESTAMP END
+WHERE id = $1",
+            id,
+            attempt,
+            error
+        )
+        .execute(&pool)
+        .await;
+        if let Err(e) = updated {
+            tracing::error!(alert = id, "Alert Delivery Status Not Stored: {e:?}");
+        }
+        match error {
+            None => {
+                tracing::info!(alert = id, attempt, "Alert Delivered");
+                return;
+            }
+            Some(e) => tracing::warn!(alert = id, attempt, "Alert Delivery Failed: {e}"),
+        }
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+    tracing::error!(alert = id, "Alert Delivery Abandoned");
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+/// The data format for alert rules.
+struct AlertRule {
+    /// The ID of the rule.
+    id: i32,
+    /// The name of the rule.
+    name: String,
+    /// The condition of the rule.
+    condition: AlertCondition,
+    /// The threshold of the condition, in the unit of the condition.
+    threshold: f64,
+    /// The device the rule applies to, all devices if `null`.
+    device: Option<String>,
+    /// The path the rule applies to, all paths if `null`.
+    path: Option<Uuid>,
+    /// The URL the alerts are posted to.
+    webhook: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/alerts",
+    tag = "alerts",
+    responses((status = 200, description = "All the alert rules.", body = [AlertRule]))
+)]
+#[get("/rules")]
+/// Gets all the alert rules.
+async fn get_rules(state: Data<AppState>) -> Result<impl Responder> {
+    let rules = sqlx::query_as!(
+        AlertRule,
+        r#"SELECT id, name, condition AS "condition: AlertCondition", threshold, device, path,
+ webhook
+FROM alert_rules ORDER BY id"#
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(rules))
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+/// The information needed to create an alert rule.
+struct AlertRuleInput {
+    #[schema(example = "Hot water")]
+    /// The name of the rule.
+    name: String,
+    /// The condition of the rule.
+    condition: AlertCondition,
+    #[schema(example = 30.0)]
+    /// The threshold of the condition, in °C, percent or seconds.
+    threshold: f64,
+    /// The device the rule applies to, all devices if not given.
+    device: Option<String>,
+    /// The path the rule applies to, only for temperature conditions.
+    path: Option<Uuid>,
+    #[schema(example = "https://example.com/alerts")]
+    /// The HTTP or HTTPS URL the alerts are posted to.
+    webhook: String,
+}
+
+impl AlertRuleInput {
+    /// Checks that the rule can be evaluated and delivered.
+    fn validate(&self) -> Result<()> {
+        if !self.threshold.is_finite() {
+            return Err(ApiError::BadRequest("Threshold Must Be Finite".to_string()));
+        }
+        if self.condition == AlertCondition::Silent && self.threshold <= 0.0 {
+            return Err(ApiError::BadRequest(
+                "Silence Threshold Must Be Positive".to_string(),
+            ));
+        }
+        if self.path.is_some() && !self.condition.is_temperature() {
+            return Err(ApiError::BadRequest(
+                "Only Temperature Rules Can Apply To A Path".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Checks that a webhook is an HTTP URL outside of the server and its local networks, unless
+/// its host is allowed by `WEBHOOK_ALLOWED_HOSTS`, so the rules cannot make the server post to
+/// its internal services.
+async fn check_webhook(webhook: &str) -> Result<()> {
+    let url = reqwest::Url::parse(webhook)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .ok_or_else(|| ApiError::BadRequest(format!("Webhook {webhook} Is Not An HTTP URL")))?;
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    let allowed = std::env::var(WEBHOOK_ALLOWED_HOSTS_ENV).unwrap_or_default();
+    if allowed
+        .split(',')
+        .any(|allowed| allowed.trim().eq_ignore_ascii_case(&host))
+    {
+        return Ok(());
+    }
+
+    let port = url.port_or_known_default().unwrap_or(80);
+    let name = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let addresses = web::block(move || (name.as_str(), port).to_socket_addrs())
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))?
+        .map_err(|_| ApiError::BadRequest(format!("Webhook Host {host} Is Not Found")))?;
+    for address in addresses {
+        if is_internal(address.ip()) {
+            return Err(ApiError::BadRequest(format!(
+                "Webhook Host {host} Is On An Internal Network"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Whether an address is of the server itself or of a loopback, link-local or private network.
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // The shared address space of carrier-grade NAT
+                || (a == 100 && b & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // The unique local and link-local addresses
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+                || ip.to_ipv4_mapped().is_some_and(|ip| is_internal(ip.into()))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/alerts",
+    tag = "alerts",
+    request_body = AlertRuleInput,
+    responses(
+        (status = 200, description = "The alert rule is created.", body = AlertRule),
+        (status = 400, description = "The rule is not valid.", body = ErrorBody),
+        (status = 401, description = "The bearer token is not valid.", body = ErrorBody),
+        (status = 422, description = "The device or path does not exist.", body = ErrorBody),
+    )
+)]
+#[post("/rules")]
+/// Creates an alert rule.
+///
+/// Only the administrators can create rules, with the `Authorization: Bearer` header, and the
+/// webhook must not be on an internal network unless its host is in `WEBHOOK_ALLOWED_HOSTS`.
+async fn add_rule(
+    req: HttpRequest,
+    rule: Json<AlertRuleInput>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    check_admin(&req)?;
+    rule.validate()?;
+    check_webhook(&rule.webhook).await?;
+    let rule = sqlx::query_as!(
+        AlertRule,
+        r#"INSERT INTO alert_rules (name, condition, threshold, device, path, webhook)
+VALUES ($1, $2, $3, $4, $5, $6)
+RETURNING id, name, condition AS "condition: AlertCondition", threshold, device, path,
+ webhook"#,
+        rule.name,
+        rule.condition as AlertCondition,
+        rule.threshold,
+        rule.device,
+        rule.path,
+        rule.webhook
+    )
+    .fetch_one(&state.pool)
+    .await?;
+    state.metrics.inserted("alert_rules", 1);
+    Ok(Json(rule))
+}
+
+#[utoipa::path(
+    context_path = "/api/alerts",
+    tag = "alerts",
+    params(("id" = i32, Path, description = "The ID of the rule.")),
+    responses(
+        (status = 200, description = "The alert rule and its alerts are deleted."),
+        (status = 401, description = "The bearer token is not valid.", body = ErrorBody),
+        (status = 404, description = "The rule does not exist.", body = ErrorBody),
+    )
+)]
+#[delete("/rules/{id}")]
+/// Deletes an alert rule and its alerts.
+///
+/// Only the administrators can delete rules, with the `Authorization: Bearer` header.
+async fn delete_rule(
+    req: HttpRequest,
+    id: Path<i32>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    check_admin(&req)?;
+    let deleted = sqlx::query!("DELETE FROM alert_rules WHERE id = $1", *id)
+        .execute(&state.pool)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(ApiError::NotFound(format!("Alert Rule {id}")));
+    }
+    Ok("")
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+/// The query specification for getting the alert history.
+struct AlertsQuery {
+    /// The device to get the alerts of, all devices if not given.
+    device: Option<String>,
+    #[serde(default)]
+    /// Whether only the alerts which are not resolved are returned.
+    open: bool,
+    #[serde(default = "AlertsQuery::count_default")]
+    /// The amount of alerts to get.
+    count: i64,
+}
+
+impl AlertsQuery {
+    /// Defaults count to 100 alerts.
+    fn count_default() -> i64 {
+        100
+    }
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+/// The data format for the alert history.
+struct AlertValues {
+    /// The ID of the alert.
+    id: i32,
+    /// The ID of the rule.
+    rule: i32,
+    /// The name of the rule.
+    name: String,
+    /// The condition of the rule.
+    condition: AlertCondition,
+    /// The device the alert is raised for.
+    device: String,
+    /// The value which raised the alert.
+    value: f64,
+    #[serde(with = "time::serde::rfc3339")]
+    /// The time the alert is raised.
+    time: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    /// The time the condition stopped holding, `null` while the alert is open.
+    resolved_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    /// The time the webhook accepted the alert, `null` if not delivered yet.
+    delivered_at: Option<OffsetDateTime>,
+    /// The number of delivery attempts.
+    attempts: i32,
+    /// The error of the last failed delivery attempt.
+    last_error: Option<String>,
+}
+
+#[utoipa::path(
+    context_path = "/api/alerts",
+    tag = "alerts",
+    params(AlertsQuery),
+    responses(
+        (status = 200, description = "The latest alerts.", body = [AlertValues]),
+        (status = 400, description = "The query is not valid.", body = ErrorBody),
+    )
+)]
+#[get("")]
+/// Gets the history of the alerts, latest first.
+async fn get_alerts(query: Query<AlertsQuery>, state: Data<AppState>) -> Result<impl Responder> {
+    let alerts = sqlx::query_as!(
+        AlertValues,
+        r#"SELECT alerts.id, alerts.rule, alert_rules.name,
+ alert_rules.condition AS "condition: AlertCondition", alerts.device, alerts.value,
+ alerts.time, alerts.resolved_at, alerts.delivered_at, alerts.attempts, alerts.last_error
+FROM alerts
+JOIN alert_rules ON alert_rules.id = alerts.rule
+WHERE ($1::TEXT IS NULL OR alerts.device = $1) AND (NOT $2 OR alerts.resolved_at IS NULL)
+ORDER BY alerts.time DESC, alerts.id DESC LIMIT $3"#,
+        query.device,
+        query.open,
+        query.count
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(alerts))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use sqlx::PgPool;
+    use uuid::Uuid;
+
+    use super::{check_webhook, deliver, MAX_ATTEMPTS, WEBHOOK_ALLOWED_HOSTS_ENV};
+
+    /// The delivery status of an alert.
+    type Status = (i32, Option<String>, bool);
+
+    /// Starts a webhook which fails the given number of requests and then accepts them,
+    /// returning its URL and the number of requests received.
+    fn webhook(failures: usize) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let server = HttpServer::new(move || {
+            let counter = counter.clone();
+            App::new().default_service(web::to(move |body: web::Json<serde_json::Value>| {
+                let request = counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if request < failures || body.get("id").is_none() {
+                        HttpResponse::InternalServerError().finish()
+                    } else {
+                        HttpResponse::Ok().finish()
+                    }
+                }
+            }))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .expect("Webhook Not Bound");
+        let url = format!("http://{}/hook", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        (url, requests)
+    }
+
+    /// Delivers an alert of a new rule posting to a webhook after a number of attempts,
+    /// returning the delivery status.
+    async fn deliver_to(pool: &PgPool, url: &str, attempts: i32) -> Status {
+        std::env::set_var(WEBHOOK_ALLOWED_HOSTS_ENV, "127.0.0.1");
+        let device = format!("webhook-{}", Uuid::new_v4());
+        sqlx::query("INSERT INTO devices (id, secret) VALUES ($1, '')")
+            .bind(&device)
+            .execute(pool)
+            .await
+            .unwrap();
+        let rule: i32 = sqlx::query_scalar(
+            "INSERT INTO alert_rules (name, condition, threshold, webhook)
+VALUES ('Webhook Test', 'temperature_above', 30, $1) RETURNING id",
+        )
+        .bind(url)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        let id: i32 = sqlx::query_scalar(
+            "INSERT INTO alerts (rule, device, value, attempts) VALUES ($1, $2, 31, $3)
+RETURNING id",
+        )
+        .bind(rule)
+        .bind(&device)
+        .bind(attempts)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+
+        deliver(
+            pool.clone(),
+            reqwest::Client::new(),
+            id,
+            Duration::from_millis(10),
+        )
+        .await;
+        let status = sqlx::query_as(
+            "SELECT attempts, last_error, delivered_at IS NOT NULL FROM alerts WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        sqlx::query("DELETE FROM devices WHERE id = $1")
+            .bind(&device)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM alert_rules WHERE id = $1")
+            .bind(rule)
+            .execute(pool)
+            .await
+            .unwrap();
+        status
+    }
+
+    /// Connects to the database with the schema at `DATABASE_URL`.
+    async fn pool() -> PgPool {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL Not Set");
+        PgPool::connect(&url).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn webhook_on_internal_networks_is_rejected() {
+        for webhook in [
+            "http://127.0.0.2/hook",
+            "http://localhost:8000/hook",
+            "http://10.1.2.3/hook",
+            "http://172.16.0.1/hook",
+            "http://192.168.1.10/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.64.0.1/hook",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:10.0.0.1]/hook",
+            "ftp://example.com/hook",
+        ] {
+            assert!(check_webhook(webhook).await.is_err(), "{webhook}");
+        }
+        for webhook in [
+            "https://93.184.216.34/hook",
+            "http://[2606:4700::1111]:8080/hook",
+        ] {
+            assert!(check_webhook(webhook).await.is_ok(), "{webhook}");
+        }
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires a database at DATABASE_URL"]
+    async fn webhook_is_retried_until_delivered() {
+        let pool = pool().await;
+        let (url, requests) = webhook(2);
+        let (attempts, last_error, delivered) = deliver_to(&pool, &url, 0).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(attempts, 3);
+        assert_eq!(last_error, None);
+        assert!(delivered);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires a database at DATABASE_URL"]
+    async fn webhook_delivery_is_abandoned() {
+        let pool = pool().await;
+        let (url, requests) = webhook(usize::MAX);
+        let (attempts, last_error, delivered) = deliver_to(&pool, &url, 0).await;
+        assert_eq!(requests.load(Ordering::SeqCst), MAX_ATTEMPTS as usize);
+        assert_eq!(attempts, MAX_ATTEMPTS);
+        assert!(last_error.is_some_and(|e| e.contains("500")));
+        assert!(!delivered);
+    }
+    #[actix_web::test]
+    #[ignore = "requires a database at DATABASE_URL"]
+    async fn webhook_delivery_resumes_after_the_attempts() {
+        let pool = pool().await;
+        let (url, requests) = webhook(usize::MAX);
+        let (attempts, _, delivered) = deliver_to(&pool, &url, 3).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(attempts, MAX_ATTEMPTS);
+        assert!(!delivered);
+    }
+}
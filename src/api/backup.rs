@@ -0,0 +1,1257 @@
+//! Module for Actix services for backing up and restoring the whole database.
+//!
+//! The archive is NDJSON. Its first line is the manifest, with the version of the archive and
+//! the number of rows of each table, and each other line is a row of a table, as
+//! `{"table": ..., "row": {...}}` with the columns of the row, the rows of each table together.
+//! The tables share a single file rather than a file each, so the archive is streamed and
+//! downloaded as is, without an archive format such as zip. The archive contains the secrets
+//! of the devices, so the resources require the token in the `ADMIN_TOKEN` environment variable.
+//!
+//! The rows are restored into an empty or an existing database, a chunk of rows at a time. The
+//! quantities, devices, sensors, installations, calibrations and LoRaWAN devices which already
+//! exist are kept. The paths and trips which already exist are handled by the conflict mode of
+//! the restore, and the gps history, data and events of a trip follow its trip. The rows of the
+//! other tables are restored under new IDs, skipping the gps fixes outside of trips, telemetry,
+//! alerts, rules and commands which already exist.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    body::{BodySize, MessageBody},
+    get, post,
+    web::{scope, Bytes, Data, Json, PayloadConfig, Query, ServiceConfig},
+    HttpRequest, HttpResponse, Responder,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{Executor, PgConnection, Postgres, Transaction};
+use time::{format_description::FormatItem, macros::format_description, OffsetDateTime};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::check_admin;
+
+/// The format in the manifest of the archives.
+const ARCHIVE_FORMAT: &str = "awtc-backup";
+/// The version of the archives, raised whenever the columns of the tables change.
+const ARCHIVE_VERSION: u32 = 3;
+/// The content type of the archives.
+const NDJSON: &str = "application/x-ndjson";
+/// The number of rows read from or written to the database at a time.
+const CHUNK_SIZE: usize = 1000;
+/// The number of chunks of an exported archive buffered for a slow client.
+const EXPORT_BUFFER: usize = 4;
+/// The maximum size of a restored archive in bytes, which is held in memory while restored.
+const MAX_ARCHIVE_SIZE: usize = 256 * 1024 * 1024;
+/// The format of the time in the file names of the archives.
+const FILE_TIME_FORMAT: &[FormatItem<'static>] =
+    format_description!("[year][month][day]T[hour][minute][second]Z");
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_backup, restore_backup),
+    components(schemas(Manifest, Table, ConflictMode, RestoreReport, TableReport))
+)]
+/// The OpenAPI specification of the backup API resources.
+pub struct BackupApi;
+
+/// Configuration function for the backup API resources.
+pub fn backup_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/backup").service(get_backup).service(
+            scope("/restore")
+                .app_data(PayloadConfig::new(MAX_ARCHIVE_SIZE))
+                .service(restore_backup),
+        ),
+    );
+}
+
+#[derive(
+    Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "snake_case")]
+/// A table of the archive.
+enum Table {
+    /// The measured quantities.
+    Quantities,
+    /// The devices, with their secrets.
+    Devices,
+    /// The paths.
+    Paths,
+    /// The trips.
+    Trips,
+    /// The sensors of the devices.
+    Sensors,
+    /// The periods the sensors are installed on the devices.
+    Installations,
+    /// The calibrations of the sensors.
+    Calibrations,
+    /// The gps fixes.
+    History,
+    /// The readings.
+    Data,
+    /// The other quantities measured in the readings.
+    Measurements,
+    /// The quality control flags of the readings.
+    QcFlags,
+    /// The telemetry of the devices.
+    Telemetry,
+    /// The alert rules.
+    AlertRules,
+    /// The alerts raised by the rules.
+    Alerts,
+    /// The commands queued for the devices.
+    Commands,
+    /// The events of the trips.
+    Events,
+    /// The mappings of the LoRaWAN devices.
+    LorawanDevices,
+}
+
+impl Table {
+    /// All the tables, each after the tables it references.
+    const ALL: [Table; 17] = [
+        Table::Quantities,
+        Table::Devices,
+        Table::Paths,
+        Table::Trips,
+        Table::Sensors,
+        Table::Installations,
+        Table::Calibrations,
+        Table::History,
+        Table::Data,
+        Table::Measurements,
+        Table::QcFlags,
+        Table::Telemetry,
+        Table::AlertRules,
+        Table::Alerts,
+        Table::Commands,
+        Table::Events,
+        Table::LorawanDevices,
+    ];
+
+    /// The name of the table in the database.
+    fn name(self) -> &'static str {
+        match self {
+            Table::Quantities => "quantities",
+            Table::Devices => "devices",
+            Table::Paths => "paths",
+            Table::Trips => "trips",
+            Table::Sensors => "sensors",
+            Table::Installations => "installations",
+            Table::Calibrations => "calibrations",
+            Table::History => "history",
+            Table::Data => "data",
+            Table::Measurements => "measurements",
+            Table::QcFlags => "qc_flags",
+            Table::Telemetry => "telemetry",
+            Table::AlertRules => "alert_rules",
+            Table::Alerts => "alerts",
+            Table::Commands => "commands",
+            Table::Events => "events",
+            Table::LorawanDevices => "lorawan_devices",
+        }
+    }
+
+    /// The query of the rows of the table as JSON objects of their columns, in a stable order.
+    fn export_query(self) -> &'static str {
+        match self {
+            Table::Quantities => "SELECT row_to_json(t) FROM quantities t ORDER BY name",
+            Table::Devices => "SELECT row_to_json(t) FROM devices t ORDER BY id",
+            Table::Paths => "SELECT row_to_json(t) FROM paths t ORDER BY uuid",
+            Table::Trips => "SELECT row_to_json(t) FROM trips t ORDER BY time, uuid",
+            Table::Sensors => "SELECT row_to_json(t) FROM sensors t ORDER BY serial",
+            Table::Installations => {
+                "SELECT row_to_json(t) FROM installations t ORDER BY sensor, installed_from"
+            }
+            Table::Calibrations => {
+                "SELECT row_to_json(t) FROM calibrations t ORDER BY sensor, valid_from"
+            }
+            Table::History => "SELECT row_to_json(t) FROM history t ORDER BY id",
+            Table::Data => "SELECT row_to_json(t) FROM data t ORDER BY id",
+            Table::Measurements => {
+                "SELECT row_to_json(t) FROM measurements t ORDER BY data, quantity"
+            }
+            Table::QcFlags => "SELECT row_to_json(t) FROM qc_flags t ORDER BY data, test",
+            Table::Telemetry => "SELECT row_to_json(t) FROM telemetry t ORDER BY id",
+            Table::AlertRules => "SELECT row_to_json(t) FROM alert_rules t ORDER BY id",
+            Table::Alerts => "SELECT row_to_json(t) FROM alerts t ORDER BY id",
+            Table::Commands => "SELECT row_to_json(t) FROM commands t ORDER BY id",
+            Table::Events => "SELECT row_to_json(t) FROM events t ORDER BY id",
+            Table::LorawanDevices => {
+                "SELECT row_to_json(t) FROM lorawan_devices t ORDER BY dev_eui"
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+/// The first line of an archive.
+struct Manifest {
+    #[schema(example = "awtc-backup")]
+    /// The format of the archive, always `awtc-backup`.
+    format: String,
+    /// The version of the archive.
+    version: u32,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    /// The time the archive was exported.
+    created_at: OffsetDateTime,
+    /// The number of rows of each table.
+    tables: BTreeMap<Table, usize>,
+}
+
+/// The columns of a row, keyed by their names.
+type Record = Map<String, Value>;
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A line of an archive after the manifest.
+struct Line {
+    /// The table of the row.
+    table: Table,
+    /// The columns of the row.
+    row: Value,
+}
+
+#[utoipa::path(
+    context_path = "/api/backup",
+    tag = "backup",
+    responses(
+        (status = 200, description = "The NDJSON archive of the whole database.", content_type = "application/x-ndjson", body = Manifest),
+        (status = 401, description = "The bearer token is not valid.", body = ErrorBody),
+    )
+)]
+#[get("")]
+/// Exports all the tables as an archive.
+///
+/// The tables are read from a single snapshot of the database, so the archive is consistent
+/// while the devices keep reporting. The archive is sent while its rows are read, and a
+/// failure after the manifest ends it early, which the restore rejects by the manifest.
+async fn get_backup(req: HttpRequest, state: Data<AppState>) -> Result<impl Responder> {
+    check_admin(&req)?;
+    let mut tx = state.pool.begin().await?;
+    (&mut *tx)
+        .execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .await?;
+    let mut tables = BTreeMap::new();
+    for table in Table::ALL {
+        let rows: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table.name()))
+            .fetch_one(&mut *tx)
+            .await?;
+        tables.insert(table, rows as usize);
+    }
+
+    let manifest = Manifest {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        created_at: OffsetDateTime::now_utc(),
+        tables,
+    };
+    let mut first = serde_json::to_vec(&manifest)?;
+    first.push(b'\n');
+    let time = manifest
+        .created_at
+        .format(FILE_TIME_FORMAT)
+        .map_err(|e| ApiError::Internal(e.to_string()))?;
+    let (chunks, receiver) = mpsc::channel(EXPORT_BUFFER);
+    // The channel is empty, so the manifest always fits
+    let _ = chunks.try_send(Ok(Bytes::from(first)));
+    tokio::spawn(async move {
+        match export_rows(tx, &chunks).await {
+            Ok(rows) => tracing::info!(rows, "Backup Exported"),
+            Err(e) => {
+                tracing::error!("Backup Failed: {e}");
+                let _ = chunks.send(Err(e)).await;
+            }
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type(NDJSON)
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"awtc-backup-{time}.ndjson\""),
+        ))
+        .body(ArchiveBody { chunks: receiver }))
+}
+
+/// Reads the rows of all the tables from the snapshot of the transaction in chunks, sending
+/// them as lines of the archive, and returns the number of rows.
+async fn export_rows(
+    mut tx: Transaction<'static, Postgres>,
+    chunks: &Sender<Result<Bytes>>,
+) -> Result<usize> {
+    let mut rows = 0;
+    for table in Table::ALL {
+        let cursor = format!(
+            "DECLARE export NO SCROLL CURSOR FOR {}",
+            table.export_query()
+        );
+        (&mut *tx).execute(cursor.as_str()).await?;
+        loop {
+            let chunk: Vec<Value> = sqlx::query_scalar(&format!("FETCH {CHUNK_SIZE} FROM export"))
+                .fetch_all(&mut *tx)
+                .await?;
+            if chunk.is_empty() {
+                break;
+            }
+            rows += chunk.len();
+            let mut lines = Vec::new();
+            for row in chunk {
+                serde_json::to_writer(&mut lines, &Line { table, row })?;
+                lines.push(b'\n');
+            }
+            if chunks.send(Ok(Bytes::from(lines))).await.is_err() {
+                return Err(ApiError::Internal("Backup Download Cancelled".to_string()));
+            }
+        }
+        (&mut *tx).execute("CLOSE export").await?;
+    }
+    tx.commit().await?;
+    Ok(rows)
+}
+
+/// The body of an archive, sent as its chunks are read.
+struct ArchiveBody {
+    /// The chunks of the archive, ending early with the error of a failed export.
+    chunks: Receiver<Result<Bytes>>,
+}
+
+impl MessageBody for ArchiveBody {
+    type Error = actix_web::Error;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Bytes, Self::Error>>> {
+        self.chunks
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map_err(Into::into)))
+    }
+}
+
+#[derive(Deserialize, ToSchema, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// How the paths and trips of an archive which already exist are restored.
+enum ConflictMode {
+    #[default]
+    /// Nothing is restored if any of them exists.
+    Fail,
+    /// The existing paths and trips are kept, and the rows of the trips in the archive skipped.
+    Skip,
+    /// The existing paths and trips are overwritten, and the rows of the trips replaced.
+    Replace,
+    /// The paths and trips are restored as copies with new UUIDs.
+    Copy,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+/// The query specification for restoring an archive.
+struct RestoreQuery {
+    #[serde(default)]
+    #[param(inline)]
+    /// The query to specify how the paths and trips which already exist are restored.
+    conflict: ConflictMode,
+}
+
+#[derive(Serialize, ToSchema, Debug, Default)]
+/// The result of restoring an archive.
+struct RestoreReport {
+    /// The number of rows restored and skipped of each table.
+    tables: BTreeMap<Table, TableReport>,
+    /// The new UUIDs of the copied paths and trips, keyed by their UUIDs in the archive.
+    copied: BTreeMap<Uuid, Uuid>,
+}
+
+#[derive(Serialize, ToSchema, Debug, Default)]
+/// The result of restoring the rows of a table.
+struct TableReport {
+    /// The number of rows restored.
+    restored: u64,
+    /// The number of rows which already exist or belong to a skipped trip.
+    skipped: u64,
+}
+
+#[utoipa::path(
+    context_path = "/api/backup/restore",
+    tag = "backup",
+    params(RestoreQuery),
+    request_body(content = String, content_type = "application/x-ndjson", description = "An archive exported by the backup endpoint."),
+    responses(
+        (status = 200, description = "The archive is restored.", body = RestoreReport),
+        (status = 400, description = "The archive is not valid.", body = ErrorBody),
+        (status = 401, description = "The bearer token is not valid.", body = ErrorBody),
+        (status = 409, description = "A path or trip already exists in the `fail` mode.", body = ErrorBody),
+        (status = 413, description = "The archive is larger than 256 MiB."),
+    )
+)]
+#[post("")]
+/// Restores an archive into the database.
+///
+/// The archive is restored in a single transaction, so nothing is restored if any row fails.
+/// The whole archive is held in memory while it is restored, with only a chunk of its rows
+/// parsed at a time, so archives are limited to 256 MiB.
+async fn restore_backup(
+    req: HttpRequest,
+    query: Query<RestoreQuery>,
+    body: Bytes,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    check_admin(&req)?;
+    let archive = parse_archive(&body)?;
+    let mut restore = Restore {
+        tx: state.pool.begin().await?,
+        archive,
+        mode: query.conflict,
+        report: RestoreReport::default(),
+        skipped_trips: HashSet::new(),
+        ids: HashMap::new(),
+    };
+    restore.run().await?;
+    let Restore { tx, report, .. } = restore;
+    tx.commit().await?;
+
+    for (table, counts) in &report.tables {
+        state.metrics.inserted(table.name(), counts.restored);
+    }
+    let rows: u64 = report.tables.values().map(|counts| counts.restored).sum();
+    tracing::info!(rows, mode = ?query.conflict, "Backup Restored");
+    Ok(Json(report))
+}
+
+/// The lines of an archive with their line numbers.
+type Lines<'a> = Vec<(usize, &'a str)>;
+
+/// Splits an archive into the lines of each table, checking them against the manifest.
+fn parse_archive(archive: &[u8]) -> Result<BTreeMap<Table, Lines<'_>>> {
+    let archive = std::str::from_utf8(archive)
+        .map_err(|_| ApiError::BadRequest("Archive Is Not UTF-8 Text".to_string()))?;
+    let mut lines = archive
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let (_, manifest) = lines
+        .next()
+        .ok_or_else(|| ApiError::BadRequest("Archive Is Empty".to_string()))?;
+    let manifest: Manifest = serde_json::from_str(manifest)
+        .map_err(|e| ApiError::BadRequest(format!("Bad Manifest: {e}")))?;
+    if manifest.format != ARCHIVE_FORMAT {
+        return Err(ApiError::BadRequest(format!(
+            "Archive Format {} Is Not {ARCHIVE_FORMAT}",
+            manifest.format
+        )));
+    }
+    if manifest.version != ARCHIVE_VERSION {
+        return Err(ApiError::BadRequest(format!(
+            "Archive Version {} Is Not Supported",
+            manifest.version
+        )));
+    }
+
+    let mut tables: BTreeMap<Table, Lines> = BTreeMap::new();
+    for (i, line) in lines {
+        let header: LineTable = serde_json::from_str(line)
+            .map_err(|e| ApiError::BadRequest(format!("Bad Row On Line {}: {e}", i + 1)))?;
+        tables.entry(header.table).or_default().push((i + 1, line));
+    }
+    // A truncated archive is rejected rather than partially restored
+    for table in Table::ALL {
+        let expected = manifest.tables.get(&table).copied().unwrap_or(0);
+        let found = tables.get(&table).map_or(0, Vec::len);
+        if found != expected {
+            return Err(ApiError::BadRequest(format!(
+                "Archive Has {found} Rows Of {} Instead Of {expected}",
+                table.name()
+            )));
+        }
+    }
+    Ok(tables)
+}
+
+#[derive(Deserialize)]
+/// The table of a line of an archive, read before its row.
+struct LineTable {
+    /// The table of the row.
+    table: Table,
+}
+
+/// Parses the rows of lines of an archive.
+fn parse_rows(lines: &[(usize, &str)]) -> Result<Vec<Record>> {
+    lines
+        .iter()
+        .map(|(i, line)| {
+            let line: Line = serde_json::from_str(line)
+                .map_err(|e| ApiError::BadRequest(format!("Bad Row On Line {i}: {e}")))?;
+            match line.row {
+                Value::Object(row) => Ok(row),
+                _ => Err(ApiError::BadRequest(format!(
+                    "Row On Line {i} Is Not An Object"
+                ))),
+            }
+        })
+        .collect()
+}
+
+/// Makes a JSON array of rows, to bind as a query parameter.
+fn to_array(records: Vec<Record>) -> Value {
+    Value::Array(records.into_iter().map(Value::Object).collect())
+}
+
+/// Reads a column of a row, which is none if it is null or missing.
+fn column<T: DeserializeOwned>(record: &Record, name: &str) -> Result<Option<T>> {
+    Option::<T>::deserialize(record.get(name).unwrap_or(&Value::Null))
+        .map_err(|e| ApiError::BadRequest(format!("Bad Column {name}: {e}")))
+}
+
+/// Replaces a column of a row which is a key of the map by its value, returning whether it
+/// was replaced.
+fn remap<K, V>(record: &mut Record, name: &str, map: &HashMap<K, V>) -> Result<bool>
+where
+    K: DeserializeOwned + Eq + std::hash::Hash,
+    V: Serialize,
+{
+    let Some(value) = column(record, name)?.and_then(|key: K| map.get(&key)) else {
+        return Ok(false);
+    };
+    record.insert(name.to_string(), serde_json::json!(value));
+    Ok(true)
+}
+
+/// Reports a row which does not fit the schema as an invalid archive.
+fn unfit(error: sqlx::Error) -> ApiError {
+    match error {
+        sqlx::Error::Database(e) => ApiError::BadRequest(format!(
+            "Archive Does Not Fit The Database: {}",
+            e.message()
+        )),
+        e => e.into(),
+    }
+}
+
+/// Finds the paths or trips of the archive which already exist.
+async fn find_existing(
+    conn: &mut PgConnection,
+    table: Table,
+    records: &[Record],
+) -> Result<HashSet<Uuid>> {
+    let uuids = records
+        .iter()
+        .filter_map(|record| column(record, "uuid").transpose())
+        .collect::<Result<Vec<Uuid>>>()?;
+    let existing = match table {
+        Table::Paths => {
+            sqlx::query_scalar!("SELECT uuid FROM paths WHERE uuid = ANY($1)", &uuids)
+                .fetch_all(conn)
+                .await?
+        }
+        _ => {
+            sqlx::query_scalar!("SELECT uuid FROM trips WHERE uuid = ANY($1)", &uuids)
+                .fetch_all(conn)
+                .await?
+        }
+    };
+    Ok(existing.into_iter().collect())
+}
+
+/// The state of restoring an archive.
+struct Restore<'a> {
+    /// The transaction the archive is restored in.
+    tx: Transaction<'static, Postgres>,
+    /// The lines of the archive which are not restored yet.
+    archive: BTreeMap<Table, Lines<'a>>,
+    /// How the paths and trips which already exist are restored.
+    mode: ConflictMode,
+    /// The result of the restore so far.
+    report: RestoreReport,
+    /// The trips of the archive whose rows are skipped.
+    skipped_trips: HashSet<Uuid>,
+    /// The IDs the rows of the tables with serial IDs are restored as, keyed by their IDs in
+    /// the archive.
+    ids: HashMap<Table, HashMap<i32, i32>>,
+}
+
+impl<'a> Restore<'a> {
+    /// Restores all the tables.
+    async fn run(&mut self) -> Result<()> {
+        self.check_conflicts().await?;
+        self.merge(Table::Quantities).await?;
+        self.merge(Table::Devices).await?;
+        self.restore_paths().await?;
+        self.restore_trips().await?;
+        self.merge(Table::Sensors).await?;
+        self.merge(Table::Installations).await?;
+        self.merge(Table::Calibrations).await?;
+        self.restore_history().await?;
+        self.restore_data().await?;
+        self.restore_telemetry().await?;
+        self.restore_alerts().await?;
+        self.restore_commands().await?;
+        self.restore_events().await?;
+        self.merge(Table::LorawanDevices).await
+    }
+
+    /// Takes the lines of a table out of the archive.
+    fn take(&mut self, table: Table) -> Lines<'a> {
+        self.archive.remove(&table).unwrap_or_default()
+    }
+
+    /// Counts the rows of a table restored out of the rows given.
+    fn count(&mut self, table: Table, restored: u64, total: usize) {
+        let counts = self.report.tables.entry(table).or_default();
+        counts.restored += restored;
+        counts.skipped += total as u64 - restored;
+    }
+
+    /// Gives the rows new IDs from the sequence of the table, remembering the new IDs.
+    async fn renumber(&mut self, table: Table, records: &mut [Record]) -> Result<()> {
+        let ids = sqlx::query_scalar!(
+            r#"SELECT nextval(pg_get_serial_sequence($1, 'id'))::INT4 AS "id!"
+FROM generate_series(1, $2)"#,
+            table.name(),
+            records.len() as i64
+        )
+        .fetch_all(&mut *self.tx)
+        .await?;
+        let map = self.ids.entry(table).or_default();
+        for (record, id) in records.iter_mut().zip(ids) {
+            let old: i32 = column(record, "id")?
+                .ok_or_else(|| ApiError::BadRequest(format!("Missing ID In {}", table.name())))?;
+            map.insert(old, id);
+            record.insert("id".to_string(), id.into());
+        }
+        Ok(())
+    }
+
+    /// Removes the rows of the trips which are skipped, and moves the rows of the copied trips
+    /// to their copies.
+    fn follow_trips(&mut self, table: Table, records: Vec<Record>) -> Result<Vec<Record>> {
+        let total = records.len();
+        let mut kept = Vec::new();
+        for mut record in records {
+            let trip: Option<Uuid> = column(&record, "trip")?;
+            if trip.is_some_and(|trip| self.skipped_trips.contains(&trip)) {
+                continue;
+            }
+            if let Some(copy) = trip.and_then(|trip| self.report.copied.get(&trip)) {
+                record.insert("trip".to_string(), copy.to_string().into());
+            }
+            kept.push(record);
+        }
+        self.count(table, 0, total - kept.len());
+        Ok(kept)
+    }
+
+    /// Fails in the `fail` mode if any of the paths or trips of the archive already exists.
+    async fn check_conflicts(&mut self) -> Result<()> {
+        if self.mode != ConflictMode::Fail {
+            return Ok(());
+        }
+        let mut existing = Vec::new();
+        for table in [Table::Paths, Table::Trips] {
+            let lines = self.archive.get(&table).map_or(&[][..], Vec::as_slice);
+            for chunk in lines.chunks(CHUNK_SIZE) {
+                let records = parse_rows(chunk)?;
+                let uuids = find_existing(&mut self.tx, table, &records).await?;
+                existing.extend(uuids.iter().map(Uuid::to_string));
+            }
+        }
+        if existing.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::Conflict(format!(
+                "Paths Or Trips Already Exist: {}",
+                existing.join(", ")
+            )))
+        }
+    }
+
+    /// Handles the paths or trips of the archive which already exist by the mode, returning
+    /// the ones which are replaced.
+    async fn resolve(&mut self, table: Table, records: &mut Vec<Record>) -> Result<Vec<Uuid>> {
+        let existing = find_existing(&mut self.tx, table, records).await?;
+        let mut replaced = Vec::new();
+        let mut kept = Vec::new();
+        for mut record in records.drain(..) {
+            let Some(uuid) = column(&record, "uuid")?.filter(|uuid| existing.contains(uuid)) else {
+                kept.push(record);
+                continue;
+            };
+            match self.mode {
+                ConflictMode::Fail | ConflictMode::Replace => {
+                    replaced.push(uuid);
+                    kept.push(record);
+                }
+                ConflictMode::Skip => {
+                    if table == Table::Trips {
+                        self.skipped_trips.insert(uuid);
+                    }
+                    self.count(table, 0, 1);
+                }
+                ConflictMode::Copy => {
+                    let copy = Uuid::new_v4();
+                    self.report.copied.insert(uuid, copy);
+                    record.insert("uuid".to_string(), copy.to_string().into());
+                    kept.push(record);
+                }
+            }
+        }
+        *records = kept;
+        Ok(replaced)
+    }
+
+    /// Restores the rows of a table, keeping the rows which already exist.
+    async fn merge(&mut self, table: Table) -> Result<()> {
+        // The sensors installed before the restore, whose installations in the archive are
+        // skipped so a device never has two sensors of a quantity at once
+        let installed = if table == Table::Installations {
+            sqlx::query_scalar!("SELECT DISTINCT sensor FROM installations")
+                .fetch_all(&mut *self.tx)
+                .await?
+        } else {
+            Vec::new()
+        };
+        for chunk in self.take(table).chunks(CHUNK_SIZE) {
+            let records = parse_rows(chunk)?;
+            let total = records.len();
+            let rows = to_array(records);
+            let query = match table {
+                Table::Quantities => sqlx::query!(
+                    "INSERT INTO quantities
+SELECT * FROM jsonb_populate_recordset(NULL::quantities, $1)
+ON CONFLICT DO NOTHING",
+                    rows
+                ),
+                Table::Devices => sqlx::query!(
+                    "INSERT INTO devices SELECT * FROM jsonb_populate_recordset(NULL::devices, $1)
+ON CONFLICT DO NOTHING",
+                    rows
+                ),
+                Table::Sensors => sqlx::query!(
+                    "INSERT INTO sensors SELECT * FROM jsonb_populate_recordset(NULL::sensors, $1)
+ON CONFLICT DO NOTHING",
+                    rows
+                ),
+                Table::Installations => sqlx::query!(
+                    "INSERT INTO installations
+SELECT * FROM jsonb_populate_recordset(NULL::installations, $1) AS r
+WHERE r.sensor <> ALL($2)
+ON CONFLICT DO NOTHING",
+                    rows,
+                    &installed
+                ),
+                // The calibrations of the sensors which were kept for another serial are skipped
+                Table::Calibrations => sqlx::query!(
+                    "INSERT INTO calibrations
+SELECT * FROM jsonb_populate_recordset(NULL::calibrations, $1) AS r
+WHERE EXISTS (SELECT 1 FROM sensors WHERE sensors.serial = r.sensor)
+ON CONFLICT DO NOTHING",
+                    rows
+                ),
+                Table::LorawanDevices => sqlx::query!(
+                    "INSERT INTO lorawan_devices
+SELECT * FROM jsonb_populate_recordset(NULL::lorawan_devices, $1)
+ON CONFLICT DO NOTHING",
+                    rows
+                ),
+                table => unreachable!("{} is not merged", table.name()),
+            };
+            let restored = query.execute(&mut *self.tx).await.map_err(unfit)?;
+            self.count(table, restored.rows_affected(), total);
+        }
+        Ok(())
+    }
+
+    /// Restores the paths.
+    async fn restore_paths(&mut self) -> Result<()> {
+        for chunk in self.take(Table::Paths).chunks(CHUNK_SIZE) {
+            let mut records = parse_rows(chunk)?;
+            self.resolve(Table::Paths, &mut records).await?;
+            let total = records.len();
+            let restored = sqlx::query!(
+                "INSERT INTO paths SELECT * FROM jsonb_populate_recordset(NULL::paths, $1)
+ON CONFLICT (uuid) DO UPDATE SET name = EXCLUDED.name, path = EXCLUDED.path,
+ corridor = EXCLUDED.corridor, area = EXCLUDED.area, return_home = EXCLUDED.return_home",
+                to_array(records)
+            )
+            .execute(&mut *self.tx)
+            .await
+            .map_err(unfit)?;
+            self.count(Table::Paths, restored.rows_affected(), total);
+        }
+        Ok(())
+    }
+
+    /// Restores the trips, removing the rows of the replaced trips.
+    async fn restore_trips(&mut self) -> Result<()> {
+        let paths = self.copies();
+        for chunk in self.take(Table::Trips).chunks(CHUNK_SIZE) {
+            let mut records = parse_rows(chunk)?;
+            for record in &mut records {
+                remap(record, "path", &paths)?;
+            }
+            let replaced = self.resolve(Table::Trips, &mut records).await?;
+            sqlx::query!("DELETE FROM data WHERE trip = ANY($1)", &replaced)
+                .execute(&mut *self.tx)
+                .await?;
+            sqlx::query!("DELETE FROM history WHERE trip = ANY($1)", &replaced)
+                .execute(&mut *self.tx)
+                .await?;
+            sqlx::query!("DELETE FROM events WHERE trip = ANY($1)", &replaced)
+                .execute(&mut *self.tx)
+                .await?;
+
+            let total = records.len();
+            let restored = sqlx::query!(
+                "INSERT INTO trips SELECT * FROM jsonb_populate_recordset(NULL::trips, $1)
+ON CONFLICT (uuid) DO UPDATE SET time = EXCLUDED.time, path = EXCLUDED.path",
+                to_array(records)
+            )
+            .execute(&mut *self.tx)
+            .await
+            .map_err(unfit)?;
+            self.count(Table::Trips, restored.rows_affected(), total);
+        }
+        Ok(())
+    }
+
+    /// The new UUIDs of the copied paths and trips.
+    fn copies(&self) -> HashMap<Uuid, Uuid> {
+        self.report.copied.iter().map(|(k, v)| (*k, *v)).collect()
+    }
+
+    /// Restores the gps history under new IDs. The fixes of a trip follow their trip, so the
+    /// copy of a trip has its own fixes, and the fixes outside of trips are skipped if the
+    /// device already has a fix at the time.
+    async fn restore_history(&mut self) -> Result<()> {
+        for chunk in self.take(Table::History).chunks(CHUNK_SIZE) {
+            let records = parse_rows(chunk)?;
+            let mut records = self.follow_trips(Table::History, records)?;
+            self.renumber(Table::History, &mut records).await?;
+            let total = records.len();
+            let restored = sqlx::query!(
+                "INSERT INTO history
+SELECT r.* FROM jsonb_populate_recordset(NULL::history, $1) AS r
+WHERE r.trip IS NOT NULL OR NOT EXISTS (
+ SELECT 1 FROM history WHERE history.trip IS NULL AND history.device = r.device
+ AND history.time = r.time
+)",
+                to_array(records)
+            )
+            .execute(&mut *self.tx)
+            .await
+            .map_err(unfit)?;
+            self.count(Table::History, restored.rows_affected(), total);
+        }
+        // The fixes are not referenced by other tables
+        self.ids.remove(&Table::History);
+        Ok(())
+    }
+
+    /// Restores the readings with their measurements and quality control flags.
+    async fn restore_data(&mut self) -> Result<()> {
+        for chunk in self.take(Table::Data).chunks(CHUNK_SIZE) {
+            let records = parse_rows(chunk)?;
+            let mut records = self.follow_trips(Table::Data, records)?;
+            self.renumber(Table::Data, &mut records).await?;
+            let total = records.len();
+            let restored = sqlx::query!(
+                "INSERT INTO data SELECT * FROM jsonb_populate_recordset(NULL::data, $1)",
+                to_array(records)
+            )
+            .execute(&mut *self.tx)
+            .await
+            .map_err(unfit)?;
+            self.count(Table::Data, restored.rows_affected(), total);
+        }
+
+        let ids = self.ids.remove(&Table::Data).unwrap_or_default();
+        for table in [Table::Measurements, Table::QcFlags] {
+            for chunk in self.take(table).chunks(CHUNK_SIZE) {
+                let records = parse_rows(chunk)?;
+                let total = records.len();
+                let mut kept = Vec::new();
+                for mut record in records {
+                    // The rows of the skipped readings are skipped with them
+                    if remap(&mut record, "data", &ids)? {
+                        kept.push(record);
+                    }
+                }
+                let rows = to_array(kept);
+                let query = match table {
+                    Table::Measurements => sqlx::query!(
+                        "INSERT INTO measurements
+SELECT * FROM jsonb_populate_recordset(NULL::measurements, $1)",
+                        rows
+                    ),
+                    _ => sqlx::query!(
+                        "INSERT INTO qc_flags
+SELECT * FROM jsonb_populate_recordset(NULL::qc_flags, $1)",
+                        rows
+                    ),
+                };
+                let restored = query.execute(&mut *self.tx).await.map_err(unfit)?;
+                self.count(table, restored.rows_affected(), total);
+            }
+        }
+        Ok(())
+    }
+
+    /// Restores the telemetry, skipping the reports at the time of an existing report of the
+    /// device.
+    async fn restore_telemetry(&mut self) -> Result<()> {
+        for chunk in self.take(Table::Telemetry).chunks(CHUNK_SIZE) {
+            let records = parse_rows(chunk)?;
+            let total = records.len();
+            let restored = sqlx::query!(
+                "INSERT INTO telemetry (device, time, battery)
+SELECT r.device, r.time, r.battery FROM jsonb_populate_recordset(NULL::telemetry, $1) AS r
+WHERE NOT EXISTS (
+ SELECT 1 FROM telemetry WHERE telemetry.device = r.device AND telemetry.time = r.time
+)",
+                to_array(records)
+            )
+            .execute(&mut *self.tx)
+            .await
+            .map_err(unfit)?;
+            self.count(Table::Telemetry, restored.rows_affected(), total);
+        }
+        Ok(())
+    }
+
+    /// Restores the alert rules, reusing the identical existing rules, and their alerts,
+    /// skipping the alerts raised at the same time as an existing alert of the rule.
+    async fn restore_alerts(&mut self) -> Result<()> {
+        let paths = self.copies();
+        let mut existing = HashMap::new();
+        for chunk in self.take(Table::AlertRules).chunks(CHUNK_SIZE) {
+            let mut records = parse_rows(chunk)?;
+            for record in &mut records {
+                remap(record, "path", &paths)?;
+            }
+            let rows = to_array(records.clone());
+            let identical: HashMap<i32, i32> = sqlx::query!(
+                r#"SELECT r.id AS "archived!", MIN(alert_rules.id) AS "existing!"
+FROM jsonb_populate_recordset(NULL::alert_rules, $1) AS r
+JOIN alert_rules ON alert_rules.name = r.name AND alert_rules.condition = r.condition
+ AND alert_rules.threshold = r.threshold AND alert_rules.webhook = r.webhook
+ AND alert_rules.device IS NOT DISTINCT FROM r.device
+ AND alert_rules.path IS NOT DISTINCT FROM r.path
+GROUP BY r.id"#,
+                rows
+            )
+            .fetch_all(&mut *self.tx)
+            .await
+            .map_err(unfit)?
+            .into_iter()
+            .map(|rule| (rule.archived, rule.existing))
+            .collect();
+            let total = records.len();
+            let mut new = Vec::new();
+            for record in records {
+                if !column(&record, "id")?.is_some_and(|id: i32| identical.contains_key(&id)) {
+                    new.push(record);
+                }
+            }
+            existing.extend(identical);
+            self.renumber(Table::AlertRules, &mut new).await?;
+            let restored = sqlx::query!(
+                "INSERT INTO alert_rules
+SELECT * FROM jsonb_populate_recordset(NULL::alert_rules, $1)",
+                to_array(new)
+            )
+            .execute(&mut *self.tx)
+            .await
+            .map_err(unfit)?;
+            self.count(Table::AlertRules, restored.rows_affected(), total);
+        }
+
+        let mut rules = self.ids.remove(&Table::AlertRules).unwrap_or_default();
+        rules.extend(existing);
+        for chunk in self.take(Table::Alerts).chunks(CHUNK_SIZE) {
+            let records = parse_rows(chunk)?;
+            let total = records.len();
+            let mut kept = Vec::new();
+            for mut record in records {
+                if remap(&mut record, "rule", &rules)? {
+                    kept.push(record);
+                }
+            }
+            let restored = sqlx::query!(
+                "INSERT INTO alerts
+ (rule, device, time, value, resolved_at, delivered_at, attempts, last_error)
+SELECT r.rule, r.device, r.time, r.value, r.resolved_at, r.delivered_at, r.attempts,
+ r.last_error
+FROM jsonb_populate_recordset(NULL::alerts, $1) AS r
+WHERE NOT EXISTS (
+ SELECT 1 FROM alerts WHERE alerts.rule = r.rule AND alerts.device = r.device
+ AND alerts.time = r.time
+)
+ON CONFLICT DO NOTHING",
+                to_array(kept)
+            )
+            .execute(&mut *self.tx)
+            .await
+            .map_err(unfit)?;
+            self.count(Table::Alerts, restored.rows_affected(), total);
+        }
+        Ok(())
+    }
+
+    /// Restores the commands, reusing the commands queued at the same time for the device.
+    async fn restore_commands(&mut self) -> Result<()> {
+        let mut existing = HashMap::new();
+        for chunk in self.take(Table::Commands).chunks(CHUNK_SIZE) {
+            let records = parse_rows(chunk)?;
+            let rows = to_array(records.clone());
+            let queued: HashMap<i32, i32> = sqlx::query!(
+                r#"SELECT r.id AS "archived!", MIN(commands.id) AS "existing!"
+FROM jsonb_populate_recordset(NULL::commands, $1) AS r
+JOIN commands ON commands.device = r.device AND commands.command = r.command
+ AND commands.time = r.time
+GROUP BY r.id"#,
+                rows
+            )
+            .fetch_all(&mut *self.tx)
+            .await
+            .map_err(unfit)?
+            .into_iter()
+            .map(|command| (command.archived, command.existing))
+            .collect();
+            let total = records.len();
+            let mut new = Vec::new();
+            for record in records {
+                if !column(&record, "id")?.is_some_and(|id: i32| queued.contains_key(&id)) {
+                    new.push(record);
+                }
+            }
+            existing.extend(queued);
+            self.renumber(Table::Commands, &mut new).await?;
+            let restored = sqlx::query!(
+                "INSERT INTO commands SELECT * FROM jsonb_populate_recordset(NULL::commands, $1)",
+                to_array(new)
+            )
+            .execute(&mut *self.tx)
+            .await
+            .map_err(unfit)?;
+            self.count(Table::Commands, restored.rows_affected(), total);
+        }
+        self.ids
+            .entry(Table::Commands)
+            .or_default()
+            .extend(existing);
+        Ok(())
+    }
+
+    /// Restores the events of the trips.
+    async fn restore_events(&mut self) -> Result<()> {
+        let commands = self.ids.remove(&Table::Commands).unwrap_or_default();
+        for chunk in self.take(Table::Events).chunks(CHUNK_SIZE) {
+            let records = parse_rows(chunk)?;
+            let mut records = self.follow_trips(Table::Events, records)?;
+            for record in &mut records {
+                if !remap(record, "command", &commands)? {
+                    record.insert("command".to_string(), Value::Null);
+                }
+            }
+            let total = records.len();
+            let restored = sqlx::query!(
+                "INSERT INTO events (kind, device, trip, time, location, deviation, command)
+SELECT kind, device, trip, time, location, deviation, command
+FROM jsonb_populate_recordset(NULL::events, $1)",
+                to_array(records)
+            )
+            .execute(&mut *self.tx)
+            .await
+            .map_err(unfit)?;
+            self.count(Table::Events, restored.rows_affected(), total);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use uuid::Uuid;
+
+    use super::{
+        parse_archive, remap, ConflictMode, Record, Restore, RestoreReport, Table, ARCHIVE_FORMAT,
+        ARCHIVE_VERSION,
+    };
+    use crate::error::ApiError;
+
+    /// Makes an archive with a manifest and a row of each table given.
+    fn archive(manifest: Value, rows: &[Table]) -> String {
+        let mut archive = format!("{manifest}\n");
+        for (i, table) in rows.iter().enumerate() {
+            archive.push_str(&format!("{}\n", json!({"table": table, "row": {"id": i}})));
+        }
+        archive
+    }
+
+    /// Makes the manifest of an archive with the number of rows of tables.
+    fn manifest(tables: &[(Table, usize)]) -> Value {
+        json!({
+            "format": ARCHIVE_FORMAT,
+            "version": ARCHIVE_VERSION,
+            "created_at": "2024-01-01T00:00:00Z",
+            "tables": tables.iter().copied().collect::<BTreeMap<_, _>>(),
+        })
+    }
+
+    /// The message of an archive which is not valid.
+    fn bad_request(archive: &str) -> String {
+        match parse_archive(archive.as_bytes()) {
+            Err(ApiError::BadRequest(message)) => message,
+            Err(e) => panic!("Unexpected Error: {e:?}"),
+            Ok(_) => panic!("Archive Accepted"),
+        }
+    }
+
+    /// Makes a row with columns.
+    fn record(row: Value) -> Record {
+        match row {
+            Value::Object(record) => record,
+            _ => panic!("Row Is Not An Object"),
+        }
+    }
+
+    #[test]
+    fn parse_archive_groups_the_rows_by_table() {
+        let manifest = manifest(&[(Table::Devices, 2), (Table::Trips, 1)]);
+        let archive = archive(manifest, &[Table::Devices, Table::Trips, Table::Devices]);
+        let tables = parse_archive(archive.as_bytes()).unwrap();
+        let lines = |table| tables[&table].iter().map(|(i, _)| *i).collect::<Vec<_>>();
+        assert_eq!(lines(Table::Devices), [2, 4]);
+        assert_eq!(lines(Table::Trips), [3]);
+        assert!(!tables.contains_key(&Table::Paths));
+    }
+
+    #[test]
+    fn parse_archive_checks_the_manifest() {
+        assert_eq!(bad_request(""), "Archive Is Empty");
+        assert!(bad_request("[]\n").starts_with("Bad Manifest"));
+        let mut other = manifest(&[]);
+        other["format"] = json!("other");
+        assert_eq!(
+            bad_request(&archive(other, &[])),
+            format!("Archive Format other Is Not {ARCHIVE_FORMAT}")
+        );
+        let mut old = manifest(&[]);
+        old["version"] = json!(ARCHIVE_VERSION - 1);
+        assert_eq!(
+            bad_request(&archive(old, &[])),
+            format!("Archive Version {} Is Not Supported", ARCHIVE_VERSION - 1)
+        );
+        assert!(
+            bad_request(&format!("{}\n{{\"table\": \"other\"}}", manifest(&[])))
+                .starts_with("Bad Row On Line 2")
+        );
+    }
+
+    #[test]
+    fn parse_archive_rejects_truncated_archives() {
+        let truncated = archive(manifest(&[(Table::Data, 3)]), &[Table::Data, Table::Data]);
+        assert_eq!(
+            bad_request(&truncated),
+            "Archive Has 2 Rows Of data Instead Of 3"
+        );
+        let unlisted = archive(manifest(&[]), &[Table::Events]);
+        assert_eq!(
+            bad_request(&unlisted),
+            "Archive Has 1 Rows Of events Instead Of 0"
+        );
+    }
+
+    #[test]
+    fn remap_replaces_the_mapped_columns() {
+        let map = HashMap::from([(1, 10)]);
+        let mut mapped = record(json!({"rule": 1}));
+        assert!(remap(&mut mapped, "rule", &map).unwrap());
+        assert_eq!(mapped["rule"], json!(10));
+        let mut unmapped = record(json!({"rule": 2}));
+        assert!(!remap(&mut unmapped, "rule", &map).unwrap());
+        assert_eq!(unmapped["rule"], json!(2));
+        let mut null = record(json!({"rule": null}));
+        assert!(!remap(&mut null, "rule", &map).unwrap());
+        assert!(!remap(&mut record(json!({})), "rule", &map).unwrap());
+        assert!(remap(&mut record(json!({"rule": "one"})), "rule", &map).is_err());
+    }
+
+    /// Starts a restore in a mode, with a trip which already exists in its transaction.
+    async fn restore_with_trip(mode: ConflictMode) -> (Restore<'static>, Uuid) {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL Not Set");
+        let pool = PgPool::connect(&url).await.unwrap();
+        let mut restore = Restore {
+            tx: pool.begin().await.unwrap(),
+            archive: BTreeMap::new(),
+            mode,
+            report: RestoreReport::default(),
+            skipped_trips: HashSet::new(),
+            ids: HashMap::new(),
+        };
+        let (path, trip) = (Uuid::new_v4(), Uuid::new_v4());
+        sqlx::query("INSERT INTO paths (uuid, name, path) VALUES ($1, 'backup-test', '{}')")
+            .bind(path)
+            .execute(&mut *restore.tx)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO trips (uuid, time, path) VALUES ($1, CURRENT_TIMESTAMP, $2)")
+            .bind(trip)
+            .bind(path)
+            .execute(&mut *restore.tx)
+            .await
+            .unwrap();
+        (restore, trip)
+    }
+
+    /// The readings of an existing trip and of a new trip, resolving the trips first.
+    async fn follow(restore: &mut Restore<'_>, trip: Uuid) -> (Vec<Record>, Uuid) {
+        let new = Uuid::new_v4();
+        let mut trips = vec![record(json!({"uuid": trip})), record(json!({"uuid": new}))];
+        restore.resolve(Table::Trips, &mut trips).await.unwrap();
+        let data = [trip, new, trip].map(|trip| record(json!({"trip": trip})));
+        let followed = restore.follow_trips(Table::Data, data.into()).unwrap();
+        (followed, new)
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires a database at DATABASE_URL"]
+    async fn rows_of_skipped_trips_are_skipped() {
+        let (mut restore, trip) = restore_with_trip(ConflictMode::Skip).await;
+        let (followed, new) = follow(&mut restore, trip).await;
+        assert_eq!(followed, [record(json!({"trip": new}))]);
+        assert_eq!(restore.report.tables[&Table::Data].skipped, 2);
+        assert_eq!(restore.report.tables[&Table::Trips].skipped, 1);
+        assert!(restore.report.copied.is_empty());
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires a database at DATABASE_URL"]
+    async fn rows_of_copied_trips_follow_their_copy() {
+        let (mut restore, trip) = restore_with_trip(ConflictMode::Copy).await;
+        let (followed, new) = follow(&mut restore, trip).await;
+        let copy = restore.report.copied[&trip];
+        assert_ne!(copy, trip);
+        let trips = [copy, new, copy].map(|trip| record(json!({"trip": trip.to_string()})));
+        assert_eq!(followed, trips);
+        assert_eq!(restore.report.tables[&Table::Data].skipped, 0);
+    }
+}
@@ -0,0 +1,270 @@
+//! Module for Actix services for the queue of commands to the devices.
+use actix_web::{
+    get, post,
+    web::{scope, Data, Json, Query, ServiceConfig},
+    HttpRequest, Responder,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use time::OffsetDateTime;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{error::Result, AppState};
+
+use super::{
+    check_admin,
+    encoding::Decode,
+    signature::{SignatureHeaders, Signed},
+};
+
+/// The command sent to a device to return to its home position.
+pub const RETURN_HOME: &str = "return_home";
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_commands, add_command, poll_commands),
+    components(schemas(Command, CommandInput, PollInput))
+)]
+/// The OpenAPI specification of the commands API resources.
+pub struct CommandsApi;
+
+/// Configuration function for the commands API resources.
+pub fn commands_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/commands")
+            .service(get_commands)
+            .service(add_command)
+            .service(poll_commands),
+    );
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+/// The data format for commands.
+pub struct Command {
+    /// The ID of the command.
+    pub id: i32,
+    /// The device the command is for.
+    pub device: String,
+    #[schema(example = "return_home")]
+    /// The name of the command.
+    pub command: String,
+    /// The arguments of the command.
+    pub arguments: serde_json::Value,
+    #[serde(with = "time::serde::rfc3339")]
+    /// The time the command is queued.
+    pub time: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    /// The time the command is fetched by the device, `null` while pending.
+    pub delivered_at: Option<OffsetDateTime>,
+}
+
+/// Queues a command for a device, returning its ID.
+pub async fn enqueue(
+    pool: &PgPool,
+    device: &str,
+    command: &str,
+    arguments: serde_json::Value,
+) -> Result<i32> {
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO commands (device, command, arguments) VALUES ($1, $2, $3) RETURNING id",
+        device,
+        command,
+        arguments
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+/// Gets the pending commands of a device, oldest first.
+pub async fn pending(pool: &PgPool, device: &str, count: i64) -> Result<Vec<Command>> {
+    Ok(sqlx::query_as!(
+        Command,
+        "SELECT id, device, command, arguments, time, delivered_at FROM commands
+WHERE device = $1 AND delivered_at IS NULL
+ORDER BY id LIMIT $2",
+        device,
+        count
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Marks a command as delivered.
+pub async fn mark_delivered(pool: &PgPool, id: i32) -> Result<()> {
+    sqlx::query!(
+        "UPDATE commands SET delivered_at = CURRENT_TIMESTAMP WHERE id = $1",
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Takes the pending commands of a device, oldest first, marking them as delivered.
+pub async fn take_pending(pool: &PgPool, device: &str, count: i64) -> Result<Vec<Command>> {
+    let mut commands = sqlx::query_as!(
+        Command,
+        "UPDATE commands SET delivered_at = CURRENT_TIMESTAMP
+WHERE id IN (
+  SELECT id FROM commands WHERE device = $1 AND delivered_at IS NULL
+  ORDER BY id LIMIT $2 FOR UPDATE SKIP LOCKED
+)
+RETURNING id, device, command, arguments, time, delivered_at",
+        device,
+        count
+    )
+    .fetch_all(pool)
+    .await?;
+    // The rows returned by an update are not ordered
+    commands.sort_by_key(|command| command.id);
+    Ok(commands)
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+/// The query specification for getting commands.
+struct CommandsQuery {
+    /// The device to get the commands of, all devices if not given.
+    device: Option<String>,
+    #[serde(default)]
+    /// Whether only the commands which are not delivered are returned.
+    pending: bool,
+    #[serde(default = "CommandsQuery::count_default")]
+    /// The amount of commands to get.
+    count: i64,
+}
+
+impl CommandsQuery {
+    /// Defaults count to 100 commands.
+    fn count_default() -> i64 {
+        100
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/commands",
+    tag = "commands",
+    params(CommandsQuery),
+    responses(
+        (status = 200, description = "The latest commands.", body = [Command]),
+        (status = 400, description = "The query is not valid.", body = ErrorBody),
+    )
+)]
+#[get("")]
+/// Gets the commands queued for the devices, latest first.
+async fn get_commands(
+    query: Query<CommandsQuery>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    let commands = sqlx::query_as!(
+        Command,
+        "SELECT id, device, command, arguments, time, delivered_at FROM commands
+WHERE ($1::TEXT IS NULL OR device = $1) AND (NOT $2 OR delivered_at IS NULL)
+ORDER BY id DESC LIMIT $3",
+        query.device,
+        query.pending,
+        query.count
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(commands))
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+/// The information needed to queue a command.
+struct CommandInput {
+    /// The device the command is for.
+    device: String,
+    #[schema(example = "return_home")]
+    /// The name of the command.
+    command: String,
+    #[serde(default = "CommandInput::arguments_default")]
+    /// The arguments of the command.
+    arguments: serde_json::Value,
+}
+
+impl CommandInput {
+    /// Defaults the arguments to an empty object.
+    fn arguments_default() -> serde_json::Value {
+        serde_json::json!({})
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/commands",
+    tag = "commands",
+    request_body = CommandInput,
+    responses(
+        (status = 200, description = "The ID of the queued command.", body = i32),
+        (status = 400, description = "The body is not valid.", body = ErrorBody),
+        (status = 401, description = "The bearer token is not valid.", body = ErrorBody),
+        (status = 422, description = "The device does not exist.", body = ErrorBody),
+    )
+)]
+#[post("")]
+/// Queues a command for a device.
+///
+/// Only the administrators can queue commands, with the `Authorization: Bearer` header.
+async fn add_command(
+    req: HttpRequest,
+    command: Json<CommandInput>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    check_admin(&req)?;
+    let id = enqueue(
+        &state.pool,
+        &command.device,
+        &command.command,
+        command.arguments.clone(),
+    )
+    .await?;
+    tracing::info!(
+        device = command.device,
+        command = command.command,
+        "Command Queued"
+    );
+    state.metrics.inserted("commands", 1);
+    Ok(Json(id))
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+/// The request of a device for its pending commands.
+struct PollInput {
+    #[serde(default = "PollInput::count_default")]
+    /// The maximum number of commands to take.
+    count: i64,
+}
+
+impl Decode for PollInput {}
+
+impl PollInput {
+    /// Defaults count to 10 commands.
+    fn count_default() -> i64 {
+        10
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/commands",
+    tag = "commands",
+    params(SignatureHeaders),
+    request_body = PollInput,
+    responses(
+        (status = 200, description = "The pending commands, oldest first, which are now delivered.", body = [Command]),
+        (status = 400, description = "The body is not valid.", body = ErrorBody),
+        (status = 401, description = "The signature is not valid.", body = ErrorBody),
+    )
+)]
+#[post("/poll")]
+/// Takes the pending commands of the device which signed the request.
+async fn poll_commands(poll: Signed<PollInput>, state: Data<AppState>) -> Result<impl Responder> {
+    let commands = take_pending(&state.pool, &poll.device, poll.count).await?;
+    if !commands.is_empty() {
+        tracing::info!(
+            device = poll.device,
+            count = commands.len(),
+            "Commands Delivered"
+        );
+    }
+    Ok(Json(commands))
+}
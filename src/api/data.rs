@@ -1,26 +1,79 @@
 //! Module for Actix services for collected data.
 
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
 use actix_web::{
-    error::ErrorInternalServerError,
     get, post,
-    web::{scope, Data, Json, Path, Query, ServiceConfig},
-    HttpResponse, Responder, Result,
+    web::{scope, Bytes, Data, Json, Path, PayloadConfig, Query, ServiceConfig},
+    HttpRequest, HttpResponse, Responder,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
-use time::{OffsetDateTime, UtcOffset};
+use sqlx::{
+    postgres::{PgHasArrayType, PgTypeInfo},
+    FromRow, PgConnection,
+};
+use time::OffsetDateTime;
+use utoipa::{IntoParams, OpenApi, ToSchema};
 use uuid::Uuid;
 
-use crate::AppState;
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::{
+    alerts::{self, Observation},
+    check_admin,
+    encoding::{Decode, Reader},
+    export::{self, DepthUnit, TemperatureUnit, TimeFormat, Zone},
+    import::{self, Columns, Format},
+    paths::path_exists,
+    qc::{self, aggregate},
+    quantities::{validate_quantities, TEMPERATURE},
+    sensors::Calibrations,
+    signature::{SignatureHeaders, Signed},
+    trips::trip_exists,
+    Coordinates,
+};
 
-use super::Coordinates;
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_data, post_data, post_batch, import_data),
+    components(schemas(
+        FormatType,
+        ValuesType,
+        QualityType,
+        TemperatureUnit,
+        DepthUnit,
+        TimeFormat,
+        DataValuesOutput,
+        Layer,
+        DataInput,
+        BatchReport,
+        BatchError,
+        ImportReport,
+        ImportError
+    ))
+)]
+/// The OpenAPI specification of the data API resources.
+pub struct DataApi;
 
 /// Configuration function for the data API resources.
 pub fn data_cfg(cfg: &mut ServiceConfig) {
-    cfg.service(scope("/data").service(get_data).service(post_data));
+    cfg.service(
+        scope("/data")
+            .service(get_data)
+            .service(post_data)
+            .service(post_batch)
+            .service(
+                scope("/import")
+                    .app_data(PayloadConfig::new(MAX_IMPORT_SIZE))
+                    .service(import_data),
+            ),
+    );
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, ToSchema, Debug)]
 /// The type of format to respond with.
 enum FormatType {
     #[serde(
@@ -46,30 +99,83 @@ impl Default for FormatType {
     }
 }
 
-time::serde::format_description!(
-    query_offset,
-    UtcOffset,
-    "[offset_hour padding:none]"
-);
+#[derive(Deserialize, ToSchema, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// The type of values to respond with.
+pub(super) enum ValuesType {
+    #[default]
+    /// The values as measured by the sensors.
+    Raw,
+    /// The values corrected with the calibrations of the sensors at the time of measurement.
+    Calibrated,
+}
+
+#[derive(Deserialize, ToSchema, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// The quality of the data to respond with.
+pub(super) enum QualityType {
+    #[default]
+    /// All the data regardless of the quality control flags.
+    All,
+    /// Only the data which passed all the evaluated quality control tests.
+    Good,
+}
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 /// The query specification for getting data.
 struct DataQuery {
     #[serde(default)]
+    #[param(inline)]
     /// The query to specify the format.
     format: FormatType,
-    #[serde(default = "DataQuery::offset_default", with = "query_offset")]
-    /// The query to specify the timezone offset for exported CSV.
-    offset: UtcOffset,
+    #[serde(default)]
+    #[param(value_type = Option<String>, example = "Asia/Kuala_Lumpur")]
+    /// The query to specify the timezone of the exported timestamps, either an offset such as
+    /// `8`, `-03:30` or `%2B05:45`, or an IANA timezone name.
+    offset: Zone,
+    #[serde(default)]
+    #[param(inline)]
+    /// The query to specify whether raw or calibrated values are exported.
+    values: ValuesType,
+    #[serde(default)]
+    #[param(inline)]
+    /// The query to specify whether only the data which passed the quality control is exported.
+    quality: QualityType,
+    #[serde(default)]
+    #[param(inline)]
+    /// The query to specify the unit of the exported temperatures.
+    temperature_unit: TemperatureUnit,
+    #[serde(default)]
+    #[param(inline)]
+    /// The query to specify the unit of the exported depths.
+    depth_unit: DepthUnit,
+    #[serde(default)]
+    #[param(inline)]
+    /// The query to specify the format of the timestamps in exported CSV.
+    time_format: TimeFormat,
+    #[serde(default = "DataQuery::delimiter_default")]
+    #[param(value_type = Option<String>, example = ";")]
+    /// The query to specify the delimiter of the fields in exported CSV, `%09` for TSV.
+    delimiter: char,
+    #[serde(default = "DataQuery::decimal_default")]
+    #[param(value_type = Option<String>, example = ",")]
+    /// The query to specify the decimal separator of the numbers in exported CSV.
+    decimal: char,
 }
 
 impl DataQuery {
-    fn offset_default() -> UtcOffset {
-        UtcOffset::UTC
+    fn delimiter_default() -> char {
+        ','
+    }
+
+    fn decimal_default() -> char {
+        '.'
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
 /// The trip specification for getting and adding data.
 struct DataPath {
     /// The trip UUID the data belongs to.
@@ -90,13 +196,20 @@ struct DataValues {
     #[serde(with = "time::serde::rfc3339")]
     /// The time the data is measured.
     time: OffsetDateTime,
+    /// The other quantities measured, keyed by the name of the quantity.
+    measurements: serde_json::Value,
+    /// The device which measured the data.
+    device: Option<String>,
+    /// The quality control flags of the data, keyed by test.
+    qc: serde_json::Value,
 }
 
-#[derive(Serialize, Debug, FromRow)]
+#[derive(Serialize, Debug, FromRow, ToSchema)]
 /// The data format for data
 struct DataValuesOutput {
     /// The temperature measured.
     temperature: f64,
+    #[schema(value_type = Coordinates)]
     /// The location the data is measured.
     location: serde_json::Value,
     /// The depth the data is measured.
@@ -106,27 +219,39 @@ struct DataValuesOutput {
     #[serde(with = "time::serde::rfc3339")]
     /// The time the data is measured.
     time: OffsetDateTime,
+    /// The other quantities measured, keyed by the name of the quantity.
+    measurements: BTreeMap<String, f64>,
+    /// The worst QARTOD flag of the evaluated quality control tests.
+    qc_flag: i16,
+    /// The QARTOD flags of the quality control tests, keyed by test.
+    qc: BTreeMap<String, i16>,
 }
 
 impl TryFrom<DataValues> for DataValuesOutput {
-    fn try_from(value: DataValues) -> Result<Self, Self::Error> {
+    fn try_from(value: DataValues) -> std::result::Result<Self, Self::Error> {
+        let qc = serde_json::from_value(value.qc)?;
         Ok(DataValuesOutput {
             temperature: value.temperature,
             depth: value.depth,
             layer: value.layer,
             time: value.time,
             location: serde_json::from_value(value.location)?,
+            measurements: serde_json::from_value(value.measurements)?,
+            qc_flag: aggregate(&qc),
+            qc,
         })
     }
 
     type Error = serde_json::Error;
 }
 
-#[derive(Serialize, Deserialize, Debug, sqlx::Type, Clone)]
+#[derive(
+    Serialize, Deserialize, Debug, sqlx::Type, ToSchema, Clone, PartialEq, Eq, PartialOrd, Ord,
+)]
 #[sqlx(type_name = "layer")]
 #[sqlx(rename_all = "lowercase")]
 /// Enumerations for all the water body layers/levels.
-enum Layer {
+pub(super) enum Layer {
     #[serde(rename = "surface")]
     /// The surface of the water body.
     Surface,
@@ -139,11 +264,12 @@ enum Layer {
     SeaBed,
 }
 
-time::serde::format_description!(
-    csv_format,
-    OffsetDateTime,
-    "[day]/[month]/[year] [hour]:[minute]:[second] +[offset_hour]"
-);
+impl PgHasArrayType for Layer {
+    /// The type of the arrays of layers, to bind the layers of many readings at once.
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_layer")
+    }
+}
 
 #[derive(Serialize, Debug, FromRow)]
 /// The data format for data for CSV output
@@ -158,48 +284,80 @@ struct DataRecord {
     name: String,
     /// The layer the data is measured.
     layer: Layer,
-    #[serde(with = "csv_format")]
+    #[serde(with = "time::serde::rfc3339")]
     /// The time the data is measured.
     time: OffsetDateTime,
+    /// The other quantities measured, keyed by the name of the quantity.
+    measurements: serde_json::Value,
+    /// The device which measured the data.
+    device: Option<String>,
+    /// The quality control flags of the data, keyed by test.
+    qc: serde_json::Value,
 }
 
-#[derive(Serialize, Debug, FromRow)]
-/// The data format for data for CSV output
+#[derive(Serialize, Debug)]
+/// The data format for data for CSV output, with the numbers and time formatted.
 struct DataRecordOutput {
     /// The temperature measured.
-    temperature: f64,
+    temperature: String,
     /// The latitude the data is measured.
-    latitude: f64,
+    latitude: String,
     /// The longitude the data is measured.
-    longitude: f64,
+    longitude: String,
     /// The depth the data is measured.
-    depth: f64,
+    depth: String,
     /// The name of the path the data is collected on
     name: String,
     /// The layer the data is measured.
     layer: Layer,
-    #[serde(with = "csv_format")]
     /// The time the data is measured.
-    time: OffsetDateTime,
+    time: String,
+    /// The worst QARTOD flag of the evaluated quality control tests.
+    qc_flag: i16,
+}
+
+impl DataRecordOutput {
+    /// The header of the CSV output, excluding the columns of the other quantities.
+    const HEADER: [&'static str; 8] = [
+        "temperature",
+        "latitude",
+        "longitude",
+        "depth",
+        "name",
+        "layer",
+        "time",
+        "qc_flag",
+    ];
 }
 
-impl TryFrom<DataRecord> for DataRecordOutput {
-    fn try_from(value: DataRecord) -> Result<Self, Self::Error> {
+impl DataRecordOutput {
+    /// Converts and formats a record with the export options of the query.
+    fn new(value: DataRecord, query: &DataQuery, decimal: u8) -> Result<Self> {
         let location: Coordinates = serde_json::from_value(value.location)?;
+        let qc = serde_json::from_value(value.qc)?;
+        let temperature = query.temperature_unit.convert(value.temperature);
         Ok(DataRecordOutput {
-            temperature: value.temperature,
-            depth: value.depth,
+            temperature: export::number(temperature, decimal),
+            depth: export::number(query.depth_unit.convert(value.depth), decimal),
             layer: value.layer,
-            time: value.time,
+            time: query.time_format.format(query.offset.apply(value.time))?,
             name: value.name,
-            latitude: location.latitude,
-            longitude: location.longitude,
+            latitude: export::number(location.latitude, decimal),
+            longitude: export::number(location.longitude, decimal),
+            qc_flag: aggregate(&qc),
         })
     }
-
-    type Error = serde_json::Error;
 }
 
+#[utoipa::path(
+    context_path = "/api/data",
+    tag = "data",
+    params(DataPath, DataQuery),
+    responses(
+        (status = 200, description = "The data collected in the trip, as CSV when `format=csv`.", body = [DataValuesOutput]),
+        (status = 404, description = "The trip does not exist.", body = ErrorBody),
+    )
+)]
 #[get("/{uuid}")]
 /// Gets the data from the database.
 async fn get_data(
@@ -207,79 +365,147 @@ async fn get_data(
     trip: Path<DataPath>,
     state: Data<AppState>,
 ) -> Result<impl Responder> {
+    if !trip_exists(&state.pool, trip.uuid).await? {
+        return Err(ApiError::NotFound(format!("Trip {}", trip.uuid)));
+    }
+    let calibrations = match query.values {
+        ValuesType::Raw => Calibrations::default(),
+        ValuesType::Calibrated => Calibrations::for_trip(&state.pool, trip.uuid).await?,
+    };
     Ok(match query.format {
-        FormatType::CSV => get_csv(trip.uuid, query.offset, state).await?,
-        _ => get_json(trip.uuid, state).await?,
+        FormatType::CSV => get_csv(trip.uuid, &query, &calibrations, state).await?,
+        _ => get_json(trip.uuid, &query, &calibrations, state).await?,
     })
 }
 
-async fn get_json(trip: Uuid, state: Data<AppState>) -> Result<HttpResponse> {
+/// Checks whether a reading is left out of the response by its quality control flag.
+fn is_filtered<E>(quality: QualityType, flag: std::result::Result<i16, &E>) -> bool {
+    quality == QualityType::Good && flag.is_ok_and(|flag| flag != qc::PASS)
+}
+
+async fn get_json(
+    trip: Uuid,
+    query: &DataQuery,
+    calibrations: &Calibrations,
+    state: Data<AppState>,
+) -> Result<HttpResponse> {
     let data = sqlx::query_as!(
         DataValues,
         r#"SELECT data.temperature, data.location, data.depth, data.layer AS "layer: Layer",
- data.time FROM data WHERE data.trip = $1"#,
+ data.time, COALESCE(json_object_agg(measurements.quantity, measurements.value)
+ FILTER (WHERE measurements.quantity IS NOT NULL), '{}') AS "measurements!", data.device,
+ (SELECT COALESCE(json_object_agg(qc_flags.test, qc_flags.flag), '{}') FROM qc_flags
+ WHERE qc_flags.data = data.id) AS "qc!"
+FROM data
+LEFT JOIN measurements ON measurements.data = data.id
+WHERE data.trip = $1
+GROUP BY data.id
+ORDER BY data.time"#,
         trip
     )
     .fetch_all(&state.pool)
-    .await
-    .map_err(|e| actix_web::error::ErrorBadRequest(e.to_string()))?
+    .await?
     .into_iter()
-    .map(|v| DataValuesOutput::try_from(v))
+    .map(|item| {
+        let device = item.device.clone();
+        let mut output = DataValuesOutput::try_from(item)?;
+        calibrations.apply_reading(
+            device.as_deref(),
+            output.time,
+            &mut output.temperature,
+            &mut output.measurements,
+        );
+        output.temperature = query.temperature_unit.convert(output.temperature);
+        output.depth = query.depth_unit.convert(output.depth);
+        output.time = query.offset.apply(output.time);
+        Ok(output)
+    })
+    .filter(|output| !is_filtered(query.quality, output.as_ref().map(|o| o.qc_flag)))
     .collect::<Result<Vec<_>, serde_json::Error>>()?;
     Ok(HttpResponse::Ok().json(data))
 }
 
-async fn get_csv(trip: Uuid, offset: UtcOffset, state: Data<AppState>) -> Result<HttpResponse> {
-    let data = sqlx::query_as!(
+async fn get_csv(
+    trip: Uuid,
+    query: &DataQuery,
+    calibrations: &Calibrations,
+    state: Data<AppState>,
+) -> Result<HttpResponse> {
+    let (delimiter, decimal) = export::separators(query.delimiter, query.decimal)?;
+    let mut data = sqlx::query_as!(
         DataRecord,
         r#"SELECT data.temperature, data.location, data.depth, data.layer AS "layer: Layer",
- data.time, paths.name
+ data.time, paths.name, COALESCE(json_object_agg(measurements.quantity, measurements.value)
+ FILTER (WHERE measurements.quantity IS NOT NULL), '{}') AS "measurements!", data.device,
+ (SELECT COALESCE(json_object_agg(qc_flags.test, qc_flags.flag), '{}') FROM qc_flags
+ WHERE qc_flags.data = data.id) AS "qc!"
 FROM data
 JOIN trips ON trips.uuid = data.trip
 JOIN paths ON trips.path = paths.uuid
-WHERE data.trip = $1"#,
+LEFT JOIN measurements ON measurements.data = data.id
+WHERE data.trip = $1
+GROUP BY data.id, paths.name
+ORDER BY data.time"#,
         trip
     )
     .fetch_all(&state.pool)
-    .await
-    .map_err(|_| ErrorInternalServerError("An Error Occured When Fetching Data."))?;
+    .await?
+    .into_iter()
+    .map(|item| {
+        let qc = serde_json::from_value(item.qc.clone())?;
+        Ok((item, aggregate(&qc)))
+    })
+    .filter(|item| !is_filtered(query.quality, item.as_ref().map(|(_, flag)| *flag)))
+    .map(|item| item.map(|(item, _)| item))
+    .collect::<Result<Vec<_>, serde_json::Error>>()?;
+    let measurements = data
+        .iter_mut()
+        .map(|item| {
+            let mut measurements = serde_json::from_value(item.measurements.take())?;
+            calibrations.apply_reading(
+                item.device.as_deref(),
+                item.time,
+                &mut item.temperature,
+                &mut measurements,
+            );
+            Ok(measurements)
+        })
+        .collect::<Result<Vec<BTreeMap<String, f64>>, serde_json::Error>>()?;
+    // One column for each quantity present in the trip
+    let quantities: BTreeSet<&String> = measurements.iter().flat_map(BTreeMap::keys).collect();
 
-    let mut writer = csv::Writer::from_writer(vec![]);
-    // Adding header if there is no data
-    if data.len() == 0 {
-        writer
-            .write_record(&[
-                "temperature",
-                "latitude",
-                "longitude",
-                "depth",
-                "layer",
-                "trip",
-                "time",
-            ])
-            .unwrap();
-    }
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .from_writer(vec![]);
+    writer.write_record(
+        DataRecordOutput::HEADER
+            .iter()
+            .copied()
+            .chain(quantities.iter().map(|q| q.as_str())),
+    )?;
     // Inserting data
-    for mut item in data {
-        item.time = item.time.to_offset(offset);
-        writer
-            .serialize(DataRecordOutput::try_from(item)?)
-            .map_err(|_| ErrorInternalServerError("An Error Occured When Fetching Data."))?
+    for (item, values) in data.into_iter().zip(&measurements) {
+        let values: Vec<Option<String>> = quantities
+            .iter()
+            .map(|&q| values.get(q).map(|&v| export::number(v, decimal)))
+            .collect();
+        writer.serialize((DataRecordOutput::new(item, query, decimal)?, values))?;
     }
     // Converting to string
     let csv = String::from_utf8(
         writer
             .into_inner()
-            .map_err(|_| ErrorInternalServerError("An Error Occured When Fetching Data."))?,
+            .map_err(|e| ApiError::Internal(e.to_string()))?,
     )
-    .map_err(|_| ErrorInternalServerError("An Error Occured When Fetching Data."))?;
+    .map_err(|e| ApiError::Internal(e.to_string()))?;
     // Sending CSV
     Ok(HttpResponse::Ok().content_type("text/csv").body(csv))
 }
 
-#[derive(Deserialize, FromRow)]
+#[derive(Deserialize, FromRow, ToSchema)]
 /// The input data format for inserting data
-struct DataInput {
+pub(super) struct DataInput {
     /// The temperature measured.
     temperature: f64,
     /// The location the data is measured.
@@ -290,22 +516,623 @@ struct DataInput {
     layer: Layer,
     /// The trip the data is collected in.
     trip: Uuid,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    /// The time the data is measured, the time it is received if not given.
+    pub(super) time: Option<OffsetDateTime>,
+    #[serde(default)]
+    /// The other quantities measured, keyed by the name of the registered quantity.
+    measurements: BTreeMap<String, f64>,
 }
 
+impl Decode for DataInput {
+    /// Decodes the trip, temperature, coordinates, depth, layer, a byte of flags, the time if
+    /// flagged, and the number of measurements followed by their quantities and values.
+    fn decode_binary(reader: &mut Reader) -> Result<Self> {
+        let trip = reader.uuid()?;
+        let temperature = reader.f32()?;
+        let location = reader.coordinates()?;
+        let depth = reader.f32()?;
+        let layer = match reader.u8()? {
+            0 => Layer::Surface,
+            1 => Layer::Middle,
+            2 => Layer::SeaBed,
+            layer => return Err(ApiError::BadRequest(format!("Layer {layer} Is Not Valid"))),
+        };
+        let flags = reader.u8()?;
+        let time = reader.optional(flags & 0x01 != 0, Reader::time)?;
+        let mut measurements = BTreeMap::new();
+        for _ in 0..reader.u8()? {
+            let quantity = reader.string()?.to_string();
+            measurements.insert(quantity, reader.f32()?);
+        }
+        Ok(Self {
+            temperature,
+            location,
+            depth,
+            layer,
+            trip,
+            time,
+            measurements,
+        })
+    }
+}
+
+impl Decode for Vec<DataInput> {
+    /// Decodes the readings one after another until the end of the body.
+    fn decode_binary(reader: &mut Reader) -> Result<Self> {
+        let mut readings = Vec::new();
+        while !reader.is_empty() {
+            readings.push(DataInput::decode_binary(reader)?);
+        }
+        Ok(readings)
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/data",
+    tag = "data",
+    params(SignatureHeaders),
+    request_body(
+        content = DataInput,
+        description = "The reading as JSON, `application/cbor` or `application/vnd.awtc.binary`."
+    ),
+    responses(
+        (status = 200, description = "The data is inserted."),
+        (status = 400, description = "The body is not valid.", body = ErrorBody),
+        (status = 401, description = "The signature is not valid.", body = ErrorBody),
+        (status = 415, description = "The content type is not accepted.", body = ErrorBody),
+        (status = 422, description = "The trip or a quantity does not exist.", body = ErrorBody),
+    )
+)]
 #[post("")]
-/// Insert new data to the database.
-async fn post_data(data: Json<DataInput>, state: Data<AppState>) -> Result<impl Responder> {
-    sqlx::query!(
-        "INSERT INTO data (temperature, location, depth, layer, trip, time)
-VALUES ($1, $2, $3, $4, $5, CURRENT_TIMESTAMP)",
+/// Insert new data signed by a device to the database.
+///
+/// The body can be JSON, CBOR or the binary layout of a reading, by its content type.
+async fn post_data(data: Signed<DataInput>, state: Data<AppState>) -> Result<impl Responder> {
+    insert_data(&state, &data, &data.device).await?;
+    Ok("")
+}
+
+/// The maximum number of readings in a batch.
+const MAX_BATCH: usize = 1000;
+
+#[derive(Serialize, ToSchema, Debug, Default)]
+/// The result of inserting a batch of readings.
+pub(super) struct BatchReport {
+    /// The number of readings inserted.
+    pub(super) inserted: usize,
+    /// The readings which could not be inserted.
+    pub(super) errors: Vec<BatchError>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+/// A reading of a batch which could not be inserted.
+pub(super) struct BatchError {
+    /// The index of the reading in the batch, starting from 0.
+    index: usize,
+    /// The reason the reading could not be inserted.
+    error: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/data",
+    tag = "data",
+    params(SignatureHeaders),
+    request_body(
+        content = [DataInput],
+        description = "The readings as JSON, `application/cbor` or `application/vnd.awtc.binary`."
+    ),
+    responses(
+        (status = 200, description = "The readings are inserted.", body = BatchReport),
+        (status = 400, description = "The body is not valid.", body = ErrorBody),
+        (status = 401, description = "The signature is not valid.", body = ErrorBody),
+        (status = 415, description = "The content type is not accepted.", body = ErrorBody),
+    )
+)]
+#[post("/batch")]
+/// Inserts a batch of readings signed by a device into the database.
+///
+/// Each reading is inserted like the readings of the single reading endpoint, the readings
+/// which could not be inserted are reported back and the rest are still inserted.
+async fn post_batch(data: Signed<Vec<DataInput>>, state: Data<AppState>) -> Result<impl Responder> {
+    if data.len() > MAX_BATCH {
+        return Err(ApiError::BadRequest(format!(
+            "Batch Has More Than {MAX_BATCH} Readings"
+        )));
+    }
+    Ok(Json(insert_batch(&state, &data, &data.device).await))
+}
+
+/// Inserts readings from a device one by one, reporting the readings which could not be
+/// inserted.
+pub(super) async fn insert_batch(
+    state: &AppState,
+    readings: &[DataInput],
+    device: &str,
+) -> BatchReport {
+    let mut inserted = 0;
+    let mut errors = Vec::new();
+    for (index, reading) in readings.iter().enumerate() {
+        match insert_data(state, reading, device).await {
+            Ok(()) => inserted += 1,
+            Err(e) => {
+                tracing::warn!(device, index, "Batch Reading Rejected: {e:?}");
+                errors.push(BatchError {
+                    index,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+    BatchReport { inserted, errors }
+}
+
+/// Validates and inserts a reading from a device into the database.
+///
+/// Only the readings measured in the last minutes are evaluated by the temperature alerts, the
+/// readings logged earlier would raise alerts which are already over.
+pub(super) async fn insert_data(state: &AppState, data: &DataInput, device: &str) -> Result<()> {
+    if !trip_exists(&state.pool, data.trip).await? {
+        return Err(ApiError::Unprocessable(format!(
+            "Trip {} Does Not Exist",
+            data.trip
+        )));
+    }
+    if data.measurements.contains_key(TEMPERATURE) {
+        return Err(ApiError::Unprocessable(format!(
+            "Quantity {TEMPERATURE} Is Reported In The `temperature` Field"
+        )));
+    }
+    let (quantities, values): (Vec<String>, Vec<f64>) = data
+        .measurements
+        .iter()
+        .map(|(quantity, value)| (quantity.clone(), *value))
+        .unzip();
+    validate_quantities(&state.pool, &quantities).await?;
+
+    let mut tx = state.pool.begin().await?;
+    let (id, time) = insert_reading(&mut tx, data, Some(device)).await?;
+    tx.commit().await?;
+
+    tracing::info!(trip = %data.trip, device, "Data Inserted");
+    if let Err(e) = qc::check_reading(state, data.trip, id).await {
+        tracing::error!(trip = %data.trip, device, "Quality Control Failed: {e:?}");
+    }
+    if alerts::is_live(time) {
+        let observation = Observation::Temperature {
+            trip: data.trip,
+            time,
+            value: data.temperature,
+        };
+        alerts::observe(state, device, observation).await;
+    }
+    alerts::seen(state, device).await;
+    state.metrics.inserted("data", 1);
+    state.metrics.inserted("measurements", values.len() as u64);
+    state
+        .metrics
+        .data_reading(&data.trip.to_string(), device, time);
+    Ok(())
+}
+
+/// Inserts a reading and its measurements in a transaction, returning its ID and time.
+async fn insert_reading(
+    conn: &mut PgConnection,
+    data: &DataInput,
+    device: Option<&str>,
+) -> Result<(i32, OffsetDateTime)> {
+    let row = sqlx::query!(
+        "INSERT INTO data (temperature, location, depth, layer, trip, device, time)
+VALUES ($1, $2, $3, $4, $5, $6, COALESCE($7, CURRENT_TIMESTAMP)) RETURNING id, time",
         data.temperature,
         serde_json::json!(data.location),
         data.depth,
         data.layer.clone() as Layer,
-        data.trip
+        data.trip,
+        device,
+        data.time
     )
-    .execute(&state.pool)
-    .await
-    .map_err(|_| actix_web::error::ErrorBadRequest("Invalid Data"))?;
-    Ok("")
+    .fetch_one(&mut *conn)
+    .await?;
+    let (quantities, values): (Vec<&str>, Vec<f64>) = data
+        .measurements
+        .iter()
+        .map(|(quantity, value)| (quantity.as_str(), *value))
+        .unzip();
+    sqlx::query!(
+        "INSERT INTO measurements (data, quantity, value)
+SELECT $1, * FROM UNNEST($2::TEXT[], $3::FLOAT8[])",
+        row.id,
+        &quantities as &[&str],
+        &values
+    )
+    .execute(conn)
+    .await?;
+    Ok((row.id, row.time))
+}
+
+/// The maximum size of an imported CSV in bytes.
+const MAX_IMPORT_SIZE: usize = 32 * 1024 * 1024;
+/// The number of imported readings inserted at a time.
+const IMPORT_CHUNK_SIZE: usize = 1000;
+
+/// Inserts imported readings and their measurements in a transaction.
+async fn insert_rows(
+    conn: &mut PgConnection,
+    rows: &[import::Row],
+    trip: Uuid,
+    device: Option<&str>,
+) -> Result<()> {
+    // The IDs are taken first, so the measurements are inserted with the IDs of their readings
+    let ids = sqlx::query_scalar!(
+        r#"SELECT nextval(pg_get_serial_sequence('data', 'id'))::INT4 AS "id!"
+FROM generate_series(1, $1)"#,
+        rows.len() as i64
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO data (id, temperature, location, depth, layer, time, trip, device)
+SELECT r.id, r.temperature, json_build_object('latitude', r.latitude, 'longitude', r.longitude),
+ r.depth, r.layer, r.time, $8, $9
+FROM UNNEST($1::INT4[], $2::FLOAT8[], $3::FLOAT8[], $4::FLOAT8[], $5::FLOAT8[], $6::layer[],
+ $7::TIMESTAMPTZ[]) AS r(id, temperature, latitude, longitude, depth, layer, time)",
+        &ids,
+        &rows.iter().map(|row| row.temperature).collect::<Vec<_>>(),
+        &rows
+            .iter()
+            .map(|row| row.location.latitude)
+            .collect::<Vec<_>>(),
+        &rows
+            .iter()
+            .map(|row| row.location.longitude)
+            .collect::<Vec<_>>(),
+        &rows.iter().map(|row| row.depth).collect::<Vec<_>>(),
+        &rows.iter().map(|row| row.layer.clone()).collect::<Vec<_>>() as &[Layer],
+        &rows.iter().map(|row| row.time).collect::<Vec<_>>(),
+        trip,
+        device
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let mut data = Vec::new();
+    let mut quantities = Vec::new();
+    let mut values = Vec::new();
+    for (row, id) in rows.iter().zip(ids) {
+        for (quantity, value) in &row.measurements {
+            data.push(id);
+            quantities.push(quantity.as_str());
+            values.push(*value);
+        }
+    }
+    sqlx::query!(
+        "INSERT INTO measurements (data, quantity, value)
+SELECT * FROM UNNEST($1::INT4[], $2::TEXT[], $3::FLOAT8[])",
+        &data,
+        &quantities as &[&str],
+        &values
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+/// The query specification for importing data.
+struct ImportQuery {
+    /// The existing trip the data is attached to.
+    trip: Option<Uuid>,
+    /// The path of a new trip the data is attached to, if no trip is given.
+    path: Option<Uuid>,
+    /// The registered device which logged the data, if any.
+    device: Option<String>,
+    #[serde(default)]
+    /// The query to only validate the CSV and report what would be imported.
+    dry_run: bool,
+    #[serde(default)]
+    #[param(value_type = Option<String>, example = "Asia/Kuala_Lumpur")]
+    /// The query to specify the timezone of the timestamps without an offset, either an offset
+    /// such as `8`, `-03:30` or `%2B05:45`, or an IANA timezone name.
+    offset: Zone,
+    #[serde(default)]
+    #[param(inline)]
+    /// The query to specify the unit of the temperatures.
+    temperature_unit: TemperatureUnit,
+    #[serde(default)]
+    #[param(inline)]
+    /// The query to specify the unit of the depths.
+    depth_unit: DepthUnit,
+    #[serde(default)]
+    #[param(inline)]
+    /// The query to specify the format of the timestamps.
+    time_format: TimeFormat,
+    #[serde(default = "DataQuery::delimiter_default")]
+    #[param(value_type = Option<String>, example = ";")]
+    /// The query to specify the delimiter of the fields, `%09` for TSV.
+    delimiter: char,
+    #[serde(default = "DataQuery::decimal_default")]
+    #[param(value_type = Option<String>, example = ",")]
+    /// The query to specify the decimal separator of the numbers.
+    decimal: char,
+    #[param(example = "Temp (C)")]
+    /// The header of the temperatures, `temperature` if not given.
+    temperature_column: Option<String>,
+    /// The header of the latitudes, `latitude` if not given.
+    latitude_column: Option<String>,
+    /// The header of the longitudes, `longitude` if not given.
+    longitude_column: Option<String>,
+    /// The header of the depths, `depth` if not given.
+    depth_column: Option<String>,
+    /// The header of the layers, `layer` if not given.
+    layer_column: Option<String>,
+    /// The header of the times, `time` if not given.
+    time_column: Option<String>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+/// The result of importing data.
+struct ImportReport {
+    /// The trip the data is attached to, none if a new trip was not needed.
+    trip: Option<Uuid>,
+    /// The number of readings inserted, or which would be inserted in a dry run.
+    inserted: usize,
+    /// The lines of the rows which duplicate an existing reading or an earlier row.
+    duplicates: Vec<u64>,
+    /// The rows which could not be imported.
+    errors: Vec<ImportError>,
+    /// The headers of the columns which are neither a field nor a registered quantity.
+    ignored_columns: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+/// A row of an imported CSV which could not be imported.
+struct ImportError {
+    /// The line the row starts on, starting from 1 for the header.
+    line: u64,
+    /// The reason the row could not be imported.
+    error: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/data/import",
+    tag = "data",
+    params(ImportQuery),
+    request_body(content = String, content_type = "text/csv", description = "The readings in the layout of the exported CSV, or with the columns of the query."),
+    responses(
+        (status = 200, description = "The valid readings are imported.", body = ImportReport),
+        (status = 400, description = "The CSV or the query is not valid.", body = ErrorBody),
+        (status = 401, description = "The admin token is not valid.", body = ErrorBody),
+        (status = 422, description = "The trip, path or device does not exist.", body = ErrorBody),
+    )
+)]
+#[post("")]
+/// Imports the readings of a CSV logged offline or collected in a survey.
+///
+/// The readings are attached to an existing trip, or to a new trip of a path starting at the
+/// earliest reading. A reading is a duplicate if a reading in the database or an earlier row
+/// has the same second, coordinates to the millionth of a degree and depth to the millimetre.
+/// The valid readings which are not duplicates are inserted together, and the quality control
+/// is run on the trip afterwards. The readings do not trigger alerts.
+///
+/// The readings are not signed by a device, so importing requires the admin token.
+async fn import_data(
+    req: HttpRequest,
+    query: Query<ImportQuery>,
+    body: Bytes,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    check_admin(&req)?;
+    let (delimiter, decimal) = export::separators(query.delimiter, query.decimal)?;
+    let format = Format {
+        delimiter,
+        decimal,
+        temperature_unit: query.temperature_unit,
+        depth_unit: query.depth_unit,
+        time_format: query.time_format,
+        zone: query.offset,
+    };
+    let columns = Columns {
+        temperature: query.temperature_column.as_deref().unwrap_or("temperature"),
+        latitude: query.latitude_column.as_deref().unwrap_or("latitude"),
+        longitude: query.longitude_column.as_deref().unwrap_or("longitude"),
+        depth: query.depth_column.as_deref().unwrap_or("depth"),
+        layer: query.layer_column.as_deref().unwrap_or("layer"),
+        time: query.time_column.as_deref().unwrap_or("time"),
+    };
+    match (query.trip, query.path) {
+        (Some(trip), None) => {
+            if !trip_exists(&state.pool, trip).await? {
+                return Err(ApiError::Unprocessable(format!(
+                    "Trip {trip} Does Not Exist"
+                )));
+            }
+        }
+        (None, Some(path)) => {
+            if !path_exists(&state.pool, path).await? {
+                return Err(ApiError::Unprocessable(format!(
+                    "Path {path} Does Not Exist"
+                )));
+            }
+        }
+        _ => {
+            return Err(ApiError::BadRequest(
+                "Either A Trip Or A Path Is Required".to_string(),
+            ))
+        }
+    }
+    if let Some(device) = &query.device {
+        let registered = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM devices WHERE id = $1) AS "exists!""#,
+            device
+        )
+        .fetch_one(&state.pool)
+        .await?;
+        if !registered {
+            return Err(ApiError::Unprocessable(format!(
+                "Device {device} Does Not Exist"
+            )));
+        }
+    }
+
+    let quantities = sqlx::query_scalar!("SELECT name FROM quantities")
+        .fetch_all(&state.pool)
+        .await?;
+    let parsed = import::parse(&body, &columns, &format, &quantities)?;
+    let mut duplicates = Vec::new();
+    let mut keys = HashSet::new();
+    let mut rows = Vec::new();
+    for row in parsed.rows {
+        if keys.insert(row.key()) {
+            rows.push(row);
+        } else {
+            duplicates.push(row.line);
+        }
+    }
+
+    // The readings already in the database, by their index in the rows starting from 1
+    let keys: Vec<_> = rows.iter().map(import::Row::key).collect();
+    let existing: HashSet<i64> = sqlx::query_scalar!(
+        r#"SELECT DISTINCT rows.index AS "index!"
+FROM UNNEST($1::INT8[], $2::INT8[], $3::INT8[], $4::INT8[])
+ WITH ORDINALITY AS rows(second, latitude, longitude, depth, index)
+JOIN data ON FLOOR(EXTRACT(EPOCH FROM data.time))::INT8 = rows.second
+ AND ROUND((data.location->>'latitude')::FLOAT8 * 1e6)::INT8 = rows.latitude
+ AND ROUND((data.location->>'longitude')::FLOAT8 * 1e6)::INT8 = rows.longitude
+ AND ROUND(data.depth * 1e3)::INT8 = rows.depth"#,
+        &keys.iter().map(|k| k.second).collect::<Vec<_>>(),
+        &keys.iter().map(|k| k.latitude).collect::<Vec<_>>(),
+        &keys.iter().map(|k| k.longitude).collect::<Vec<_>>(),
+        &keys.iter().map(|k| k.depth).collect::<Vec<_>>()
+    )
+    .fetch_all(&state.pool)
+    .await?
+    .into_iter()
+    .collect();
+    let mut new = Vec::new();
+    for (index, row) in (1..).zip(rows) {
+        if existing.contains(&index) {
+            duplicates.push(row.line);
+        } else {
+            new.push(row);
+        }
+    }
+    duplicates.sort_unstable();
+
+    let rows = new;
+    let mut report = ImportReport {
+        trip: query.trip,
+        inserted: rows.len(),
+        duplicates,
+        errors: parsed
+            .rejected
+            .into_iter()
+            .map(|e| ImportError {
+                line: e.line,
+                error: e.error,
+            })
+            .collect(),
+        ignored_columns: parsed.ignored,
+    };
+    if query.dry_run {
+        return Ok(Json(report));
+    }
+    let Some(start) = rows.iter().map(|row| row.time).min() else {
+        return Ok(Json(report));
+    };
+
+    let mut tx = state.pool.begin().await?;
+    let trip = match query.trip {
+        Some(trip) => trip,
+        None => {
+            sqlx::query_scalar!(
+                "INSERT INTO trips (uuid, time, path) VALUES ($1, $2, $3) RETURNING uuid",
+                Uuid::new_v4(),
+                start,
+                query.path
+            )
+            .fetch_one(&mut *tx)
+            .await?
+        }
+    };
+    let readings = rows.len();
+    let measurements: usize = rows.iter().map(|row| row.measurements.len()).sum();
+    for chunk in rows.chunks(IMPORT_CHUNK_SIZE) {
+        insert_rows(&mut tx, chunk, trip, query.device.as_deref()).await?;
+    }
+    tx.commit().await?;
+
+    tracing::info!(%trip, readings, "Data Imported");
+    if query.trip.is_none() {
+        state.metrics.inserted("trips", 1);
+    }
+    state.metrics.inserted("data", readings as u64);
+    state.metrics.inserted("measurements", measurements as u64);
+    if let Err(e) = qc::check_trip(&state, trip).await {
+        tracing::error!(%trip, "Quality Control Failed: {e:?}");
+    }
+    report.trip = Some(trip);
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{DataInput, Layer};
+    use crate::api::encoding::decode_binary;
+
+    /// Encodes a reading in the binary layout, without the version.
+    fn reading(layer: u8, time: Option<i64>, measurements: &[(&str, f32)]) -> Vec<u8> {
+        let mut body = Uuid::from_u128(3).as_bytes().to_vec();
+        body.extend(28.5f32.to_be_bytes());
+        body.extend(54_000_000i32.to_be_bytes());
+        body.extend(1_003_000_000i32.to_be_bytes());
+        body.extend(1.5f32.to_be_bytes());
+        body.push(layer);
+        body.push(u8::from(time.is_some()));
+        body.extend(time.into_iter().flat_map(i64::to_be_bytes));
+        body.push(measurements.len() as u8);
+        for (quantity, value) in measurements {
+            body.push(quantity.len() as u8);
+            body.extend(quantity.as_bytes());
+            body.extend(value.to_be_bytes());
+        }
+        body
+    }
+
+    #[test]
+    fn reading_matches_the_layout() {
+        let body = [vec![1], reading(2, None, &[])].concat();
+        assert_eq!(body.len(), 36);
+        let data: DataInput = decode_binary(&body).unwrap();
+        assert_eq!(data.trip, Uuid::from_u128(3));
+        assert_eq!(data.temperature, 28.5);
+        assert_eq!(data.location.latitude, 5.4);
+        assert_eq!(data.location.longitude, 100.3);
+        assert_eq!(data.depth, 1.5);
+        assert_eq!(data.layer, Layer::SeaBed);
+        assert!(data.time.is_none() && data.measurements.is_empty());
+
+        let body = [vec![1], reading(0, Some(1_000), &[("ph", 8.0)])].concat();
+        let data: DataInput = decode_binary(&body).unwrap();
+        assert_eq!(data.time.map(|time| time.unix_timestamp()), Some(1));
+        assert_eq!(data.measurements["ph"], 8.0);
+
+        let body = [vec![1], reading(3, None, &[])].concat();
+        assert!(decode_binary::<DataInput>(&body).is_err());
+    }
+
+    #[test]
+    fn batch_is_the_readings_one_after_another() {
+        let mut body = [vec![1], reading(0, None, &[]), reading(1, None, &[])].concat();
+        let batch: Vec<DataInput> = decode_binary(&body).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[1].layer, Layer::Middle);
+        body.pop();
+        assert!(decode_binary::<Vec<DataInput>>(&body).is_err());
+        assert!(decode_binary::<Vec<DataInput>>(&[1]).unwrap().is_empty());
+    }
 }
@@ -0,0 +1,107 @@
+//! Module for Actix services for all the devices.
+//!
+//! Registering a device requires the token of the administrators, as the shared secret of the
+//! device is the only credential of its signed requests. The secrets are stored as they are
+//! rather than hashed, as the server needs the secret itself to compute the HMAC of each request
+//! it verifies, so the database and its backups must be kept as private as the secrets.
+
+use actix_web::{
+    get, post,
+    web::{scope, Data, Json, ServiceConfig},
+    HttpRequest, Responder,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{error::Result, AppState};
+
+use super::check_admin;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_devices, register_device),
+    components(schemas(DeviceValues, DeviceInput, DeviceResponse))
+)]
+/// The OpenAPI specification of the devices API resources.
+pub struct DevicesApi;
+
+/// Configuration function for the devices API resources.
+pub fn devices_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/devices")
+            .service(get_devices)
+            .service(register_device),
+    );
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+/// The data format for devices data.
+struct DeviceValues {
+    /// The ID of the device.
+    id: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/devices",
+    tag = "devices",
+    responses((status = 200, description = "All the registered devices.", body = [DeviceValues]))
+)]
+#[get("")]
+/// Gets all the registered devices.
+async fn get_devices(state: Data<AppState>) -> Result<impl Responder> {
+    let devices = sqlx::query_as!(DeviceValues, "SELECT id FROM devices")
+        .fetch_all(&state.pool)
+        .await?;
+    Ok(Json(devices))
+}
+
+#[derive(Deserialize, ToSchema)]
+/// The input data format for registering a device.
+struct DeviceInput {
+    /// The ID of the device.
+    id: String,
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+/// The response message for registering a device.
+struct DeviceResponse {
+    /// The ID of the device.
+    id: String,
+    /// The shared secret used to sign the payloads from the device.
+    secret: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/devices",
+    tag = "devices",
+    request_body = DeviceInput,
+    responses(
+        (status = 200, description = "The device is registered.", body = DeviceResponse),
+        (status = 401, description = "The bearer token is not valid.", body = ErrorBody),
+        (status = 409, description = "The device is already registered.", body = ErrorBody),
+    )
+)]
+#[post("")]
+/// Registers a new device with a freshly generated shared secret.
+///
+/// Only the administrators can register devices, with the `Authorization: Bearer` header.
+async fn register_device(
+    req: HttpRequest,
+    device: Json<DeviceInput>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    check_admin(&req)?;
+    let secret = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let device = sqlx::query_as!(
+        DeviceResponse,
+        "INSERT INTO devices (id, secret) VALUES ($1, $2) RETURNING id, secret",
+        device.id,
+        secret
+    )
+    .fetch_one(&state.pool)
+    .await?;
+    state.metrics.inserted("devices", 1);
+    Ok(Json(device))
+}
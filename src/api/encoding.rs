@@ -0,0 +1,362 @@
+//! Decoding of the request bodies of the devices, negotiated by their `Content-Type`.
+//!
+//! The bodies are JSON, which is assumed without a content type. `application/cbor` bodies are
+//! the CBOR encoding of the same structure, with the UUIDs as 16 byte byte strings.
+//! `application/vnd.awtc.binary` bodies are in the fixed layout of the resource, starting with
+//! the version of the layout, which is documented in the README.
+
+use actix_web::{HttpMessage, HttpRequest};
+use serde::de::DeserializeOwned;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::error::{ApiError, Result};
+
+use super::Coordinates;
+
+/// The content type of CBOR bodies.
+pub const CBOR: &str = "application/cbor";
+/// The content type of the fixed layout binary bodies.
+pub const BINARY: &str = "application/vnd.awtc.binary";
+/// The version of the fixed binary layout.
+const BINARY_VERSION: u8 = 1;
+/// The number of units of the coordinates in the binary layout in a degree.
+const COORDINATE_SCALE: f64 = 1e7;
+
+/// A request body which can be decoded from the encodings of the devices.
+pub trait Decode: DeserializeOwned {
+    /// Decodes the body from the fixed binary layout, after its version.
+    fn decode_binary(_reader: &mut Reader) -> Result<Self> {
+        Err(ApiError::UnsupportedMediaType(format!(
+            "{BINARY} Is Not Accepted By This Resource"
+        )))
+    }
+}
+
+/// Decodes a request body by its content type.
+pub fn decode<T: Decode>(req: &HttpRequest, body: &[u8]) -> Result<T> {
+    match req.content_type() {
+        CBOR => ciborium::from_reader(body)
+            .map_err(|e| ApiError::BadRequest(format!("Bad CBOR Data: {e}"))),
+        BINARY => decode_binary(body),
+        "" | "application/json" => {
+            serde_json::from_slice(body).map_err(|e| ApiError::InvalidJson(e.to_string()))
+        }
+        other => Err(ApiError::UnsupportedMediaType(format!(
+            "Content Type {other} Is Not Accepted, Expected application/json, {CBOR} Or {BINARY}"
+        ))),
+    }
+}
+
+/// Decodes a body in the fixed binary layout.
+pub fn decode_binary<T: Decode>(body: &[u8]) -> Result<T> {
+    let mut reader = Reader { body, offset: 0 };
+    let version = reader.u8()?;
+    if version != BINARY_VERSION {
+        return Err(ApiError::BadRequest(format!(
+            "Binary Layout Version {version} Is Not Supported"
+        )));
+    }
+    let value = T::decode_binary(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(ApiError::BadRequest(format!(
+            "Unexpected Data After Byte {}",
+            reader.offset
+        )));
+    }
+    Ok(value)
+}
+
+/// A reader of the big-endian fields of a binary body.
+pub struct Reader<'a> {
+    /// The binary body.
+    body: &'a [u8],
+    /// The offset of the next field.
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Whether the whole body has been read.
+    pub fn is_empty(&self) -> bool {
+        self.offset == self.body.len()
+    }
+
+    /// Reads a slice of bytes.
+    fn slice(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = self.offset + length;
+        let slice = self
+            .body
+            .get(self.offset..end)
+            .ok_or_else(|| ApiError::BadRequest(format!("Binary Data Ends Before Byte {end}")))?;
+        self.offset = end;
+        Ok(slice)
+    }
+
+    /// Reads a fixed number of bytes.
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.slice(N)?.try_into().expect("the slice has N bytes"))
+    }
+
+    /// Reads an unsigned byte.
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    /// Reads a signed 64 bit integer.
+    fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_be_bytes(self.bytes()?))
+    }
+
+    /// Reads a single precision float.
+    pub fn f32(&mut self) -> Result<f64> {
+        Ok(f32::from_be_bytes(self.bytes()?).into())
+    }
+
+    /// Reads a UUID.
+    pub fn uuid(&mut self) -> Result<Uuid> {
+        Ok(Uuid::from_bytes(self.bytes()?))
+    }
+
+    /// Reads a field which is only present if its flag is set.
+    pub fn optional<T>(
+        &mut self,
+        present: bool,
+        read: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<Option<T>> {
+        present.then(|| read(self)).transpose()
+    }
+
+    /// Reads a string prefixed with its length in bytes.
+    pub fn string(&mut self) -> Result<&'a str> {
+        let length = self.u8()?;
+        std::str::from_utf8(self.slice(length.into())?)
+            .map_err(|_| ApiError::BadRequest("Binary String Is Not UTF-8".to_string()))
+    }
+
+    /// Reads a time stored as the milliseconds since the UNIX epoch.
+    pub fn time(&mut self) -> Result<OffsetDateTime> {
+        let milliseconds = self.i64()?;
+        OffsetDateTime::from_unix_timestamp_nanos(i128::from(milliseconds) * 1_000_000)
+            .map_err(|_| ApiError::BadRequest(format!("Time {milliseconds} Is Out Of Range")))
+    }
+
+    /// Reads coordinates stored as the latitude and longitude in ten millionths of a degree.
+    pub fn coordinates(&mut self) -> Result<Coordinates> {
+        let latitude = f64::from(i32::from_be_bytes(self.bytes()?)) / COORDINATE_SCALE;
+        let longitude = f64::from(i32::from_be_bytes(self.bytes()?)) / COORDINATE_SCALE;
+        if latitude.abs() > 90.0 || longitude.abs() > 180.0 {
+            return Err(ApiError::BadRequest(format!(
+                "Coordinates {latitude}, {longitude} Are Out Of Range"
+            )));
+        }
+        Ok(Coordinates {
+            latitude,
+            longitude,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use serde::Deserialize;
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    use super::{decode, decode_binary, Decode, Reader, BINARY, BINARY_VERSION, CBOR};
+    use crate::{
+        api::gps::{FixType, GPSInput},
+        error::{ApiError, Result},
+    };
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    /// A body with a field of each type of the binary layout.
+    struct Fields {
+        /// A UUID.
+        uuid: Uuid,
+        /// A float.
+        value: f64,
+        /// A string.
+        name: String,
+        /// A time, present if flagged.
+        time: Option<OffsetDateTime>,
+        /// Coordinates, as the latitude and longitude.
+        location: (f64, f64),
+    }
+
+    impl Decode for Fields {
+        fn decode_binary(reader: &mut Reader) -> Result<Self> {
+            let uuid = reader.uuid()?;
+            let value = reader.f32()?;
+            let name = reader.string()?.to_string();
+            let flags = reader.u8()?;
+            let time = reader.optional(flags & 0x01 != 0, Reader::time)?;
+            let location = reader.coordinates()?;
+            Ok(Self {
+                uuid,
+                value,
+                name,
+                time,
+                location: (location.latitude, location.longitude),
+            })
+        }
+    }
+
+    /// Encodes coordinates in ten millionths of a degree.
+    fn coordinates(latitude: i32, longitude: i32) -> Vec<u8> {
+        [latitude.to_be_bytes(), longitude.to_be_bytes()].concat()
+    }
+
+    /// Encodes the fields of a body, with a time if given.
+    fn encode(time: Option<i64>) -> Vec<u8> {
+        let mut body = vec![BINARY_VERSION];
+        body.extend(Uuid::from_u128(1).as_bytes());
+        body.extend(12.5f32.to_be_bytes());
+        body.push(4);
+        body.extend("pH\u{b0}".as_bytes());
+        body.extend([u8::from(time.is_some())]);
+        body.extend(time.into_iter().flat_map(i64::to_be_bytes));
+        body.extend(coordinates(54_000_000, -1_234_567_890));
+        body
+    }
+
+    /// The message of a body which is not valid.
+    fn bad_request<T: Decode + std::fmt::Debug>(body: &[u8]) -> String {
+        match decode_binary::<T>(body) {
+            Err(ApiError::BadRequest(message)) => message,
+            result => panic!("Body Not Rejected: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn fields_are_decoded() {
+        let fields: Fields = decode_binary(&encode(Some(1_700_000_000_123))).unwrap();
+        assert_eq!(
+            fields,
+            Fields {
+                uuid: Uuid::from_u128(1),
+                value: 12.5,
+                name: "pH\u{b0}".to_string(),
+                time: Some(
+                    OffsetDateTime::from_unix_timestamp_nanos(1_700_000_000_123_000_000).unwrap()
+                ),
+                location: (5.4, -123.456_789),
+            }
+        );
+        let fields: Fields = decode_binary(&encode(None)).unwrap();
+        assert_eq!(fields.time, None);
+    }
+
+    #[test]
+    fn versions_and_trailing_data_are_rejected() {
+        assert_eq!(bad_request::<Fields>(&[]), "Binary Data Ends Before Byte 1");
+        let mut body = encode(None);
+        body[0] = BINARY_VERSION + 1;
+        assert_eq!(
+            bad_request::<Fields>(&body),
+            format!(
+                "Binary Layout Version {} Is Not Supported",
+                BINARY_VERSION + 1
+            )
+        );
+        let mut body = encode(None);
+        let length = body.len();
+        body.push(0);
+        assert_eq!(
+            bad_request::<Fields>(&body),
+            format!("Unexpected Data After Byte {length}")
+        );
+    }
+
+    #[test]
+    fn truncated_bodies_are_rejected() {
+        let body = encode(Some(0));
+        for length in 1..body.len() {
+            assert!(
+                bad_request::<Fields>(&body[..length]).starts_with("Binary Data Ends Before Byte"),
+                "{length}"
+            );
+        }
+    }
+
+    #[test]
+    fn bad_fields_are_rejected() {
+        let mut body = encode(None);
+        let end = body.len();
+        body.splice(end - 8.., coordinates(900_000_001, 0));
+        assert_eq!(
+            bad_request::<Fields>(&body),
+            "Coordinates 90.0000001, 0 Are Out Of Range"
+        );
+        let mut body = encode(None);
+        body[23] = 0xff;
+        assert_eq!(bad_request::<Fields>(&body), "Binary String Is Not UTF-8");
+    }
+
+    #[test]
+    fn gps_fix_matches_the_layout() {
+        // The coordinates and the flags, without the optional fields
+        let mut body = vec![BINARY_VERSION];
+        body.extend(coordinates(-54_000_000, 1_000_000_000));
+        body.push(0);
+        assert_eq!(body.len(), 10);
+        let fix: GPSInput = decode_binary(&body).unwrap();
+        assert_eq!(fix.location.latitude, -5.4);
+        assert_eq!(fix.location.longitude, 100.0);
+        assert!(fix.trip.is_none() && fix.time.is_none() && fix.course.is_none());
+
+        body[9] = 0xff;
+        body.extend(Uuid::from_u128(2).as_bytes());
+        body.extend(1_000i64.to_be_bytes());
+        for value in [92.5f32, 0.9] {
+            body.extend(value.to_be_bytes());
+        }
+        body.extend([9, 2]);
+        for value in [1.25f32, 94.0] {
+            body.extend(value.to_be_bytes());
+        }
+        let fix: GPSInput = decode_binary(&body).unwrap();
+        assert_eq!(fix.trip, Some(Uuid::from_u128(2)));
+        assert_eq!(
+            fix.time,
+            Some(OffsetDateTime::from_unix_timestamp(1).unwrap())
+        );
+        assert_eq!((fix.heading, fix.hdop), (Some(92.5), Some(0.9f32.into())));
+        assert_eq!((fix.satellites, fix.fix), (Some(9), Some(FixType::Dgps)));
+        assert_eq!((fix.speed, fix.course), (Some(1.25), Some(94.0)));
+
+        // The flagged fields are required
+        assert!(bad_request::<GPSInput>(&body[..body.len() - 1]).starts_with("Binary Data Ends"));
+        let quality = body.len() - 9;
+        body[quality] = 9;
+        assert_eq!(bad_request::<GPSInput>(&body), "Fix Quality 9 Is Not Valid");
+    }
+
+    #[test]
+    fn bodies_are_decoded_by_content_type() {
+        let json = TestRequest::default().to_http_request();
+        let fix: GPSInput = decode(&json, br#"{"lat": 1.5, "lng": 2}"#).unwrap();
+        assert_eq!(fix.location.latitude, 1.5);
+
+        let mut cbor = Vec::new();
+        let value = serde_json::json!({"latitude": 1.5, "longitude": 2.0, "satellites": 7});
+        ciborium::into_writer(&value, &mut cbor).unwrap();
+        let request = TestRequest::default()
+            .insert_header(("Content-Type", CBOR))
+            .to_http_request();
+        let fix: GPSInput = decode(&request, &cbor).unwrap();
+        assert_eq!(fix.satellites, Some(7));
+
+        let request = TestRequest::default()
+            .insert_header(("Content-Type", BINARY))
+            .to_http_request();
+        assert!(decode::<GPSInput>(&request, &cbor).is_err());
+        let request = TestRequest::default()
+            .insert_header(("Content-Type", "text/plain"))
+            .to_http_request();
+        assert!(matches!(
+            decode::<GPSInput>(&request, b"{}"),
+            Err(ApiError::UnsupportedMediaType(_))
+        ));
+    }
+}
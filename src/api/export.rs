@@ -0,0 +1,242 @@
+//! Units and formats of the exported and imported data.
+
+use serde::Deserialize;
+use time::{
+    format_description::{well_known::Iso8601, FormatItem},
+    macros::format_description,
+    OffsetDateTime, PrimitiveDateTime, UtcOffset,
+};
+use time_tz::{timezones, OffsetDateTimeExt, PrimitiveDateTimeExt, Tz};
+use utoipa::ToSchema;
+
+use crate::error::{ApiError, Result};
+
+/// The largest offset from UTC in use, in hours.
+const MAX_OFFSET_HOURS: i8 = 14;
+/// The format of the timestamps originally used in the exported CSV.
+const DEFAULT_TIME_FORMAT: &[FormatItem<'static>] = format_description!(
+    "[day]/[month]/[year] [hour]:[minute]:[second] [offset_hour sign:mandatory]:[offset_minute]"
+);
+/// The format of the exported CSV timestamps without the offset, as in manual spreadsheets.
+const LOCAL_TIME_FORMAT: &[FormatItem<'static>] =
+    format_description!("[day]/[month]/[year] [hour]:[minute]:[second]");
+
+#[derive(Deserialize, ToSchema, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// The unit of the exported temperatures.
+pub enum TemperatureUnit {
+    #[default]
+    #[serde(alias = "c")]
+    /// Degrees Celsius.
+    Celsius,
+    #[serde(alias = "f")]
+    /// Degrees Fahrenheit.
+    Fahrenheit,
+    #[serde(alias = "k")]
+    /// Kelvin.
+    Kelvin,
+}
+
+impl TemperatureUnit {
+    /// Converts a temperature in degrees Celsius to the unit.
+    pub fn convert(self, celsius: f64) -> f64 {
+        match self {
+            Self::Celsius => celsius,
+            Self::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+            Self::Kelvin => celsius + 273.15,
+        }
+    }
+
+    /// Converts a temperature in the unit to degrees Celsius.
+    pub fn to_celsius(self, value: f64) -> f64 {
+        match self {
+            Self::Celsius => value,
+            Self::Fahrenheit => (value - 32.0) * 5.0 / 9.0,
+            Self::Kelvin => value - 273.15,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// The unit of the exported depths.
+pub enum DepthUnit {
+    #[default]
+    #[serde(alias = "m")]
+    /// Metres.
+    Metres,
+    #[serde(alias = "ft")]
+    /// Feet.
+    Feet,
+}
+
+impl DepthUnit {
+    /// Converts a depth in metres to the unit.
+    pub fn convert(self, metres: f64) -> f64 {
+        match self {
+            Self::Metres => metres,
+            Self::Feet => metres / 0.3048,
+        }
+    }
+
+    /// Converts a depth in the unit to metres.
+    pub fn to_metres(self, value: f64) -> f64 {
+        match self {
+            Self::Metres => value,
+            Self::Feet => value * 0.3048,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// The format of the exported timestamps.
+pub enum TimeFormat {
+    #[default]
+    /// `day/month/year hour:minute:second ±HH:MM`.
+    Default,
+    /// ISO 8601 with the offset, e.g. `2023-11-05T14:30:00.000000000+08:00`.
+    Iso8601,
+    /// Seconds since the UNIX epoch.
+    Epoch,
+}
+
+impl TimeFormat {
+    /// Formats a timestamp.
+    pub fn format(self, time: OffsetDateTime) -> Result<String> {
+        match self {
+            Self::Default => time.format(DEFAULT_TIME_FORMAT),
+            Self::Iso8601 => time.format(&Iso8601::DEFAULT),
+            Self::Epoch => return Ok(time.unix_timestamp().to_string()),
+        }
+        .map_err(|e| ApiError::Internal(e.to_string()))
+    }
+
+    /// Parses a timestamp, which is in the timezone if it has no offset.
+    pub fn parse(self, value: &str, zone: Zone) -> Option<OffsetDateTime> {
+        let value = value.trim();
+        match self {
+            Self::Default => OffsetDateTime::parse(value, DEFAULT_TIME_FORMAT)
+                .ok()
+                .or_else(|| zone.assume(PrimitiveDateTime::parse(value, LOCAL_TIME_FORMAT).ok()?)),
+            Self::Iso8601 => OffsetDateTime::parse(value, &Iso8601::DEFAULT)
+                .ok()
+                .or_else(|| zone.assume(PrimitiveDateTime::parse(value, &Iso8601::DEFAULT).ok()?)),
+            Self::Epoch => {
+                let seconds: f64 = value.parse().ok().filter(|s: &f64| s.is_finite())?;
+                OffsetDateTime::from_unix_timestamp_nanos((seconds * 1e9).round() as i128).ok()
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(try_from = "String")]
+/// The timezone of the exported timestamps.
+pub enum Zone {
+    /// A fixed offset from UTC.
+    Offset(UtcOffset),
+    /// An IANA timezone, with the offset in effect at the time of each reading.
+    Named(&'static Tz),
+}
+
+impl Default for Zone {
+    /// Zone defaults to UTC.
+    fn default() -> Self {
+        Zone::Offset(UtcOffset::UTC)
+    }
+}
+
+impl Zone {
+    /// Converts a timestamp to the timezone.
+    pub fn apply(self, time: OffsetDateTime) -> OffsetDateTime {
+        match self {
+            Self::Offset(offset) => time.to_offset(offset),
+            Self::Named(tz) => time.to_timezone(tz),
+        }
+    }
+
+    /// Gives a local time of the timezone its offset, the earlier one if it is ambiguous.
+    pub fn assume(self, time: PrimitiveDateTime) -> Option<OffsetDateTime> {
+        match self {
+            Self::Offset(offset) => Some(time.assume_offset(offset)),
+            Self::Named(tz) => time.assume_timezone(tz).take_first(),
+        }
+    }
+
+    /// Parses an offset of the form `±H`, `±HH` or `±HH:MM`, where the sign is optional.
+    fn parse_offset(value: &str) -> Option<UtcOffset> {
+        let (sign, value) = match value.as_bytes().first()? {
+            b'-' => (-1, &value[1..]),
+            // `+` is decoded to a space in query strings
+            b'+' | b' ' => (1, &value[1..]),
+            _ => (1, value),
+        };
+        let (hours, minutes) = value.split_once(':').unwrap_or((value, "0"));
+        if !(1..=2).contains(&hours.len()) || minutes.len() > 2 {
+            return None;
+        }
+        let hours: i8 = hours.parse().ok().filter(|h| *h <= MAX_OFFSET_HOURS)?;
+        let minutes: i8 = minutes.parse().ok()?;
+        UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+    }
+}
+
+impl TryFrom<String> for Zone {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        Self::parse_offset(&value)
+            .map(Self::Offset)
+            .or_else(|| timezones::get_by_name(value.trim()).map(Self::Named))
+            .ok_or_else(|| format!("{value} Is Not A Valid Offset Or Timezone"))
+    }
+}
+
+/// Checks that a CSV separator is a printable ASCII punctuation character which cannot be part
+/// of a number or quote a field.
+fn separator(name: &str, separator: char) -> Result<u8> {
+    u8::try_from(separator)
+        .ok()
+        .filter(|c| c.is_ascii_punctuation() && !matches!(c, b'"' | b'-' | b'+'))
+        .ok_or_else(|| {
+            ApiError::BadRequest(format!("{name} {separator:?} Is Not A Valid Separator"))
+        })
+}
+
+/// Checks the delimiter of the fields and the decimal separator of the numbers of a CSV, the
+/// fields being delimited by tabs in TSV.
+pub fn separators(delimiter: char, decimal: char) -> Result<(u8, u8)> {
+    let delimiter = match delimiter {
+        '\t' => b'\t',
+        delimiter => separator("Delimiter", delimiter)?,
+    };
+    let decimal = separator("Decimal Separator", decimal)?;
+    if delimiter == decimal {
+        return Err(ApiError::BadRequest(format!(
+            "Delimiter {:?} Is The Same As The Decimal Separator",
+            char::from(delimiter)
+        )));
+    }
+    Ok((delimiter, decimal))
+}
+
+/// Formats a number with a decimal separator.
+pub fn number(value: f64, decimal: u8) -> String {
+    let value = value.to_string();
+    match decimal {
+        b'.' => value,
+        decimal => value.replace('.', &char::from(decimal).to_string()),
+    }
+}
+
+/// Parses a number with a decimal separator.
+pub fn parse_number(value: &str, decimal: u8) -> Option<f64> {
+    let value = value.trim();
+    let value: f64 = match decimal {
+        b'.' => value.parse(),
+        decimal => value.replace(char::from(decimal), ".").parse(),
+    }
+    .ok()?;
+    value.is_finite().then_some(value)
+}
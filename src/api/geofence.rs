@@ -0,0 +1,316 @@
+//! Module for the enforcement of the planned paths and operating areas of the trips.
+//!
+//! Each GPS fix of a trip is compared against the path of the trip: the cross-track error is
+//! the distance to the nearest segment of the path, and the fix is off the path when it is
+//! further than the corridor of the path. A fix outside the operating area of the path breaches
+//! the geofence. An event is created when a device leaves the corridor or the area, not for
+//! every fix outside of them.
+
+use std::f64::consts::PI;
+
+use actix_web::{
+    get,
+    web::{scope, Data, Json, Query, ServiceConfig},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json as SqlJson, FromRow, PgPool};
+use time::OffsetDateTime;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::{
+    commands::{self, RETURN_HOME},
+    Coordinates, EARTH_RADIUS,
+};
+
+/// The number of metres in a degree of latitude.
+pub(super) const METRES_PER_DEGREE: f64 = EARTH_RADIUS * PI / 180.0;
+
+#[derive(OpenApi)]
+#[openapi(paths(get_events), components(schemas(EventKind, Event)))]
+/// The OpenAPI specification of the events API resources.
+pub struct EventsApi;
+
+/// Configuration function for the events API resources.
+pub fn events_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(scope("/events").service(get_events));
+}
+
+/// Projects a point to metres east and north of an origin, accurate over the extent of a
+/// survey.
+pub(super) fn project(origin: &Coordinates, point: &Coordinates) -> (f64, f64) {
+    let scale = origin.latitude.to_radians().cos();
+    (
+        (point.longitude - origin.longitude) * METRES_PER_DEGREE * scale,
+        (point.latitude - origin.latitude) * METRES_PER_DEGREE,
+    )
+}
+
+/// The distance from the origin to a segment between two projected points.
+fn segment_distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = dx * dx + dy * dy;
+    let t = if length > 0.0 {
+        (-(a.0 * dx + a.1 * dy) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (a.0 + t * dx).hypot(a.1 + t * dy)
+}
+
+/// The distance in metres from a point to the segment between two coordinates.
+pub(super) fn distance_to_segment(point: &Coordinates, a: &Coordinates, b: &Coordinates) -> f64 {
+    segment_distance(project(point, a), project(point, b))
+}
+
+/// The distance in metres from a point to the nearest segment of a path, `None` for an empty
+/// path.
+fn cross_track(point: &Coordinates, path: &[Coordinates]) -> Option<f64> {
+    let projected: Vec<_> = path.iter().map(|p| project(point, p)).collect();
+    match projected.as_slice() {
+        [] => None,
+        [only] => Some(only.0.hypot(only.1)),
+        _ => projected
+            .windows(2)
+            .map(|segment| segment_distance(segment[0], segment[1]))
+            .min_by(f64::total_cmp),
+    }
+}
+
+/// Whether a point is inside a polygon, by casting a ray to the east.
+fn inside(point: &Coordinates, area: &[Coordinates]) -> bool {
+    let mut inside = false;
+    for (a, b) in area.iter().zip(area.iter().cycle().skip(1)) {
+        if (a.latitude > point.latitude) != (b.latitude > point.latitude) {
+            let longitude = a.longitude
+                + (point.latitude - a.latitude) / (b.latitude - a.latitude)
+                    * (b.longitude - a.longitude);
+            if point.longitude < longitude {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "event_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+/// The kinds of breaches of a trip.
+pub enum EventKind {
+    /// The device left the corridor around the path.
+    OffPath,
+    /// The device left the operating area.
+    Geofence,
+}
+
+/// The position of a GPS fix relative to the path and operating area of its trip.
+pub struct Position {
+    /// The distance from the path in metres, `None` for a path without points.
+    pub deviation: Option<f64>,
+    /// Whether the fix is outside the operating area.
+    pub outside: bool,
+    /// Whether the fix is further from the path than the corridor.
+    off_path: bool,
+    /// Whether the previous fix of the device in the trip is off the path.
+    was_off_path: bool,
+    /// Whether the previous fix of the device in the trip is outside the operating area.
+    was_outside: bool,
+    /// Whether the device is commanded to return home on a breach.
+    return_home: bool,
+}
+
+impl Position {
+    /// Locates a fix of a device relative to the path of a trip, comparing it with the fix
+    /// before it, which is the latest one unless the fix is sent late.
+    pub async fn locate(
+        pool: &PgPool,
+        trip: Uuid,
+        device: &str,
+        location: &Coordinates,
+        time: Option<OffsetDateTime>,
+    ) -> Result<Self> {
+        let fence = sqlx::query!(
+            r#"SELECT array_to_json(paths.path) AS "path!: SqlJson<Vec<Coordinates>>",
+ array_to_json(paths.area) AS "area: SqlJson<Vec<Coordinates>>", paths.corridor,
+ paths.return_home,
+ COALESCE(previous.deviation > paths.corridor, FALSE) AS "was_off_path!",
+ COALESCE(previous.outside, FALSE) AS "was_outside!"
+FROM trips
+JOIN paths ON paths.uuid = trips.path
+LEFT JOIN LATERAL (
+  SELECT history.deviation, history.outside FROM history
+  WHERE history.trip = trips.uuid AND history.device = $2 AND NOT history.poor
+    AND history.time < COALESCE($3, CURRENT_TIMESTAMP)
+  ORDER BY history.time DESC
+  LIMIT 1
+) AS previous ON TRUE
+WHERE trips.uuid = $1"#,
+            trip,
+            device,
+            time
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ApiError::Unprocessable(format!("Trip {trip} Does Not Exist")))?;
+
+        let deviation = cross_track(location, &fence.path);
+        Ok(Self {
+            deviation,
+            outside: fence.area.is_some_and(|area| !inside(location, &area)),
+            off_path: deviation.is_some_and(|d| d > fence.corridor),
+            was_off_path: fence.was_off_path,
+            was_outside: fence.was_outside,
+            return_home: fence.return_home,
+        })
+    }
+
+    /// Creates the events of the breaches started by the fix.
+    pub async fn report(
+        &self,
+        state: &AppState,
+        trip: Uuid,
+        device: &str,
+        time: OffsetDateTime,
+        location: &Coordinates,
+    ) -> Result<()> {
+        if self.off_path && !self.was_off_path {
+            self.raise(state, EventKind::OffPath, trip, device, time, location)
+                .await?;
+        }
+        if self.outside && !self.was_outside {
+            self.raise(state, EventKind::Geofence, trip, device, time, location)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Creates an event, commanding the device to return home if configured.
+    async fn raise(
+        &self,
+        state: &AppState,
+        kind: EventKind,
+        trip: Uuid,
+        device: &str,
+        time: OffsetDateTime,
+        location: &Coordinates,
+    ) -> Result<()> {
+        let command = if self.return_home {
+            // A device which is already told to return home is not told again
+            let pending = sqlx::query_scalar!(
+                "SELECT id FROM commands WHERE device = $1 AND command = $2
+ AND delivered_at IS NULL
+ORDER BY id LIMIT 1",
+                device,
+                RETURN_HOME
+            )
+            .fetch_optional(&state.pool)
+            .await?;
+            match pending {
+                Some(command) => Some(command),
+                None => {
+                    let arguments = serde_json::json!({ "reason": kind, "trip": trip });
+                    let command =
+                        commands::enqueue(&state.pool, device, RETURN_HOME, arguments).await?;
+                    state.metrics.inserted("commands", 1);
+                    Some(command)
+                }
+            }
+        } else {
+            None
+        };
+        let id = sqlx::query_scalar!(
+            "INSERT INTO events (kind, device, trip, time, location, deviation, command)
+VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+            kind as EventKind,
+            device,
+            trip,
+            time,
+            serde_json::json!(location),
+            self.deviation,
+            command
+        )
+        .fetch_one(&state.pool)
+        .await?;
+        tracing::warn!(event = id, ?kind, %trip, device, deviation = self.deviation, "Trip Breached");
+        state.metrics.inserted("events", 1);
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+/// The query specification for getting events.
+struct EventsQuery {
+    /// The trip to get the events of, all trips if not given.
+    trip: Option<Uuid>,
+    /// The device to get the events of, all devices if not given.
+    device: Option<String>,
+    #[serde(default = "EventsQuery::count_default")]
+    /// The amount of events to get.
+    count: i64,
+}
+
+impl EventsQuery {
+    /// Defaults count to 100 events.
+    fn count_default() -> i64 {
+        100
+    }
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+/// The data format for events.
+struct Event {
+    /// The ID of the event.
+    id: i32,
+    /// The kind of breach.
+    kind: EventKind,
+    /// The device which breached the trip.
+    device: Option<String>,
+    /// The trip which is breached.
+    trip: Uuid,
+    #[serde(with = "time::serde::rfc3339")]
+    /// The time of the fix which breached the trip.
+    time: OffsetDateTime,
+    #[schema(value_type = Coordinates)]
+    /// The location of the fix which breached the trip.
+    location: SqlJson<Coordinates>,
+    /// The distance from the path in metres.
+    deviation: Option<f64>,
+    /// The ID of the return home command queued, if any.
+    command: Option<i32>,
+}
+
+#[utoipa::path(
+    context_path = "/api/events",
+    tag = "events",
+    params(EventsQuery),
+    responses(
+        (status = 200, description = "The latest events.", body = [Event]),
+        (status = 400, description = "The query is not valid.", body = ErrorBody),
+    )
+)]
+#[get("")]
+/// Gets the breaches of the paths and operating areas of the trips, latest first.
+async fn get_events(query: Query<EventsQuery>, state: Data<AppState>) -> Result<impl Responder> {
+    let events = sqlx::query_as!(
+        Event,
+        r#"SELECT id, kind AS "kind: EventKind", device, trip, time,
+ location AS "location: SqlJson<Coordinates>", deviation, command
+FROM events
+WHERE ($1::UUID IS NULL OR trip = $1) AND ($2::TEXT IS NULL OR device = $2)
+ORDER BY time DESC, id DESC LIMIT $3"#,
+        query.trip,
+        query.device,
+        query.count
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(events))
+}
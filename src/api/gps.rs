@@ -1,29 +1,78 @@
 //! Module for Actix services for all the history.
+use std::collections::HashMap;
+
 use actix_web::{
-    error::{ErrorBadRequest, Result},
     get, post,
     web::{scope, Data, Json, Query, ServiceConfig},
-    Responder,
+    HttpResponse, Responder,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use time::OffsetDateTime;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::{
+    alerts,
+    encoding::{Decode, Reader},
+    geofence::Position,
+    nmea,
+    signature::{SignatureHeaders, Signed, SignedText},
+    track,
+    trips::trip_exists,
+    Coordinates, EARTH_RADIUS,
+};
 
-use crate::AppState;
+/// The largest horizontal dilution of precision of a good fix.
+const MAX_HDOP: f64 = 5.0;
+/// The fewest satellites in view for a good fix.
+const MIN_SATELLITES: i32 = 4;
+/// The most fixes on either side averaged when smoothing a track.
+const MAX_SMOOTH: usize = 100;
 
-use super::Coordinates;
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_gps, add_gps, add_nmea, flag_gps),
+    components(schemas(GPSOutput, GPSInput, FixType, NmeaReport, NmeaError, GPSFlag))
+)]
+/// The OpenAPI specification of the gps API resources.
+pub struct GpsApi;
 
 /// Configuration function for the gps API resources.
 pub fn gps_cfg(cfg: &mut ServiceConfig) {
-    cfg.service(scope("/gps").service(get_gps).service(add_gps));
+    cfg.service(
+        scope("/gps")
+            .service(get_gps)
+            .service(add_gps)
+            .service(add_nmea)
+            .service(flag_gps),
+    );
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 /// The query specification for getting gps data.
 struct GPSQuery {
+    /// The trip to get the gps data of, all the gps data if not given.
+    trip: Option<Uuid>,
+    #[serde(default)]
+    /// Whether the fixes flagged as poor are included, they are excluded from the track by
+    /// default.
+    include_poor: bool,
     #[serde(default = "GPSQuery::count_default")]
     /// The amount of data to get.
     count: i64,
+    /// The distance in metres within which the fixes are simplified away, the track is not
+    /// simplified if not given.
+    tolerance: Option<f64>,
+    /// The number of fixes on either side averaged to smooth the locations, at most 100, the
+    /// locations are not smoothed if not given.
+    smooth: Option<usize>,
 }
 
 impl GPSQuery {
@@ -31,6 +80,24 @@ impl GPSQuery {
     fn count_default() -> i64 {
         100
     }
+
+    /// Checks that the tolerance and smoothing are usable.
+    fn validate(&self) -> Result<()> {
+        if self.tolerance.is_some_and(|t| !t.is_finite() || t <= 0.0) {
+            return Err(ApiError::BadRequest(
+                "Tolerance Must Be Positive".to_string(),
+            ));
+        }
+        if self
+            .smooth
+            .is_some_and(|smooth| !(1..=MAX_SMOOTH).contains(&smooth))
+        {
+            return Err(ApiError::BadRequest(format!(
+                "Smoothing Must Be Between 1 And {MAX_SMOOTH} Fixes"
+            )));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Serialize, FromRow)]
@@ -41,9 +108,35 @@ struct GPSValues {
     #[serde(with = "time::serde::rfc3339")]
     /// The when the data is recorded.
     time: OffsetDateTime,
+    /// The device which recorded the data.
+    device: Option<String>,
+    /// The trip the data is recorded in.
+    trip: Option<Uuid>,
+    /// The distance from the path of the trip in metres.
+    deviation: Option<f64>,
+    /// The heading of the device in degrees clockwise from true north.
+    heading: Option<f64>,
+    /// The horizontal dilution of precision of the fix.
+    hdop: Option<f64>,
+    /// The number of satellites used for the fix.
+    satellites: Option<i32>,
+    /// The type of the fix.
+    fix: Option<FixType>,
+    /// Whether the fix is flagged as poor.
+    poor: bool,
+    /// The distance from the previous fix of the device in the trip in metres.
+    distance: Option<f64>,
+    /// The distance travelled by the device in the trip up to the fix in metres.
+    total_distance: Option<f64>,
+    /// The speed over ground in metres per second, as reported by the receiver or since the
+    /// previous fix.
+    speed: Option<f64>,
+    /// The course over ground in degrees clockwise from true north, as reported by the receiver
+    /// or since the previous fix.
+    course: Option<f64>,
 }
 
-#[derive(Serialize, FromRow)]
+#[derive(Serialize, FromRow, ToSchema)]
 /// The data format for gps data.
 struct GPSOutput {
     /// The coordinate of the data.
@@ -51,6 +144,32 @@ struct GPSOutput {
     #[serde(with = "time::serde::rfc3339")]
     /// The when the data is recorded.
     time: OffsetDateTime,
+    /// The device which recorded the data.
+    device: Option<String>,
+    /// The trip the data is recorded in.
+    trip: Option<Uuid>,
+    /// The distance from the path of the trip in metres.
+    deviation: Option<f64>,
+    /// The heading of the device in degrees clockwise from true north.
+    heading: Option<f64>,
+    /// The horizontal dilution of precision of the fix.
+    hdop: Option<f64>,
+    /// The number of satellites used for the fix.
+    satellites: Option<i32>,
+    /// The type of the fix.
+    fix: Option<FixType>,
+    /// Whether the fix is flagged as poor.
+    poor: bool,
+    /// The distance from the previous fix of the device in the trip in metres.
+    distance: Option<f64>,
+    /// The distance travelled by the device in the trip up to the fix in metres.
+    total_distance: Option<f64>,
+    /// The speed over ground in metres per second, as reported by the receiver or since the
+    /// previous fix.
+    speed: Option<f64>,
+    /// The course over ground in degrees clockwise from true north, as reported by the receiver
+    /// or since the previous fix.
+    course: Option<f64>,
 }
 
 impl TryFrom<GPSValues> for GPSOutput {
@@ -60,36 +179,499 @@ impl TryFrom<GPSValues> for GPSOutput {
         Ok(Self {
             location: serde_json::from_value(value.location)?,
             time: value.time,
+            device: value.device,
+            trip: value.trip,
+            deviation: value.deviation,
+            heading: value.heading,
+            hdop: value.hdop,
+            satellites: value.satellites,
+            fix: value.fix,
+            poor: value.poor,
+            distance: value.distance,
+            total_distance: value.total_distance,
+            speed: value.speed,
+            course: value.course,
         })
     }
 }
 
+#[utoipa::path(
+    context_path = "/api/gps",
+    tag = "gps",
+    params(GPSQuery),
+    responses(
+        (
+            status = 200, description = "The latest gps data.", body = [GPSOutput],
+            headers(("X-Dropped-Points" = usize, description = "The number of fixes simplified away."))
+        ),
+        (status = 400, description = "The query is not valid.", body = ErrorBody),
+    )
+)]
 #[get("")]
-/// Gets the gps data from the database.
+/// Gets the gps data from the database, latest first.
+///
+/// The distance is derived from the previous fix of the same device in the same trip, as are the
+/// speed and course when the receiver did not report them, skipping the poor fixes unless they
+/// are included. The track of each device in each trip is smoothed and then simplified
+/// separately, the derived values are of the raw fixes.
 async fn get_gps(query: Query<GPSQuery>, state: Data<AppState>) -> Result<impl Responder> {
-    let locations: Vec<GPSOutput> = sqlx::query_as!(
+    query.validate()?;
+    let mut locations: Vec<GPSOutput> = sqlx::query_as!(
         GPSValues,
-        "SELECT location, time FROM history ORDER BY time DESC LIMIT $1",
+        r#"WITH fixes AS (
+  SELECT time, location, device, trip, deviation, heading, hdop, satellites, fix, poor,
+   speed AS reported_speed, course AS reported_course,
+   radians((location->>'latitude')::FLOAT8) AS lat,
+   radians((location->>'longitude')::FLOAT8) AS lng
+  FROM history
+  WHERE ($1::UUID IS NULL OR trip = $1) AND ($2 OR NOT poor)
+), legs AS (
+  SELECT *, LAG(lat) OVER w AS prev_lat, LAG(lng) OVER w AS prev_lng,
+   EXTRACT(EPOCH FROM time - LAG(time) OVER w)::FLOAT8 AS seconds
+  FROM fixes
+  WINDOW w AS (PARTITION BY device, trip ORDER BY time)
+), steps AS (
+  SELECT *,
+   2 * $3::FLOAT8 * asin(sqrt(sin((lat - prev_lat) / 2) ^ 2
+    + cos(prev_lat) * cos(lat) * sin((lng - prev_lng) / 2) ^ 2)) AS distance,
+   degrees(atan2(sin(lng - prev_lng) * cos(lat),
+    cos(prev_lat) * sin(lat) - sin(prev_lat) * cos(lat) * cos(lng - prev_lng))) AS bearing
+  FROM legs
+)
+SELECT time AS "time!", location AS "location!", device, trip, deviation, heading, hdop, satellites,
+ fix AS "fix: FixType", poor AS "poor!", distance,
+ CASE WHEN trip IS NOT NULL
+  THEN SUM(COALESCE(distance, 0)) OVER (PARTITION BY device, trip ORDER BY time) END
+  AS total_distance,
+ COALESCE(reported_speed, distance / NULLIF(seconds, 0)) AS speed,
+ COALESCE(reported_course,
+  CASE WHEN distance > 0 THEN CASE WHEN bearing < 0 THEN bearing + 360 ELSE bearing END END)
+  AS course
+FROM steps
+ORDER BY time DESC LIMIT $4"#,
+        query.trip,
+        query.include_poor,
+        EARTH_RADIUS,
         query.count
     )
     .fetch_all(&state.pool)
-    .await
-    .map_err(|e| ErrorBadRequest(e.to_string()))?
+    .await?
     .into_iter()
     .map(|v| GPSOutput::try_from(v))
     .collect::<Result<Vec<_>, serde_json::Error>>()?;
-    Ok(Json(locations))
+    let dropped = render(&mut locations, &query);
+    Ok(HttpResponse::Ok()
+        .insert_header(("X-Dropped-Points", dropped.to_string()))
+        .json(locations))
+}
+
+/// Smooths and simplifies the tracks of the fixes, returning the number of fixes dropped.
+fn render(locations: &mut Vec<GPSOutput>, query: &GPSQuery) -> usize {
+    if query.smooth.is_none() && query.tolerance.is_none() {
+        return 0;
+    }
+    let mut tracks: HashMap<(Option<String>, Option<Uuid>), Vec<usize>> = HashMap::new();
+    // The fixes are latest first, the tracks are oldest first
+    for (i, fix) in locations.iter().enumerate().rev() {
+        tracks
+            .entry((fix.device.clone(), fix.trip))
+            .or_default()
+            .push(i);
+    }
+
+    let mut keep = vec![true; locations.len()];
+    for indices in tracks.values() {
+        let mut points: Vec<Coordinates> = indices
+            .iter()
+            .map(|&i| locations[i].location.clone())
+            .collect();
+        if let Some(neighbours) = query.smooth {
+            points = track::smooth(&points, neighbours);
+        }
+        if let Some(tolerance) = query.tolerance {
+            for (&i, kept) in indices.iter().zip(track::simplify(&points, tolerance)) {
+                keep[i] = kept;
+            }
+        }
+        for (&i, point) in indices.iter().zip(points) {
+            locations[i].location = point;
+        }
+    }
+
+    let total = locations.len();
+    let mut keep = keep.into_iter();
+    locations.retain(|_| keep.next().unwrap_or(true));
+    total - locations.len()
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+/// The input data format for gps data.
+pub(super) struct GPSInput {
+    #[serde(flatten)]
+    /// The coordinate of the data.
+    pub(super) location: Coordinates,
+    /// The trip the data is recorded in, checked against the path of the trip.
+    pub(super) trip: Option<Uuid>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    /// The time of the fix, the time it is received if not given.
+    pub(super) time: Option<OffsetDateTime>,
+    #[schema(example = 92.5)]
+    /// The heading of the device in degrees clockwise from true north.
+    pub(super) heading: Option<f64>,
+    #[schema(example = 0.9)]
+    /// The horizontal dilution of precision of the fix.
+    pub(super) hdop: Option<f64>,
+    #[schema(example = 9)]
+    /// The number of satellites used for the fix.
+    pub(super) satellites: Option<i32>,
+    /// The type of the fix.
+    pub(super) fix: Option<FixType>,
+    #[schema(example = 1.2)]
+    /// The speed over ground reported by the receiver in metres per second.
+    pub(super) speed: Option<f64>,
+    #[schema(example = 94.0)]
+    /// The course over ground reported by the receiver in degrees clockwise from true north.
+    pub(super) course: Option<f64>,
+}
+
+impl Decode for GPSInput {
+    /// Decodes the coordinates, a byte of flags and the fields flagged as present, in order.
+    fn decode_binary(reader: &mut Reader) -> Result<Self> {
+        let location = reader.coordinates()?;
+        let flags = reader.u8()?;
+        let trip = reader.optional(flags & 0x01 != 0, Reader::uuid)?;
+        let time = reader.optional(flags & 0x02 != 0, Reader::time)?;
+        let heading = reader.optional(flags & 0x04 != 0, Reader::f32)?;
+        let hdop = reader.optional(flags & 0x08 != 0, Reader::f32)?;
+        let satellites = reader.optional(flags & 0x10 != 0, |r| r.u8().map(i32::from))?;
+        let fix = reader.optional(flags & 0x20 != 0, |r| {
+            let quality = r.u8()?;
+            FixType::from_quality(quality)
+                .ok_or_else(|| ApiError::BadRequest(format!("Fix Quality {quality} Is Not Valid")))
+        })?;
+        Ok(Self {
+            location,
+            trip,
+            time,
+            heading,
+            hdop,
+            satellites,
+            fix,
+            speed: reader.optional(flags & 0x40 != 0, Reader::f32)?,
+            course: reader.optional(flags & 0x80 != 0, Reader::f32)?,
+        })
+    }
+}
+
+impl GPSInput {
+    /// Checks that the reported heading and quality of the fix are possible.
+    fn validate(&self) -> Result<()> {
+        if self
+            .heading
+            .is_some_and(|heading| !(0.0..=360.0).contains(&heading))
+        {
+            return Err(ApiError::BadRequest(
+                "Heading Must Be Between 0 And 360 Degrees".to_string(),
+            ));
+        }
+        if self
+            .hdop
+            .is_some_and(|hdop| !hdop.is_finite() || hdop < 0.0)
+        {
+            return Err(ApiError::BadRequest(
+                "HDOP Must Not Be Negative".to_string(),
+            ));
+        }
+        if self.satellites.is_some_and(|satellites| satellites < 0) {
+            return Err(ApiError::BadRequest(
+                "Satellites Must Not Be Negative".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether the reported quality of the fix is too poor to track the device.
+    fn is_poor(&self) -> bool {
+        self.hdop.is_some_and(|hdop| hdop > MAX_HDOP)
+            || self.satellites.is_some_and(|s| s < MIN_SATELLITES)
+            || self.fix.is_some_and(|fix| !fix.is_measured())
+    }
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "fix_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+/// The types of gps fixes, following the fix quality of NMEA GGA sentences.
+pub(super) enum FixType {
+    /// No fix.
+    None,
+    /// A standalone GPS fix.
+    Gps,
+    /// A differential GPS fix.
+    Dgps,
+    /// A fix in precise positioning service mode.
+    Pps,
+    /// A real time kinematic fix with fixed integers.
+    Rtk,
+    /// A real time kinematic fix with float integers.
+    FloatRtk,
+    /// A position estimated by dead reckoning.
+    Estimated,
+    /// A position entered manually.
+    Manual,
+    /// A simulated position.
+    Simulation,
+}
+
+impl FixType {
+    /// The type of a fix from its NMEA GGA fix quality.
+    pub(super) fn from_quality(quality: u8) -> Option<Self> {
+        Some(match quality {
+            0 => Self::None,
+            1 => Self::Gps,
+            2 => Self::Dgps,
+            3 => Self::Pps,
+            4 => Self::Rtk,
+            5 => Self::FloatRtk,
+            6 => Self::Estimated,
+            7 => Self::Manual,
+            8 => Self::Simulation,
+            _ => return None,
+        })
+    }
+
+    /// Whether the position is measured from the satellites.
+    fn is_measured(self) -> bool {
+        matches!(
+            self,
+            Self::Gps | Self::Dgps | Self::Pps | Self::Rtk | Self::FloatRtk
+        )
+    }
 }
 
+#[utoipa::path(
+    context_path = "/api/gps",
+    tag = "gps",
+    params(SignatureHeaders),
+    request_body(
+        content = GPSInput,
+        description = "The fix as JSON, `application/cbor` or `application/vnd.awtc.binary`."
+    ),
+    responses(
+        (status = 200, description = "The gps data is inserted."),
+        (status = 400, description = "The body is not valid.", body = ErrorBody),
+        (status = 401, description = "The signature is not valid.", body = ErrorBody),
+        (status = 415, description = "The content type is not accepted.", body = ErrorBody),
+        (status = 422, description = "The trip does not exist.", body = ErrorBody),
+    )
+)]
 #[post("")]
-/// Create the gps data to the database.
-async fn add_gps(data: Json<Coordinates>, state: Data<AppState>) -> Result<impl Responder> {
-    sqlx::query!(
-        "INSERT INTO history (location, time) VALUES ($1, CURRENT_TIMESTAMP)",
-        serde_json::json!(data.0)
+/// Create the gps data signed by a device to the database.
+///
+/// The body can be JSON, CBOR or the binary layout of a fix, by its content type.
+async fn add_gps(data: Signed<GPSInput>, state: Data<AppState>) -> Result<impl Responder> {
+    insert_fix(&state, &data, &data.device).await?;
+    Ok("")
+}
+
+/// Inserts a gps fix from a device into the database, checking it against its trip.
+///
+/// Poor fixes are flagged and do not breach the trip, as they are likely GPS jumps.
+pub(super) async fn insert_fix(state: &AppState, fix: &GPSInput, device: &str) -> Result<()> {
+    fix.validate()?;
+    let position = match fix.trip {
+        Some(trip) => {
+            Some(Position::locate(&state.pool, trip, device, &fix.location, fix.time).await?)
+        }
+        None => None,
+    };
+    let poor = fix.is_poor();
+    let time = sqlx::query_scalar!(
+        "INSERT INTO history (location, device, time, trip, deviation, outside, heading, hdop,
+ satellites, fix, poor, speed, course)
+VALUES ($1, $2, COALESCE($3, CURRENT_TIMESTAMP), $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+RETURNING time",
+        serde_json::json!(fix.location),
+        device,
+        fix.time,
+        fix.trip,
+        position.as_ref().and_then(|p| p.deviation),
+        position.as_ref().is_some_and(|p| p.outside),
+        fix.heading,
+        fix.hdop,
+        fix.satellites,
+        fix.fix as Option<FixType>,
+        poor,
+        fix.speed,
+        fix.course
     )
-    .fetch_all(&state.pool)
-    .await
-    .map_err(|e| ErrorBadRequest(e.to_string()))?;
+    .fetch_one(&state.pool)
+    .await?;
+    tracing::info!(device, poor, "GPS Data Inserted");
+    if let (Some(trip), Some(position), false) = (fix.trip, &position, poor) {
+        if let Err(e) = position
+            .report(state, trip, device, time, &fix.location)
+            .await
+        {
+            tracing::error!(%trip, device, "Geofence Check Failed: {e:?}");
+        }
+    }
+    state.metrics.inserted("history", 1);
+    let trip = fix.trip.map(|trip| trip.to_string()).unwrap_or_default();
+    state.metrics.gps_fix(&trip, device, time);
+    alerts::seen(state, device).await;
+    Ok(())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+/// The query specification for uploading NMEA sentences.
+struct NmeaQuery {
+    /// The trip the fixes are recorded in, checked against the path of the trip.
+    trip: Option<Uuid>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+/// The result of uploading NMEA sentences.
+struct NmeaReport {
+    /// The number of fixes inserted.
+    fixes: usize,
+    /// The number of sentences of types which are not used.
+    ignored: usize,
+    /// The sentences which could not be used, and the fixes which could not be inserted.
+    errors: Vec<NmeaError>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+/// A sentence of an upload which could not be used.
+struct NmeaError {
+    /// The line of the sentence, starting from 1.
+    line: usize,
+    #[schema(example = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*46")]
+    /// The sentence.
+    sentence: String,
+    /// The reason the sentence could not be used.
+    error: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/gps",
+    tag = "gps",
+    params(NmeaQuery, SignatureHeaders),
+    request_body(content = String, content_type = "text/plain", description = "NMEA 0183 sentences, one per line."),
+    responses(
+        (status = 200, description = "The fixes are inserted.", body = NmeaReport),
+        (status = 400, description = "The body is not valid.", body = ErrorBody),
+        (status = 401, description = "The signature is not valid.", body = ErrorBody),
+        (status = 422, description = "The trip does not exist.", body = ErrorBody),
+    )
+)]
+#[post("/nmea")]
+/// Inserts the fixes of raw NMEA sentences signed by a device into the database.
+///
+/// The GGA, RMC and VTG sentences with the same time are combined into a fix and inserted like
+/// the fixes of the JSON endpoint. The sentences which are malformed or fail their checksum are
+/// reported back with the fixes which could not be inserted, the rest are still inserted.
+async fn add_nmea(
+    query: Query<NmeaQuery>,
+    data: SignedText,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    if let Some(trip) = query.trip {
+        if !trip_exists(&state.pool, trip).await? {
+            return Err(ApiError::Unprocessable(format!(
+                "Trip {trip} Does Not Exist"
+            )));
+        }
+    }
+    let lines: Vec<&str> = data.body.lines().collect();
+    let error = |line: usize, error: String| NmeaError {
+        line,
+        sentence: lines[line - 1].trim().to_string(),
+        error,
+    };
+
+    let parsed = nmea::parse(&data.body);
+    let mut errors: Vec<NmeaError> = parsed
+        .errors
+        .into_iter()
+        .map(|e| error(e.line, e.error))
+        .collect();
+    let mut fixes = 0;
+    for fix in parsed.fixes {
+        let input = GPSInput {
+            location: fix.location,
+            trip: query.trip,
+            time: Some(fix.time),
+            heading: None,
+            hdop: fix.hdop,
+            satellites: fix.satellites,
+            fix: fix.fix,
+            speed: fix.speed,
+            course: fix.course,
+        };
+        match insert_fix(&state, &input, &data.device).await {
+            Ok(()) => fixes += 1,
+            Err(e) => {
+                tracing::warn!(
+                    device = data.device,
+                    line = fix.line,
+                    "NMEA Fix Rejected: {e:?}"
+                );
+                errors.push(error(fix.line, e.to_string()));
+            }
+        }
+    }
+    errors.sort_by_key(|e| e.line);
+    Ok(Json(NmeaReport {
+        fixes,
+        ignored: parsed.ignored,
+        errors,
+    }))
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+/// The flag of a gps fix.
+struct GPSFlag {
+    /// The device which recorded the fix.
+    device: String,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    /// The time of the fix.
+    time: OffsetDateTime,
+    /// The trip of the fix, all the fixes of the device at the time if not given.
+    trip: Option<Uuid>,
+    /// Whether the fix is poor and excluded from the track.
+    poor: bool,
+}
+
+#[utoipa::path(
+    context_path = "/api/gps",
+    tag = "gps",
+    request_body = GPSFlag,
+    responses(
+        (status = 200, description = "The fix is flagged."),
+        (status = 400, description = "The body is not valid.", body = ErrorBody),
+        (status = 404, description = "The fix does not exist.", body = ErrorBody),
+    )
+)]
+#[post("/flag")]
+/// Flags a gps fix as poor or good, overriding the flag from its reported quality.
+async fn flag_gps(flag: Json<GPSFlag>, state: Data<AppState>) -> Result<impl Responder> {
+    let updated = sqlx::query!(
+        "UPDATE history SET poor = $3 WHERE device = $1 AND time = $2
+ AND ($4::UUID IS NULL OR trip = $4)",
+        flag.device,
+        flag.time,
+        flag.poor,
+        flag.trip
+    )
+    .execute(&state.pool)
+    .await?
+    .rows_affected();
+    if updated == 0 {
+        return Err(ApiError::NotFound("GPS Fix".to_string()));
+    }
     Ok("")
 }
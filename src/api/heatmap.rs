@@ -0,0 +1,234 @@
+//! Module for Actix services for the interpolated temperature maps.
+
+use std::fmt::Write;
+
+use actix_web::{
+    get,
+    web::{self, scope, Data, Query, ServiceConfig},
+    HttpResponse, Responder,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::{
+    data::Layer,
+    geofence::{project, METRES_PER_DEGREE},
+    selection::{Sample, Selection},
+    Coordinates,
+};
+
+/// The maximum number of cells in a grid.
+const MAX_CELLS: usize = 100_000;
+/// The value of the cells without data in ASCII grids.
+const NODATA: f64 = -9999.0;
+
+#[derive(OpenApi)]
+#[openapi(paths(get_heatmap), components(schemas(GridFormat, Grid)))]
+/// The OpenAPI specification of the heatmap API resources.
+pub struct HeatmapApi;
+
+/// Configuration function for the heatmap API resources.
+pub fn heatmap_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(scope("/heatmap").service(get_heatmap));
+}
+
+#[derive(Deserialize, ToSchema, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+/// The format of the grid to respond with.
+enum GridFormat {
+    #[default]
+    /// The grid as JSON.
+    Json,
+    /// The grid as an ESRI ASCII grid, which can be loaded by GIS software.
+    Ascii,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+/// The query specification for the interpolation.
+struct HeatmapQuery {
+    /// The layer of the readings to interpolate.
+    layer: Layer,
+    #[serde(default = "HeatmapQuery::resolution_default")]
+    /// The size of the cells of the grid in metres of latitude.
+    resolution: f64,
+    #[serde(default = "HeatmapQuery::power_default")]
+    /// The power of the inverse distance weighting.
+    power: f64,
+    /// The distance in metres beyond which readings are ignored, cells without any readings
+    /// within the distance have no value.
+    radius: Option<f64>,
+    #[serde(default)]
+    #[param(inline)]
+    /// The format of the grid.
+    format: GridFormat,
+}
+
+impl HeatmapQuery {
+    fn resolution_default() -> f64 {
+        10.0
+    }
+
+    fn power_default() -> f64 {
+        2.0
+    }
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+/// A grid of interpolated temperatures.
+struct Grid {
+    /// The longitude of the western edge of the grid.
+    west: f64,
+    /// The latitude of the southern edge of the grid.
+    south: f64,
+    /// The size of the cells in degrees.
+    cell_size: f64,
+    /// The number of columns of the grid.
+    columns: usize,
+    /// The number of rows of the grid.
+    rows: usize,
+    /// The temperatures of the cells, row by row from the north, `null` for cells without data.
+    values: Vec<Vec<Option<f64>>>,
+}
+
+impl Grid {
+    /// Interpolates the temperatures of the readings with inverse distance weighting.
+    fn interpolate(samples: &[Sample], query: &HeatmapQuery) -> Result<Self> {
+        let (mut south, mut north) = (f64::INFINITY, f64::NEG_INFINITY);
+        let (mut west, mut east) = (f64::INFINITY, f64::NEG_INFINITY);
+        for sample in samples {
+            south = south.min(sample.location.latitude);
+            north = north.max(sample.location.latitude);
+            west = west.min(sample.location.longitude);
+            east = east.max(sample.location.longitude);
+        }
+        let cell_size = query.resolution / METRES_PER_DEGREE;
+        // Counted as floats, as the cells of a tiny resolution overflow an integer
+        let columns = ((east - west) / cell_size).ceil() + 1.0;
+        let rows = ((north - south) / cell_size).ceil() + 1.0;
+        let limit = MAX_CELLS as f64;
+        if columns > limit || rows > limit || columns.is_nan() || rows.is_nan() {
+            return Err(ApiError::BadRequest(format!(
+                "Resolution Exceeds The Limit Of {MAX_CELLS} Cells"
+            )));
+        }
+        if columns * rows > limit {
+            return Err(ApiError::BadRequest(format!(
+                "{columns}x{rows} Cells Exceed The Limit Of {MAX_CELLS} Cells"
+            )));
+        }
+        let (columns, rows) = (columns as usize, rows as usize);
+        // Centring the readings at the extremes in the edge cells
+        let (west, south) = (west - cell_size / 2.0, south - cell_size / 2.0);
+
+        let values = (0..rows)
+            .map(|row| {
+                let latitude = south + ((rows - row - 1) as f64 + 0.5) * cell_size;
+                (0..columns)
+                    .map(|column| {
+                        let longitude = west + (column as f64 + 0.5) * cell_size;
+                        Self::weigh(samples, latitude, longitude, query)
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(Self {
+            west,
+            south,
+            cell_size,
+            columns,
+            rows,
+            values,
+        })
+    }
+
+    /// The weighted mean of the temperatures around a point.
+    fn weigh(
+        samples: &[Sample],
+        latitude: f64,
+        longitude: f64,
+        query: &HeatmapQuery,
+    ) -> Option<f64> {
+        let point = Coordinates {
+            latitude,
+            longitude,
+        };
+        let (mut total, mut weights) = (0.0, 0.0);
+        for sample in samples {
+            let (dx, dy) = project(&point, &sample.location);
+            let distance = dx.hypot(dy);
+            if query.radius.is_some_and(|radius| distance > radius) {
+                continue;
+            }
+            if distance < f64::EPSILON {
+                return Some(sample.temperature);
+            }
+            let weight = distance.powf(-query.power);
+            total += weight * sample.temperature;
+            weights += weight;
+        }
+        (weights > 0.0).then(|| total / weights)
+    }
+
+    /// Formats the grid as an ESRI ASCII grid.
+    fn to_ascii(&self) -> String {
+        let mut ascii = format!(
+            "ncols {}\nnrows {}\nxllcorner {}\nyllcorner {}\ncellsize {}\nNODATA_value {NODATA}\n",
+            self.columns, self.rows, self.west, self.south, self.cell_size
+        );
+        for row in &self.values {
+            let row: Vec<String> = row
+                .iter()
+                .map(|value| value.unwrap_or(NODATA).to_string())
+                .collect();
+            // Writing to a String cannot fail
+            let _ = writeln!(ascii, "{}", row.join(" "));
+        }
+        ascii
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/heatmap",
+    tag = "heatmap",
+    params(Selection, HeatmapQuery),
+    responses(
+        (status = 200, description = "The interpolated temperatures, as an ESRI ASCII grid when `format=ascii`.", body = Grid),
+        (status = 400, description = "The parameters are not valid or the grid is too large.", body = ErrorBody),
+        (status = 404, description = "The trip, path or data does not exist.", body = ErrorBody),
+    )
+)]
+#[get("")]
+/// Interpolates the temperatures of a layer over the surveyed area.
+async fn get_heatmap(
+    selection: Query<Selection>,
+    query: Query<HeatmapQuery>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    let query = query.into_inner();
+    let valid = |value: f64| value.is_finite() && value > 0.0;
+    if !valid(query.resolution) || !valid(query.power) || query.radius.is_some_and(|r| !valid(r)) {
+        return Err(ApiError::BadRequest(
+            "Resolution, Power And Radius Must Be Positive".to_string(),
+        ));
+    }
+    let samples = selection.samples(&state, Some(query.layer.clone())).await?;
+    if samples.is_empty() {
+        return Err(ApiError::NotFound("Data".to_string()));
+    }
+    let format = query.format;
+    let grid = web::block(move || Grid::interpolate(&samples, &query))
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))??;
+    Ok(match format {
+        GridFormat::Json => HttpResponse::Ok().json(grid),
+        GridFormat::Ascii => HttpResponse::Ok()
+            .content_type("text/plain")
+            .body(grid.to_ascii()),
+    })
+}
@@ -0,0 +1,411 @@
+//! Parsing of the CSV files of readings logged offline or collected in manual surveys.
+//!
+//! The columns of the temperature, coordinates, depth, layer and time are found by their
+//! headers, which default to the headers of the exported CSV. The other columns named after a
+//! registered quantity are its measurements, and the rest, such as the name of the path and the
+//! quality control flag of the exported CSV, are ignored.
+
+use std::collections::BTreeMap;
+
+use csv::StringRecord;
+use time::OffsetDateTime;
+
+use crate::error::{ApiError, Result};
+
+use super::{
+    data::Layer,
+    export::{self, DepthUnit, TemperatureUnit, TimeFormat, Zone},
+    quantities::TEMPERATURE,
+    Coordinates,
+};
+
+/// The number of units of the coordinates in a degree when comparing readings.
+const KEY_DEGREE_SCALE: f64 = 1e6;
+/// The number of units of the depths in a metre when comparing readings.
+const KEY_DEPTH_SCALE: f64 = 1e3;
+
+/// The headers of the columns of the fields of the readings.
+pub struct Columns<'a> {
+    /// The header of the temperatures.
+    pub temperature: &'a str,
+    /// The header of the latitudes.
+    pub latitude: &'a str,
+    /// The header of the longitudes.
+    pub longitude: &'a str,
+    /// The header of the depths.
+    pub depth: &'a str,
+    /// The header of the layers.
+    pub layer: &'a str,
+    /// The header of the times.
+    pub time: &'a str,
+}
+
+/// The separators, units and formats of the values in the CSV.
+pub struct Format {
+    /// The delimiter of the fields.
+    pub delimiter: u8,
+    /// The decimal separator of the numbers.
+    pub decimal: u8,
+    /// The unit of the temperatures.
+    pub temperature_unit: TemperatureUnit,
+    /// The unit of the depths.
+    pub depth_unit: DepthUnit,
+    /// The format of the times.
+    pub time_format: TimeFormat,
+    /// The timezone of the times without an offset.
+    pub zone: Zone,
+}
+
+#[derive(Debug)]
+/// A reading of a row.
+pub struct Row {
+    /// The line the row starts on, starting from 1 for the header.
+    pub line: u64,
+    /// The temperature in degrees Celsius.
+    pub temperature: f64,
+    /// The location of the reading.
+    pub location: Coordinates,
+    /// The depth in metres.
+    pub depth: f64,
+    /// The layer of the reading.
+    pub layer: Layer,
+    /// The time of the reading.
+    pub time: OffsetDateTime,
+    /// The other quantities measured, keyed by the name of the quantity.
+    pub measurements: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// The identity of a reading when looking for duplicates: the second of its time, its
+/// coordinates in millionths of a degree and its depth in millimetres.
+pub struct Key {
+    /// The seconds since the UNIX epoch.
+    pub second: i64,
+    /// The latitude in millionths of a degree.
+    pub latitude: i64,
+    /// The longitude in millionths of a degree.
+    pub longitude: i64,
+    /// The depth in millimetres.
+    pub depth: i64,
+}
+
+impl Row {
+    /// The identity of the reading when looking for duplicates.
+    pub fn key(&self) -> Key {
+        Key {
+            second: self.time.unix_timestamp(),
+            latitude: (self.location.latitude * KEY_DEGREE_SCALE).round() as i64,
+            longitude: (self.location.longitude * KEY_DEGREE_SCALE).round() as i64,
+            depth: (self.depth * KEY_DEPTH_SCALE).round() as i64,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A row which could not be used.
+pub struct Rejected {
+    /// The line the row starts on, starting from 1 for the header.
+    pub line: u64,
+    /// The reason the row could not be used.
+    pub error: String,
+}
+
+#[derive(Debug, Default)]
+/// The readings and problems of a CSV.
+pub struct Parsed {
+    /// The readings, in the order of the rows.
+    pub rows: Vec<Row>,
+    /// The rows which could not be used.
+    pub rejected: Vec<Rejected>,
+    /// The headers of the columns which are ignored.
+    pub ignored: Vec<String>,
+}
+
+/// Parses a layer by its name, ignoring the case.
+fn parse_layer(value: &str) -> Option<Layer> {
+    match value.trim().to_lowercase().as_str() {
+        "surface" => Some(Layer::Surface),
+        "middle" => Some(Layer::Middle),
+        "sea bed" | "sea_bed" | "seabed" => Some(Layer::SeaBed),
+        _ => None,
+    }
+}
+
+/// Parses the readings of a CSV with a header row.
+///
+/// The columns named after one of the given registered quantities are its measurements. The
+/// CSV is rejected if a column of the fields is missing, and the rows with an invalid or
+/// missing field are reported back.
+pub fn parse(
+    csv: &[u8],
+    columns: &Columns,
+    format: &Format,
+    quantities: &[String],
+) -> Result<Parsed> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(format.delimiter)
+        .flexible(true)
+        .from_reader(csv);
+    let headers = reader
+        .headers()
+        .map_err(|e| ApiError::BadRequest(format!("Bad CSV Header: {e}")))?
+        .clone();
+    let index = |header: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim() == header)
+            .ok_or_else(|| ApiError::BadRequest(format!("Column {header} Is Missing")))
+    };
+    let fields = [
+        index(columns.temperature)?,
+        index(columns.latitude)?,
+        index(columns.longitude)?,
+        index(columns.depth)?,
+        index(columns.layer)?,
+        index(columns.time)?,
+    ];
+
+    let mut parsed = Parsed::default();
+    let mut measured = Vec::new();
+    for (i, header) in headers.iter().enumerate() {
+        let header = header.trim();
+        if fields.contains(&i) {
+            continue;
+        }
+        if header != TEMPERATURE && quantities.iter().any(|q| q == header) {
+            measured.push((i, header));
+        } else {
+            parsed.ignored.push(header.to_string());
+        }
+    }
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                parsed.rejected.push(Rejected {
+                    line: e.position().map_or(0, |p| p.line()),
+                    error: format!("Bad CSV Row: {e}"),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |p| p.line());
+        if record.len() != headers.len() {
+            parsed.rejected.push(Rejected {
+                line,
+                error: format!(
+                    "Row Has {} Fields Instead Of {}",
+                    record.len(),
+                    headers.len()
+                ),
+            });
+            continue;
+        }
+        match parse_row(&record, line, &headers, fields, &measured, format) {
+            Ok(row) => parsed.rows.push(row),
+            Err(error) => parsed.rejected.push(Rejected { line, error }),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Parses the reading of a row, given the indices of the columns of the fields and of the
+/// measured quantities.
+fn parse_row(
+    record: &StringRecord,
+    line: u64,
+    headers: &StringRecord,
+    [temperature, latitude, longitude, depth, layer, time]: [usize; 6],
+    measured: &[(usize, &str)],
+    format: &Format,
+) -> std::result::Result<Row, String> {
+    let cell = |i: usize| record.get(i).unwrap_or("").trim();
+    let required = |i: usize| {
+        Some(cell(i))
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| format!("{} Is Missing", headers[i].trim()))
+    };
+    let number = |i: usize| {
+        let value = required(i)?;
+        export::parse_number(value, format.decimal)
+            .ok_or_else(|| format!("{} {value:?} Is Not A Number", headers[i].trim()))
+    };
+
+    let location = Coordinates {
+        latitude: number(latitude)?,
+        longitude: number(longitude)?,
+    };
+    if location.latitude.abs() > 90.0 || location.longitude.abs() > 180.0 {
+        return Err(format!(
+            "Coordinates {}, {} Are Out Of Range",
+            location.latitude, location.longitude
+        ));
+    }
+    let layer = required(layer)?;
+    let layer = parse_layer(layer).ok_or_else(|| format!("Layer {layer:?} Is Not Valid"))?;
+    let time = required(time)?;
+    let time = format
+        .time_format
+        .parse(time, format.zone)
+        .ok_or_else(|| format!("Time {time:?} Is Not Valid"))?;
+    let mut measurements = BTreeMap::new();
+    for &(i, quantity) in measured {
+        if !cell(i).is_empty() {
+            measurements.insert(quantity.to_string(), number(i)?);
+        }
+    }
+    Ok(Row {
+        line,
+        temperature: format.temperature_unit.to_celsius(number(temperature)?),
+        location,
+        depth: format.depth_unit.to_metres(number(depth)?),
+        layer,
+        time,
+        measurements,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Columns, Format, Parsed};
+    use crate::{
+        api::{
+            data::Layer,
+            export::{DepthUnit, TemperatureUnit, TimeFormat, Zone},
+        },
+        error::ApiError,
+    };
+
+    /// The headers of the exported CSV.
+    const COLUMNS: Columns<'static> = Columns {
+        temperature: "temperature",
+        latitude: "latitude",
+        longitude: "longitude",
+        depth: "depth",
+        layer: "layer",
+        time: "time",
+    };
+
+    /// The header of the tests, with a measured quantity and an ignored column.
+    const HEADER: &str = "temperature,latitude,longitude,depth,layer,time,salinity,name";
+
+    /// Makes the format of a CSV with separators.
+    fn format(delimiter: u8, decimal: u8) -> Format {
+        Format {
+            delimiter,
+            decimal,
+            temperature_unit: TemperatureUnit::Celsius,
+            depth_unit: DepthUnit::Metres,
+            time_format: TimeFormat::Epoch,
+            zone: Zone::default(),
+        }
+    }
+
+    /// Parses a CSV with the header of the tests and rows.
+    fn parse_rows(rows: &[&str]) -> Parsed {
+        let csv = format!("{HEADER}\n{}\n", rows.join("\n"));
+        parse(
+            csv.as_bytes(),
+            &COLUMNS,
+            &format(b',', b'.'),
+            &["salinity".to_string()],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_reads_the_readings() {
+        let parsed = parse_rows(&[
+            "25.5,5.4,100.3,1.5,surface,1700000000,33.1,p1",
+            " 26 , -5.4 , -100.3 , 0 , Sea Bed , 1700000060 , , p1",
+        ]);
+        assert!(parsed.rejected.is_empty());
+        assert_eq!(parsed.ignored, ["name"]);
+        let [first, second] = &parsed.rows[..] else {
+            panic!("{} Rows Parsed", parsed.rows.len());
+        };
+        assert_eq!((first.line, second.line), (2, 3));
+        assert_eq!(first.temperature, 25.5);
+        assert_eq!(first.location.latitude, 5.4);
+        assert_eq!(first.location.longitude, 100.3);
+        assert_eq!(first.depth, 1.5);
+        assert_eq!(first.layer, Layer::Surface);
+        assert_eq!(first.time.unix_timestamp(), 1_700_000_000);
+        assert_eq!(first.measurements["salinity"], 33.1);
+        assert_eq!(second.layer, Layer::SeaBed);
+        assert!(second.measurements.is_empty());
+    }
+
+    #[test]
+    fn parse_rejects_a_missing_column() {
+        let csv = "temperature,latitude,longitude,layer,time\n25,5,100,surface,1700000000\n";
+        let parsed = parse(csv.as_bytes(), &COLUMNS, &format(b',', b'.'), &[]);
+        let Err(ApiError::BadRequest(message)) = parsed else {
+            panic!("CSV Accepted");
+        };
+        assert_eq!(message, "Column depth Is Missing");
+    }
+
+    #[test]
+    fn parse_reports_the_bad_rows() {
+        let parsed = parse_rows(&[
+            "warm,5.4,100.3,1.5,surface,1700000000,33.1,p1",
+            "25.5,5.4,100.3,1.5,deep,1700000000,33.1,p1",
+            "25.5,91,100.3,1.5,surface,1700000000,33.1,p1",
+            "25.5,5.4,181,1.5,surface,1700000000,33.1,p1",
+            "25.5,5.4,100.3,1.5,surface",
+            "25.5,5.4,100.3,,surface,1700000000,33.1,p1",
+            "25.5,5.4,100.3,1.5,surface,yesterday,33.1,p1",
+            "25.5,5.4,100.3,1.5,surface,1700000000,salty,p1",
+            "25.5,5.4,100.3,1.5,surface,1700000000,33.1,p1",
+        ]);
+        let rejected: Vec<_> = parsed
+            .rejected
+            .iter()
+            .map(|rejected| (rejected.line, rejected.error.as_str()))
+            .collect();
+        assert_eq!(
+            rejected,
+            [
+                (2, "temperature \"warm\" Is Not A Number"),
+                (3, "Layer \"deep\" Is Not Valid"),
+                (4, "Coordinates 91, 100.3 Are Out Of Range"),
+                (5, "Coordinates 5.4, 181 Are Out Of Range"),
+                (6, "Row Has 5 Fields Instead Of 8"),
+                (7, "depth Is Missing"),
+                (8, "Time \"yesterday\" Is Not Valid"),
+                (9, "salinity \"salty\" Is Not A Number"),
+            ]
+        );
+        assert_eq!(parsed.rows.len(), 1);
+        assert_eq!(parsed.rows[0].line, 10);
+    }
+
+    #[test]
+    fn parse_reads_decimal_commas() {
+        let csv = "temperature;latitude;longitude;depth;layer;time
+25,5;-5,25;100;1,5;middle;1700000000
+";
+        let parsed = parse(csv.as_bytes(), &COLUMNS, &format(b';', b','), &[]).unwrap();
+        assert!(parsed.rejected.is_empty());
+        let row = &parsed.rows[0];
+        assert_eq!(row.temperature, 25.5);
+        assert_eq!(row.location.latitude, -5.25);
+        assert_eq!(row.depth, 1.5);
+    }
+
+    #[test]
+    fn duplicate_rows_have_the_same_key() {
+        let parsed = parse_rows(&[
+            "25.5,5.4,100.3,1.5,surface,1700000000,33.1,p1",
+            "25.7,5.4000001,100.3,1.5001,middle,1700000000,,p2",
+            "25.5,5.4,100.3,1.6,surface,1700000000,33.1,p1",
+            "25.5,5.4,100.3,1.5,surface,1700000001,33.1,p1",
+        ]);
+        let keys: Vec<_> = parsed.rows.iter().map(|row| row.key()).collect();
+        assert_eq!(keys[0], keys[1]);
+        assert_ne!(keys[0], keys[2]);
+        assert_ne!(keys[0], keys[3]);
+    }
+}
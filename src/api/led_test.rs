@@ -5,10 +5,16 @@ use actix_web::{
     Responder,
 };
 use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
 
 use crate::AppState;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(OpenApi)]
+#[openapi(paths(get_colour, set_colour), components(schemas(Colour, ColourJson)))]
+/// The OpenAPI specification of the led_test API resources.
+pub struct LedTestApi;
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
 /// An enumeration of all possible colours.
 pub enum Colour {
@@ -25,7 +31,7 @@ pub fn led_test_cfg(cfg: &mut ServiceConfig) {
     cfg.service(scope("/led_test").service(get_colour).service(set_colour));
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
 /// The input JSON body for setting colour.
 struct ColourJson {
     #[serde(alias = "color")]
@@ -33,6 +39,11 @@ struct ColourJson {
     colour: Colour,
 }
 
+#[utoipa::path(
+    context_path = "/api/led_test",
+    tag = "led_test",
+    responses((status = 200, description = "The current colour.", body = ColourJson))
+)]
 #[get("")]
 /// Gets the current colour.
 async fn get_colour(data: Data<AppState>) -> impl Responder {
@@ -42,6 +53,12 @@ async fn get_colour(data: Data<AppState>) -> impl Responder {
     })
 }
 
+#[utoipa::path(
+    context_path = "/api/led_test",
+    tag = "led_test",
+    request_body = ColourJson,
+    responses((status = 200, description = "The colour is set."))
+)]
 #[post("")]
 /// Sets the colour.
 async fn set_colour(colour: Json<ColourJson>, data: Data<AppState>) -> impl Responder {
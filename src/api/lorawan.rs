@@ -0,0 +1,454 @@
+//! Module for Actix services for the devices connected through a LoRaWAN network server.
+//!
+//! The network server forwards the uplinks of the devices to the uplink webhook in the uplink
+//! message format of The Things Stack. The DevEUI of each device is mapped to a registered
+//! device, and the payloads are in the fixed binary layout of the signed endpoints: readings on
+//! port [`READINGS_PORT`] and gps fixes on port [`FIX_PORT`]. The network server fetches the
+//! queued commands of a device as downlinks on port [`COMMAND_PORT`].
+//!
+//! The network server authenticates with the token in the `LORAWAN_TOKEN` environment variable
+//! as a bearer token, the webhook endpoints are disabled if it is not set. The DevEUIs are
+//! mapped and unmapped with the admin token, as for registering the devices.
+//!
+//! The network server retries the uplinks it could not deliver, so an uplink is only inserted
+//! if its frame counter is after the last uplink of the device, or it is received after it as
+//! the counter restarts when the device rejoins. An uplink is recorded as the last one once it
+//! is inserted, so the retry of an uplink which failed is inserted.
+
+use actix_web::{
+    delete, get, post,
+    web::{scope, Data, Json, Path, Query, ServiceConfig},
+    HttpRequest, Responder,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use time::OffsetDateTime;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::{
+    check_admin, check_bearer,
+    commands::{self, Command},
+    data::{self, BatchReport, DataInput},
+    encoding,
+    gps::{self, GPSInput},
+};
+
+/// The environment variable with the token of the network server.
+pub const LORAWAN_TOKEN_ENV: &str = "LORAWAN_TOKEN";
+/// The port of the uplinks with readings.
+pub const READINGS_PORT: u8 = 1;
+/// The port of the uplinks with a gps fix.
+pub const FIX_PORT: u8 = 2;
+/// The port of the downlinks with a command.
+pub const COMMAND_PORT: u8 = 3;
+/// The version of the layout of the command downlinks.
+const COMMAND_VERSION: u8 = 1;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get_lorawan_devices,
+        add_lorawan_device,
+        delete_lorawan_device,
+        post_uplink,
+        get_downlinks
+    ),
+    components(schemas(
+        LorawanDevice,
+        Uplink,
+        EndDeviceIds,
+        UplinkMessage,
+        Downlinks,
+        Downlink
+    ))
+)]
+/// The OpenAPI specification of the LoRaWAN API resources.
+pub struct LorawanApi;
+
+/// Configuration function for the LoRaWAN API resources.
+pub fn lorawan_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/lorawan")
+            .service(get_lorawan_devices)
+            .service(add_lorawan_device)
+            .service(delete_lorawan_device)
+            .service(post_uplink)
+            .service(get_downlinks),
+    );
+}
+
+/// Normalises a DevEUI to 16 uppercase hexadecimal digits.
+fn normalise(dev_eui: &str) -> Result<String> {
+    let dev_eui = dev_eui.replace(['-', ':'], "").to_uppercase();
+    if dev_eui.len() != 16 || !dev_eui.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ApiError::BadRequest(format!(
+            "DevEUI {dev_eui} Is Not 16 Hexadecimal Digits"
+        )));
+    }
+    Ok(dev_eui)
+}
+
+/// Checks that the request is from the network server.
+fn authorize(req: &HttpRequest) -> Result<()> {
+    check_bearer(req, LORAWAN_TOKEN_ENV, "LoRaWAN Is Not Configured")
+}
+
+/// Locks the mapping of a DevEUI until the end of the transaction, returning whether an uplink
+/// is new rather than a retry of an uplink already received.
+///
+/// The retries of an uplink wait for the uplink being inserted, so they are only inserted once.
+async fn lock_uplink(
+    conn: &mut PgConnection,
+    dev_eui: &str,
+    f_cnt: u32,
+    received_at: OffsetDateTime,
+) -> Result<bool> {
+    sqlx::query_scalar!(
+        r#"SELECT (f_cnt IS NULL OR $2 > f_cnt OR $3 > received_at) AS "new!"
+FROM lorawan_devices WHERE dev_eui = $1 FOR UPDATE"#,
+        dev_eui,
+        i64::from(f_cnt),
+        received_at
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| ApiError::Unprocessable(format!("DevEUI {dev_eui} Is Not Mapped To A Device")))
+}
+
+/// Records an uplink of a DevEUI as the last one, once it is inserted.
+async fn record_uplink(
+    conn: &mut PgConnection,
+    dev_eui: &str,
+    f_cnt: u32,
+    received_at: OffsetDateTime,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE lorawan_devices SET f_cnt = $2, received_at = $3 WHERE dev_eui = $1",
+        dev_eui,
+        i64::from(f_cnt),
+        received_at
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Gets the registered device of a DevEUI.
+async fn device_of(state: &AppState, dev_eui: &str) -> Result<String> {
+    sqlx::query_scalar!(
+        "SELECT device FROM lorawan_devices WHERE dev_eui = $1",
+        dev_eui
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| ApiError::Unprocessable(format!("DevEUI {dev_eui} Is Not Mapped To A Device")))
+}
+
+#[derive(Serialize, Deserialize, FromRow, ToSchema)]
+/// The mapping of a LoRaWAN device to a registered device.
+struct LorawanDevice {
+    #[schema(example = "70B3D57ED0000001")]
+    /// The DevEUI of the LoRaWAN device.
+    dev_eui: String,
+    /// The ID of the registered device.
+    device: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/lorawan",
+    tag = "lorawan",
+    responses((status = 200, description = "All the mapped LoRaWAN devices.", body = [LorawanDevice]))
+)]
+#[get("/devices")]
+/// Gets the mappings of the LoRaWAN devices to the registered devices.
+async fn get_lorawan_devices(state: Data<AppState>) -> Result<impl Responder> {
+    let devices = sqlx::query_as!(
+        LorawanDevice,
+        "SELECT dev_eui, device FROM lorawan_devices ORDER BY dev_eui"
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(devices))
+}
+
+#[utoipa::path(
+    context_path = "/api/lorawan",
+    tag = "lorawan",
+    request_body = LorawanDevice,
+    responses(
+        (status = 200, description = "The LoRaWAN device is mapped.", body = LorawanDevice),
+        (status = 400, description = "The DevEUI is not valid.", body = ErrorBody),
+        (status = 401, description = "The admin token is not valid.", body = ErrorBody),
+        (status = 409, description = "The DevEUI is already mapped.", body = ErrorBody),
+        (status = 422, description = "The device does not exist.", body = ErrorBody),
+    )
+)]
+#[post("/devices")]
+/// Maps the DevEUI of a LoRaWAN device to a registered device.
+async fn add_lorawan_device(
+    req: HttpRequest,
+    device: Json<LorawanDevice>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    check_admin(&req)?;
+    let device = LorawanDevice {
+        dev_eui: normalise(&device.dev_eui)?,
+        device: device.device.clone(),
+    };
+    sqlx::query!(
+        "INSERT INTO lorawan_devices (dev_eui, device) VALUES ($1, $2)",
+        device.dev_eui,
+        device.device
+    )
+    .execute(&state.pool)
+    .await?;
+    tracing::info!(
+        dev_eui = device.dev_eui,
+        device = device.device,
+        "LoRaWAN Device Mapped"
+    );
+    Ok(Json(device))
+}
+
+#[utoipa::path(
+    context_path = "/api/lorawan",
+    tag = "lorawan",
+    params(("dev_eui" = String, Path, description = "The DevEUI of the LoRaWAN device.")),
+    responses(
+        (status = 200, description = "The mapping is deleted."),
+        (status = 401, description = "The admin token is not valid.", body = ErrorBody),
+        (status = 404, description = "The DevEUI is not mapped.", body = ErrorBody),
+    )
+)]
+#[delete("/devices/{dev_eui}")]
+/// Deletes the mapping of a LoRaWAN device.
+async fn delete_lorawan_device(
+    req: HttpRequest,
+    dev_eui: Path<String>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    check_admin(&req)?;
+    let dev_eui = normalise(&dev_eui)?;
+    let deleted = sqlx::query!("DELETE FROM lorawan_devices WHERE dev_eui = $1", dev_eui)
+        .execute(&state.pool)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(ApiError::NotFound(format!("DevEUI {dev_eui}")));
+    }
+    Ok("")
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+/// An uplink forwarded by the network server.
+struct Uplink {
+    /// The identifiers of the device which sent the uplink.
+    end_device_ids: EndDeviceIds,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    /// The time the network server received the uplink.
+    received_at: OffsetDateTime,
+    /// The message of the uplink.
+    uplink_message: UplinkMessage,
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+/// The identifiers of a LoRaWAN device.
+struct EndDeviceIds {
+    #[schema(example = "70B3D57ED0000001")]
+    /// The DevEUI of the device.
+    dev_eui: String,
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+/// The message of an uplink.
+struct UplinkMessage {
+    #[serde(default)]
+    /// The frame counter of the uplink, left out by the network server when it is 0.
+    f_cnt: u32,
+    /// The port of the payload, not given for uplinks with only MAC commands.
+    f_port: Option<u8>,
+    /// The base64 encoded payload.
+    frm_payload: Option<String>,
+}
+
+#[utoipa::path(
+    context_path = "/api/lorawan",
+    tag = "lorawan",
+    request_body = Uplink,
+    responses(
+        (status = 200, description = "The readings of the uplink are inserted.", body = BatchReport),
+        (status = 400, description = "The payload is not valid.", body = ErrorBody),
+        (status = 401, description = "The bearer token is not valid.", body = ErrorBody),
+        (status = 422, description = "The DevEUI is not mapped or the trip does not exist.", body = ErrorBody),
+    )
+)]
+#[post("/uplink")]
+/// Inserts the readings or the gps fix of an uplink forwarded by the network server.
+///
+/// The readings and fixes without a time are recorded at the time the network server received
+/// the uplink. The retries of an uplink already received are acknowledged without inserting
+/// anything.
+async fn post_uplink(
+    req: HttpRequest,
+    uplink: Json<Uplink>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    authorize(&req)?;
+    let dev_eui = normalise(&uplink.end_device_ids.dev_eui)?;
+    let device = device_of(&state, &dev_eui).await?;
+    let message = &uplink.uplink_message;
+    let (Some(port), Some(payload)) = (message.f_port, &message.frm_payload) else {
+        return Ok(Json(BatchReport::default()));
+    };
+    let payload = BASE64
+        .decode(payload)
+        .map_err(|e| ApiError::BadRequest(format!("Payload Is Not Base64: {e}")))?;
+    // The uplink is only recorded once it is inserted, so the retries of an uplink which failed
+    // are inserted
+    let mut tx = state.pool.begin().await?;
+    if !lock_uplink(&mut tx, &dev_eui, message.f_cnt, uplink.received_at).await? {
+        tracing::info!(
+            dev_eui,
+            device,
+            f_cnt = message.f_cnt,
+            "LoRaWAN Uplink Already Received"
+        );
+        return Ok(Json(BatchReport::default()));
+    }
+
+    let report = match port {
+        READINGS_PORT => {
+            let mut readings: Vec<DataInput> = encoding::decode_binary(&payload)?;
+            for reading in &mut readings {
+                reading.time = reading.time.or(Some(uplink.received_at));
+            }
+            data::insert_batch(&state, &readings, &device).await
+        }
+        FIX_PORT => {
+            let mut fix: GPSInput = encoding::decode_binary(&payload)?;
+            fix.time = fix.time.or(Some(uplink.received_at));
+            gps::insert_fix(&state, &fix, &device).await?;
+            BatchReport {
+                inserted: 1,
+                ..Default::default()
+            }
+        }
+        port => {
+            return Err(ApiError::BadRequest(format!(
+                "Port {port} Is Not Supported"
+            )))
+        }
+    };
+    record_uplink(&mut tx, &dev_eui, message.f_cnt, uplink.received_at).await?;
+    tx.commit().await?;
+    tracing::info!(dev_eui, device, port, "LoRaWAN Uplink Received");
+    Ok(Json(report))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+/// The query specification for fetching downlinks.
+struct DownlinksQuery {
+    #[serde(default = "DownlinksQuery::count_default")]
+    /// The maximum number of downlinks to take.
+    count: i64,
+}
+
+impl DownlinksQuery {
+    /// Defaults count to 1 downlink, as a class A device receives one after each uplink.
+    fn count_default() -> i64 {
+        1
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+/// The downlinks of a device, in the format of the downlink queue of The Things Stack.
+struct Downlinks {
+    /// The downlinks.
+    downlinks: Vec<Downlink>,
+}
+
+#[derive(Serialize, ToSchema)]
+/// A downlink with a command.
+struct Downlink {
+    /// The port of the payload.
+    f_port: u8,
+    /// The base64 encoded payload.
+    frm_payload: String,
+    /// Whether the device acknowledges the downlink.
+    confirmed: bool,
+    #[schema(example = json!(["awtc:command:1"]))]
+    /// The identifiers of the downlink, with the ID of the command.
+    correlation_ids: Vec<String>,
+}
+
+impl Downlink {
+    /// Encodes a command as the version of the layout followed by a CBOR array of the ID and
+    /// name of the command, and its arguments if there are any.
+    fn encode(command: &Command) -> Self {
+        let mut payload = vec![COMMAND_VERSION];
+        let written = if command
+            .arguments
+            .as_object()
+            .is_some_and(serde_json::Map::is_empty)
+        {
+            ciborium::into_writer(&(command.id, &command.command), &mut payload)
+        } else {
+            ciborium::into_writer(
+                &(command.id, &command.command, &command.arguments),
+                &mut payload,
+            )
+        };
+        written.expect("writing to a vector does not fail");
+        Self {
+            f_port: COMMAND_PORT,
+            frm_payload: BASE64.encode(payload),
+            confirmed: true,
+            correlation_ids: vec![format!("awtc:command:{}", command.id)],
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/lorawan",
+    tag = "lorawan",
+    params(
+        ("dev_eui" = String, Path, description = "The DevEUI of the LoRaWAN device."),
+        DownlinksQuery
+    ),
+    responses(
+        (status = 200, description = "The pending commands, oldest first, which are now delivered.", body = Downlinks),
+        (status = 401, description = "The bearer token is not valid.", body = ErrorBody),
+        (status = 422, description = "The DevEUI is not mapped.", body = ErrorBody),
+    )
+)]
+#[get("/downlinks/{dev_eui}")]
+/// Takes the pending commands of a LoRaWAN device as downlinks for the network server.
+async fn get_downlinks(
+    req: HttpRequest,
+    dev_eui: Path<String>,
+    query: Query<DownlinksQuery>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    authorize(&req)?;
+    let dev_eui = normalise(&dev_eui)?;
+    let device = device_of(&state, &dev_eui).await?;
+    let commands = commands::take_pending(&state.pool, &device, query.count).await?;
+    let downlinks: Vec<Downlink> = commands.iter().map(Downlink::encode).collect();
+    if !downlinks.is_empty() {
+        tracing::info!(
+            dev_eui,
+            device,
+            count = downlinks.len(),
+            "Downlinks Delivered"
+        );
+    }
+    Ok(Json(Downlinks { downlinks }))
+}
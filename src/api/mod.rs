@@ -1,48 +1,181 @@
 //! REST API related functions.
 
 use actix_web::{
-    error::InternalError,
-    web::{scope, JsonConfig, ServiceConfig},
-    HttpResponse,
+    get,
+    web::{scope, JsonConfig, PathConfig, QueryConfig, ServiceConfig},
+    HttpRequest, HttpResponse, Responder,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::error::{ApiError, ErrorBody, Result};
 
 use self::{
-    data::data_cfg, gps::gps_cfg, led_test::led_test_cfg, paths::paths_cfg, trips::trips_cfg,
+    aggregate::{aggregate_cfg, AggregateApi},
+    alerts::{alerts_cfg, AlertsApi},
+    backup::{backup_cfg, BackupApi},
+    commands::{commands_cfg, CommandsApi},
+    data::{data_cfg, DataApi},
+    devices::{devices_cfg, DevicesApi},
+    geofence::{events_cfg, EventsApi},
+    gps::{gps_cfg, GpsApi},
+    heatmap::{heatmap_cfg, HeatmapApi},
+    led_test::{led_test_cfg, LedTestApi},
+    lorawan::{lorawan_cfg, LorawanApi},
+    paths::{paths_cfg, PathsApi},
+    profiles::{profiles_cfg, ProfilesApi},
+    qc::{qc_cfg, QcApi},
+    quantities::{quantities_cfg, QuantitiesApi},
+    sensors::{sensors_cfg, SensorsApi},
+    telemetry::{telemetry_cfg, TelemetryApi},
+    trips::{trips_cfg, TripsApi},
 };
 
+pub use alerts::{check_silence, deliver_alerts, AlertSender, ALERT_QUEUE_SIZE, WEBHOOK_TIMEOUT};
 pub use led_test::Colour;
+pub use mqtt::start_bridge;
+pub use signature::NonceCache;
 
+mod aggregate;
+mod alerts;
+mod backup;
+mod commands;
 mod data;
+mod devices;
+mod encoding;
+mod export;
+mod geofence;
 mod gps;
+mod heatmap;
+mod import;
 mod led_test;
+mod lorawan;
+mod mqtt;
+mod nmea;
 mod paths;
+mod profiles;
+mod qc;
+mod quantities;
+mod selection;
+mod sensors;
+mod signature;
+mod telemetry;
+mod track;
 mod trips;
 
 /// Configuration function for the API resources.
 pub fn api_cfg(cfg: &mut ServiceConfig) {
-    let config = JsonConfig::default().error_handler(|err, _| {
-        InternalError::from_response(
-            err,
-            HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Bad JSON Data"
-            })),
-        )
-        .into()
-    });
+    let json_config =
+        JsonConfig::default().error_handler(|err, _| ApiError::InvalidJson(err.to_string()).into());
+    let query_config =
+        QueryConfig::default().error_handler(|err, _| ApiError::BadRequest(err.to_string()).into());
+    let path_config =
+        PathConfig::default().error_handler(|err, _| ApiError::BadRequest(err.to_string()).into());
     cfg.service(
         scope("/api")
-            .app_data(config)
+            .app_data(json_config)
+            .app_data(query_config)
+            .app_data(path_config)
+            .service(get_openapi)
             .configure(data_cfg)
             .configure(trips_cfg)
             .configure(gps_cfg)
             .configure(led_test_cfg)
-            .configure(paths_cfg),
+            .configure(paths_cfg)
+            .configure(devices_cfg)
+            .configure(quantities_cfg)
+            .configure(sensors_cfg)
+            .configure(qc_cfg)
+            .configure(heatmap_cfg)
+            .configure(profiles_cfg)
+            .configure(aggregate_cfg)
+            .configure(telemetry_cfg)
+            .configure(alerts_cfg)
+            .configure(commands_cfg)
+            .configure(events_cfg)
+            .configure(lorawan_cfg)
+            .configure(backup_cfg),
     );
 }
 
-#[derive(Serialize, Deserialize, FromRow, Debug)]
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "AWTC-R API",
+        description = "REST API of the Autonomous Water Temperature Collection Robot.",
+        license(name = "GPL-3.0")
+    ),
+    components(schemas(Coordinates, ErrorBody))
+)]
+/// The root of the OpenAPI specification.
+struct ApiDoc;
+
+/// Generates the OpenAPI specification of all the API resources.
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    doc.merge(DataApi::openapi());
+    doc.merge(TripsApi::openapi());
+    doc.merge(GpsApi::openapi());
+    doc.merge(LedTestApi::openapi());
+    doc.merge(PathsApi::openapi());
+    doc.merge(DevicesApi::openapi());
+    doc.merge(QuantitiesApi::openapi());
+    doc.merge(SensorsApi::openapi());
+    doc.merge(QcApi::openapi());
+    doc.merge(HeatmapApi::openapi());
+    doc.merge(ProfilesApi::openapi());
+    doc.merge(AggregateApi::openapi());
+    doc.merge(TelemetryApi::openapi());
+    doc.merge(AlertsApi::openapi());
+    doc.merge(CommandsApi::openapi());
+    doc.merge(EventsApi::openapi());
+    doc.merge(LorawanApi::openapi());
+    doc.merge(BackupApi::openapi());
+    doc
+}
+
+#[get("/openapi.json")]
+/// Gets the OpenAPI specification of the API.
+async fn get_openapi() -> impl Responder {
+    HttpResponse::Ok().json(openapi())
+}
+
+/// The environment variable with the token of the administrators.
+const ADMIN_TOKEN_ENV: &str = "ADMIN_TOKEN";
+
+/// Checks that a request is from an administrator, by the token in [`ADMIN_TOKEN_ENV`].
+///
+/// The administration resources are disabled if the token is not set.
+fn check_admin(req: &HttpRequest) -> Result<()> {
+    check_bearer(req, ADMIN_TOKEN_ENV, "Administration Is Not Configured")
+}
+
+/// Checks that a request carries the bearer token in an environment variable.
+///
+/// The resources are disabled, with the message `disabled`, if the variable is not set.
+fn check_bearer(req: &HttpRequest, env: &str, disabled: &str) -> Result<()> {
+    let token = std::env::var(env).map_err(|_| ApiError::Unauthorized(disabled.to_string()))?;
+    let given = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| ApiError::Unauthorized("Missing Bearer Token".to_string()))?;
+    // Compared in constant time to not leak the token through the timing of the responses
+    let equal = given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0;
+    if !equal {
+        return Err(ApiError::Unauthorized("Invalid Bearer Token".to_string()));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, FromRow, ToSchema, Debug, Clone)]
 /// A struct representing a coordinate in a map.
 pub struct Coordinates {
     #[serde(alias = "lat")]
@@ -52,3 +185,128 @@ pub struct Coordinates {
     /// The lattitude of the coordinate.
     longitude: f64,
 }
+
+/// The mean radius of the Earth in metres.
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+impl Coordinates {
+    /// The great-circle distance to another coordinate in metres.
+    fn distance(&self, other: &Coordinates) -> f64 {
+        let (lat_a, lat_b) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat_b - lat_a;
+        let d_lng = (other.longitude - self.longitude).to_radians();
+        let h =
+            (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lng / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * h.sqrt().asin()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::openapi;
+
+    /// The paths of the API and their methods.
+    const PATHS: &[(&str, &[&str])] = &[
+        ("/api/aggregate", &["get"]),
+        ("/api/alerts", &["get"]),
+        ("/api/alerts/rules", &["get", "post"]),
+        ("/api/alerts/rules/{id}", &["delete"]),
+        ("/api/backup", &["get"]),
+        ("/api/backup/restore", &["post"]),
+        ("/api/commands", &["get", "post"]),
+        ("/api/commands/poll", &["post"]),
+        ("/api/data", &["post"]),
+        ("/api/data/batch", &["post"]),
+        ("/api/data/import", &["post"]),
+        ("/api/data/{uuid}", &["get"]),
+        ("/api/devices", &["get", "post"]),
+        ("/api/events", &["get"]),
+        ("/api/gps", &["get", "post"]),
+        ("/api/gps/flag", &["post"]),
+        ("/api/gps/nmea", &["post"]),
+        ("/api/heatmap", &["get"]),
+        ("/api/led_test", &["get", "post"]),
+        ("/api/lorawan/devices", &["get", "post"]),
+        ("/api/lorawan/devices/{dev_eui}", &["delete"]),
+        ("/api/lorawan/downlinks/{dev_eui}", &["get"]),
+        ("/api/lorawan/uplink", &["post"]),
+        ("/api/paths", &["get", "post"]),
+        ("/api/paths/{uuid}", &["get"]),
+        ("/api/paths/{uuid}/geofence", &["post"]),
+        ("/api/profiles", &["get"]),
+        ("/api/qc/{uuid}", &["post"]),
+        ("/api/quantities", &["get", "post"]),
+        ("/api/sensors", &["get", "post"]),
+        ("/api/sensors/{serial}/calibrations", &["get", "post"]),
+        ("/api/sensors/{serial}/installations", &["get", "post"]),
+        ("/api/sensors/{serial}/uninstall", &["post"]),
+        ("/api/telemetry", &["get", "post"]),
+        ("/api/trips", &["get", "post"]),
+        ("/api/trips/{uuid}", &["get"]),
+    ];
+
+    /// Collects the schemas referenced anywhere in a part of the specification.
+    fn references<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    match (key.as_str(), value) {
+                        ("$ref", Value::String(reference)) => found.push(reference),
+                        _ => references(value, found),
+                    }
+                }
+            }
+            Value::Array(values) => values.iter().for_each(|value| references(value, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn spec_has_the_paths_of_the_routes() {
+        let spec = serde_json::to_value(openapi()).unwrap();
+        let paths = spec["paths"].as_object().unwrap();
+        let mut documented: Vec<_> = paths
+            .iter()
+            .map(|(path, item)| {
+                let mut methods: Vec<_> = item.as_object().unwrap().keys().cloned().collect();
+                methods.sort();
+                (path.clone(), methods)
+            })
+            .collect();
+        documented.sort();
+        let expected: Vec<_> = PATHS
+            .iter()
+            .map(|(path, methods)| {
+                let methods = methods.iter().map(|method| method.to_string()).collect();
+                (path.to_string(), methods)
+            })
+            .collect();
+        assert_eq!(documented, expected);
+    }
+
+    #[test]
+    fn spec_has_the_referenced_schemas() {
+        let spec = serde_json::to_value(openapi()).unwrap();
+        let schemas = spec["components"]["schemas"].as_object().unwrap();
+        let mut found = Vec::new();
+        references(&spec, &mut found);
+        assert!(!found.is_empty());
+        for reference in found {
+            let name = reference
+                .strip_prefix("#/components/schemas/")
+                .unwrap_or_else(|| panic!("{reference} is not a schema"));
+            assert!(schemas.contains_key(name), "{name} is not in the spec");
+        }
+        for name in [
+            "Coordinates",
+            "ErrorBody",
+            "DataInput",
+            "GPSInput",
+            "TripSummary",
+        ] {
+            assert!(schemas.contains_key(name), "{name} is not in the spec");
+        }
+    }
+}
@@ -0,0 +1,558 @@
+//! Module for the bridge between the MQTT broker of the devices and the database.
+//!
+//! The devices publish their readings to `{prefix}/{device}/data` and their gps fixes to
+//! `{prefix}/{device}/gps`, with the same JSON bodies as the HTTP endpoints. The messages are
+//! not signed, the broker is trusted to authenticate the devices and to only let each device
+//! publish to its own topics. The pending commands of the devices which published are
+//! published to `{prefix}/{device}/commands`, and the messages which could not be inserted are
+//! answered on `{prefix}/{device}/errors`.
+//!
+//! The messages of each device are handled in order by a task of the device, so its readings
+//! are checked against the previous ones. The commands are marked as delivered once the broker
+//! acknowledges them, and are published again after a restart otherwise.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix_web::web::Data;
+use rumqttc::{
+    AsyncClient, ClientError, Event, EventLoop, MqttOptions, OptionError, Outgoing, Packet,
+    Publish, QoS, SubscribeFilter,
+};
+use sqlx::PgPool;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::{
+    commands,
+    data::{self, DataInput},
+    gps::{self, GPSInput},
+};
+
+/// The environment variable with the URL of the broker, the bridge is disabled if not set.
+pub const MQTT_URL_ENV: &str = "MQTT_URL";
+/// The environment variable with the root of the topics, defaulting to `awtc`.
+pub const MQTT_PREFIX_ENV: &str = "MQTT_PREFIX";
+/// The root of the topics if not configured.
+const DEFAULT_PREFIX: &str = "awtc";
+/// The number of requests to the broker, or of messages of a device, queued before waiting.
+const CAPACITY: usize = 64;
+/// The interval of the keep alive pings to the broker.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// The delay before reconnecting to the broker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// The interval between publishing the commands queued for the devices.
+const COMMAND_INTERVAL: Duration = Duration::from_secs(5);
+/// The maximum number of commands published to a device at a time.
+const COMMAND_BATCH: i64 = 10;
+
+/// The connection to the broker.
+struct Bridge {
+    /// The client publishing to the broker.
+    client: AsyncClient,
+    /// The root of the topics.
+    prefix: String,
+    /// The devices which published to the broker, and are sent their commands through it.
+    devices: Mutex<HashSet<String>>,
+    /// The queues of the messages of the devices, each handled by a task of the device.
+    queues: Mutex<HashMap<String, Sender<Publish>>>,
+    /// The publishes which are not acknowledged yet.
+    outbox: Mutex<Outbox>,
+    /// Held while queueing a publish, so the publishes are recorded in the order they are sent.
+    publishing: tokio::sync::Mutex<()>,
+}
+
+#[derive(Default)]
+/// The publishes to the broker which are not acknowledged yet, with the command they deliver.
+///
+/// The client does not tell the packet IDs of the publishes, so the publishes are recorded in
+/// the order they are queued and matched with the packets sent by the event loop, which sends
+/// them in that order.
+struct Outbox {
+    /// The publishes queued to the client which are not sent yet, oldest first.
+    queued: VecDeque<Option<i32>>,
+    /// The publishes sent which are not acknowledged yet, by packet ID.
+    sent: HashMap<u16, Option<i32>>,
+    /// The publish waiting for the acknowledgement of the publish with the same packet ID.
+    collided: Option<(u16, Option<i32>)>,
+    /// The packet ID of a collision resolved before its acknowledgement was received.
+    resolved: Option<u16>,
+    /// The commands published and not acknowledged yet, which are not published again.
+    commands: HashSet<i32>,
+}
+
+impl Outbox {
+    /// Records a publish sent by the event loop, returning the command acknowledged if the
+    /// publish resolves a collision.
+    ///
+    /// A packet ID which is not acknowledged yet is a publish sent again after reconnecting.
+    fn sent(&mut self, pkid: u16) -> Option<i32> {
+        match self.collided {
+            // The collision is resolved before the acknowledgement is received
+            Some((collided, command)) if collided == pkid => {
+                self.collided = None;
+                self.resolved = Some(pkid);
+                self.sent.insert(pkid, command).flatten()
+            }
+            _ if self.sent.contains_key(&pkid) => None,
+            _ => {
+                let command = self.queued.pop_front().flatten();
+                self.sent.insert(pkid, command);
+                None
+            }
+        }
+    }
+
+    /// Records a publish which waits for the acknowledgement of its packet ID to be sent.
+    fn collided(&mut self, pkid: u16) {
+        if !matches!(self.collided, Some((collided, _)) if collided == pkid) {
+            self.collided = Some((pkid, self.queued.pop_front().flatten()));
+        }
+    }
+
+    /// Records an acknowledgement, returning the command acknowledged if any.
+    fn acknowledged(&mut self, pkid: u16) -> Option<i32> {
+        if self.resolved == Some(pkid) {
+            self.resolved = None;
+            return None;
+        }
+        self.sent.remove(&pkid).flatten()
+    }
+}
+
+/// Starts the MQTT bridge if a broker is configured.
+pub fn start_bridge(state: Data<AppState>) -> Result<(), OptionError> {
+    let Ok(url) = std::env::var(MQTT_URL_ENV) else {
+        tracing::info!("MQTT Bridge Disabled");
+        return Ok(());
+    };
+    let mut options = MqttOptions::parse_url(url)?;
+    options.set_keep_alive(KEEP_ALIVE);
+    let prefix = std::env::var(MQTT_PREFIX_ENV).unwrap_or_else(|_| DEFAULT_PREFIX.to_string());
+    tracing::info!(
+        broker = options.broker_address().0,
+        prefix,
+        "MQTT Bridge Enabled"
+    );
+
+    let (client, events) = AsyncClient::new(options, CAPACITY);
+    let bridge = Arc::new(Bridge::new(client, prefix));
+    tokio::spawn(bridge.clone().receive(state.clone(), events));
+    tokio::spawn(bridge.publish_commands(state));
+    Ok(())
+}
+
+impl Bridge {
+    /// Creates a bridge publishing through a client.
+    fn new(client: AsyncClient, prefix: String) -> Self {
+        Bridge {
+            client,
+            prefix,
+            devices: Mutex::new(HashSet::new()),
+            queues: Mutex::new(HashMap::new()),
+            outbox: Mutex::new(Outbox::default()),
+            publishing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Handles the events of the connection, subscribing on every connection as the broker
+    /// does not keep the subscriptions of a clean session.
+    async fn receive(self: Arc<Self>, state: Data<AppState>, mut events: EventLoop) {
+        loop {
+            match events.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    tracing::info!("MQTT Connected");
+                    let topics = ["data", "gps"].map(|kind| {
+                        SubscribeFilter::new(format!("{}/+/{kind}", self.prefix), QoS::AtLeastOnce)
+                    });
+                    if let Err(e) = self.client.try_subscribe_many(topics) {
+                        tracing::error!("MQTT Subscription Failed: {e}");
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    self.dispatch(&state, publish).await;
+                }
+                Ok(event) => self.track(&state.pool, &event),
+                Err(e) => {
+                    tracing::error!("MQTT Connection Failed: {e}");
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    }
+
+    /// Matches the publishes sent by the event loop with their acknowledgements, marking the
+    /// commands as delivered once acknowledged.
+    fn track(self: &Arc<Self>, pool: &PgPool, event: &Event) {
+        let mut outbox = self.outbox.lock().unwrap();
+        let acknowledged = match event {
+            Event::Outgoing(Outgoing::Publish(pkid)) => outbox.sent(*pkid),
+            Event::Outgoing(Outgoing::AwaitAck(pkid)) => {
+                outbox.collided(*pkid);
+                None
+            }
+            Event::Incoming(Packet::PubAck(ack)) => outbox.acknowledged(ack.pkid),
+            _ => None,
+        };
+        if let Some(command) = acknowledged {
+            tokio::spawn(self.clone().acknowledge(pool.clone(), command));
+        }
+    }
+
+    /// Marks a command acknowledged by the broker as delivered, publishing it again later if
+    /// it could not be marked.
+    async fn acknowledge(self: Arc<Self>, pool: PgPool, command: i32) {
+        if let Err(e) = commands::mark_delivered(&pool, command).await {
+            tracing::error!(command, "Marking Command Delivered Failed: {e:?}");
+        }
+        self.outbox.lock().unwrap().commands.remove(&command);
+    }
+
+    /// Splits a topic into its device and kind.
+    fn split_topic<'a>(&self, topic: &'a str) -> Option<(&'a str, &'a str)> {
+        topic
+            .strip_prefix(&self.prefix)
+            .and_then(|topic| topic.strip_prefix('/'))
+            .and_then(|topic| topic.split_once('/'))
+    }
+
+    /// Queues a message to the task of its device, starting the task for the first message of
+    /// the device.
+    async fn dispatch(self: &Arc<Self>, state: &Data<AppState>, publish: Publish) {
+        let Some((device, _)) = self.split_topic(&publish.topic) else {
+            tracing::warn!(topic = publish.topic, "MQTT Message Ignored");
+            return;
+        };
+        let device = device.to_string();
+        let queue = self
+            .queues
+            .lock()
+            .unwrap()
+            .entry(device.clone())
+            .or_insert_with(|| {
+                let (queue, messages) = mpsc::channel(CAPACITY);
+                tokio::spawn(self.clone().handle_device(state.clone(), messages));
+                queue
+            })
+            .clone();
+        if queue.send(publish).await.is_err() {
+            tracing::error!(device, "MQTT Device Task Stopped");
+        }
+    }
+
+    /// Handles the messages of a device one at a time, so they are inserted in order.
+    async fn handle_device(
+        self: Arc<Self>,
+        state: Data<AppState>,
+        mut messages: Receiver<Publish>,
+    ) {
+        while let Some(publish) = messages.recv().await {
+            self.handle(&state, publish).await;
+        }
+    }
+
+    /// Inserts a message from a device, answering it with the pending commands of the device
+    /// or the error.
+    async fn handle(&self, state: &AppState, publish: Publish) {
+        let Some((device, kind)) = self.split_topic(&publish.topic) else {
+            return;
+        };
+        match self.insert(state, device, kind, &publish.payload).await {
+            Ok(()) => {
+                self.devices.lock().unwrap().insert(device.to_string());
+                self.deliver(&state.pool, device).await;
+            }
+            Err(e) => {
+                tracing::warn!(topic = publish.topic, "MQTT Message Rejected: {e:?}");
+                let error = serde_json::json!({
+                    "topic": publish.topic,
+                    "code": e.code(),
+                    "message": e.to_string(),
+                });
+                let topic = format!("{}/{device}/errors", self.prefix);
+                if let Err(e) = self.publish(topic, error.to_string(), None).await {
+                    tracing::error!(device, "MQTT Publish Failed: {e}");
+                }
+            }
+        }
+    }
+
+    /// Validates and inserts a message from a device.
+    async fn insert(
+        &self,
+        state: &AppState,
+        device: &str,
+        kind: &str,
+        payload: &[u8],
+    ) -> Result<()> {
+        let registered = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM devices WHERE id = $1) AS "exists!""#,
+            device
+        )
+        .fetch_one(&state.pool)
+        .await?;
+        if !registered {
+            return Err(ApiError::Unauthorized("Unknown Device".to_string()));
+        }
+        let invalid = |e: serde_json::Error| ApiError::InvalidJson(e.to_string());
+        match kind {
+            "data" => {
+                let data: DataInput = serde_json::from_slice(payload).map_err(invalid)?;
+                data::insert_data(state, &data, device).await
+            }
+            "gps" => {
+                let fix: GPSInput = serde_json::from_slice(payload).map_err(invalid)?;
+                gps::insert_fix(state, &fix, device).await
+            }
+            _ => Err(ApiError::NotFound(format!("Topic {kind}"))),
+        }
+    }
+
+    /// Publishes the pending commands of a device which are not published yet, which are
+    /// marked as delivered once acknowledged.
+    ///
+    /// The commands after one which could not be published stay pending, so they are not
+    /// published out of order.
+    async fn deliver(&self, pool: &PgPool, device: &str) {
+        if let Err(e) = self.publish_pending(pool, device).await {
+            tracing::error!(device, "Delivering Commands Failed: {e:?}");
+        }
+    }
+
+    /// Publishes the pending commands of a device which are not published yet.
+    async fn publish_pending(&self, pool: &PgPool, device: &str) -> Result<()> {
+        let commands = commands::pending(pool, device, COMMAND_BATCH).await?;
+        let topic = format!("{}/{device}/commands", self.prefix);
+        let mut published = 0;
+        for command in &commands {
+            if !self.outbox.lock().unwrap().commands.insert(command.id) {
+                continue;
+            }
+            let payload = serde_json::json!(command).to_string();
+            if let Err(e) = self.publish(topic.clone(), payload, Some(command.id)).await {
+                tracing::error!(device, command = command.id, "MQTT Publish Failed: {e}");
+                self.outbox.lock().unwrap().commands.remove(&command.id);
+                break;
+            }
+            published += 1;
+        }
+        if published > 0 {
+            tracing::info!(device, count = published, "Commands Published");
+        }
+        Ok(())
+    }
+
+    /// Queues a publish to the broker, with the command it delivers if any.
+    async fn publish(
+        &self,
+        topic: String,
+        payload: String,
+        command: Option<i32>,
+    ) -> Result<(), ClientError> {
+        let _publishing = self.publishing.lock().await;
+        self.outbox.lock().unwrap().queued.push_back(command);
+        let published = self
+            .client
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .await;
+        if published.is_err() {
+            self.outbox.lock().unwrap().queued.pop_back();
+        }
+        published
+    }
+
+    /// Periodically publishes the commands queued for the devices which published to the
+    /// broker.
+    async fn publish_commands(self: Arc<Self>, state: Data<AppState>) {
+        let mut interval = tokio::time::interval(COMMAND_INTERVAL);
+        loop {
+            interval.tick().await;
+            let devices: Vec<String> = self.devices.lock().unwrap().iter().cloned().collect();
+            for device in devices {
+                self.deliver(&state.pool, &device).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+    use sqlx::PgPool;
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    use super::{Bridge, Outbox, CAPACITY, MQTT_URL_ENV};
+    use crate::api::commands;
+
+    /// The root of the topics of the tests.
+    const PREFIX: &str = "awtc-test";
+
+    /// Connects to the database with the schema at `DATABASE_URL`, registering a device with
+    /// two queued commands.
+    async fn device_with_commands() -> (PgPool, String, Vec<i32>) {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL Not Set");
+        let pool = PgPool::connect(&url).await.unwrap();
+        let device = format!("mqtt-{}", Uuid::new_v4());
+        sqlx::query("INSERT INTO devices (id, secret) VALUES ($1, '')")
+            .bind(&device)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut ids = Vec::new();
+        for command in ["return_home", "sleep"] {
+            let arguments = serde_json::json!({});
+            ids.push(
+                commands::enqueue(&pool, &device, command, arguments)
+                    .await
+                    .unwrap(),
+            );
+        }
+        (pool, device, ids)
+    }
+
+    /// The IDs of the commands of a device which are not delivered.
+    async fn pending(pool: &PgPool, device: &str) -> Vec<i32> {
+        sqlx::query_scalar(
+            "SELECT id FROM commands WHERE device = $1 AND delivered_at IS NULL ORDER BY id",
+        )
+        .bind(device)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    /// The IDs of the commands of a device which are not delivered, removing the device.
+    async fn pending_and_remove(pool: &PgPool, device: &str) -> Vec<i32> {
+        let pending = pending(pool, device).await;
+        sqlx::query("DELETE FROM devices WHERE id = $1")
+            .bind(device)
+            .execute(pool)
+            .await
+            .unwrap();
+        pending
+    }
+
+    /// Creates a bridge publishing through a client.
+    fn bridge(client: AsyncClient) -> Arc<Bridge> {
+        Arc::new(Bridge::new(client, PREFIX.to_string()))
+    }
+
+    #[test]
+    fn publishes_are_matched_in_order() {
+        let mut outbox = Outbox::default();
+        outbox.queued.extend([Some(1), None, Some(2)]);
+        for pkid in 1..=3 {
+            assert_eq!(outbox.sent(pkid), None);
+        }
+        assert_eq!(outbox.acknowledged(3), Some(2));
+        assert_eq!(outbox.acknowledged(2), None);
+        assert_eq!(outbox.acknowledged(1), Some(1));
+        assert!(outbox.queued.is_empty() && outbox.sent.is_empty());
+    }
+
+    #[test]
+    fn publishes_sent_again_are_not_matched() {
+        let mut outbox = Outbox::default();
+        outbox.queued.extend([Some(1), Some(2)]);
+        assert_eq!(outbox.sent(1), None);
+        // Sent again after reconnecting, before the next publish
+        assert_eq!(outbox.sent(1), None);
+        assert_eq!(outbox.sent(2), None);
+        assert_eq!(outbox.acknowledged(1), Some(1));
+        assert_eq!(outbox.acknowledged(2), Some(2));
+    }
+
+    #[test]
+    fn collisions_are_matched_once_resolved() {
+        let mut outbox = Outbox::default();
+        outbox.queued.extend([Some(1), Some(2)]);
+        assert_eq!(outbox.sent(1), None);
+        outbox.collided(1);
+        // The collision is sent before the acknowledgement of the first publish is received
+        assert_eq!(outbox.sent(1), Some(1));
+        assert_eq!(outbox.acknowledged(1), None);
+        assert_eq!(outbox.acknowledged(1), Some(2));
+        assert!(outbox.sent.is_empty());
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires a database at DATABASE_URL"]
+    async fn commands_stay_pending_when_not_published() {
+        let (pool, device, ids) = device_with_commands().await;
+        // The requests of a client fail once its event loop is dropped
+        let (client, _) = AsyncClient::new(MqttOptions::new("awtc-test", "localhost", 1883), 1);
+        bridge(client).deliver(&pool, &device).await;
+        assert_eq!(pending_and_remove(&pool, &device).await, ids);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires a broker at MQTT_URL and a database at DATABASE_URL"]
+    async fn commands_are_published_in_order() {
+        let url = std::env::var(MQTT_URL_ENV).expect("MQTT_URL Not Set");
+        let options = MqttOptions::parse_url(url).unwrap();
+        let (host, port) = options.broker_address();
+        let (pool, device, ids) = device_with_commands().await;
+
+        let subscriber = MqttOptions::new(format!("awtc-test-{}", Uuid::new_v4()), host, port);
+        let (subscriber, mut events) = AsyncClient::new(subscriber, CAPACITY);
+        subscriber
+            .subscribe(format!("{PREFIX}/{device}/commands"), QoS::AtLeastOnce)
+            .await
+            .unwrap();
+        loop {
+            let event = events.poll().await.unwrap();
+            if let Event::Incoming(Packet::SubAck(_)) = event {
+                break;
+            }
+        }
+        let (received, mut messages) = mpsc::unbounded_channel();
+        actix_web::rt::spawn(async move {
+            while let Ok(event) = events.poll().await {
+                if let Event::Incoming(Packet::Publish(publish)) = event {
+                    let command: serde_json::Value =
+                        serde_json::from_slice(&publish.payload).unwrap();
+                    let _ = received.send(command["id"].as_i64().unwrap() as i32);
+                }
+            }
+        });
+
+        let (client, mut events) = AsyncClient::new(options, CAPACITY);
+        let bridge = bridge(client);
+        let (tracker, tracked) = (bridge.clone(), pool.clone());
+        actix_web::rt::spawn(async move {
+            while let Ok(event) = events.poll().await {
+                tracker.track(&tracked, &event);
+            }
+        });
+        bridge.deliver(&pool, &device).await;
+        // The commands are not published again while waiting for their acknowledgements
+        bridge.deliver(&pool, &device).await;
+        let mut published = Vec::new();
+        while published.len() < ids.len() {
+            let id = tokio::time::timeout(Duration::from_secs(5), messages.recv())
+                .await
+                .expect("Commands Not Published");
+            published.extend(id);
+        }
+        assert_eq!(published, ids);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !pending(&pool, &device).await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("Commands Not Acknowledged");
+        assert!(pending_and_remove(&pool, &device).await.is_empty());
+        assert!(messages.try_recv().is_err());
+    }
+}
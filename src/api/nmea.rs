@@ -0,0 +1,556 @@
+//! Parsing of the raw NMEA 0183 sentences of the gps receivers.
+//!
+//! The GGA, RMC and VTG sentences are combined into fixes: the sentences with the same time of
+//! day are of the same fix, and a VTG sentence, which has no time, belongs to the fix before
+//! it. The other sentence types are ignored.
+
+use time::{Date, OffsetDateTime, Time};
+
+use super::{gps::FixType, Coordinates};
+
+/// The number of metres per second in a knot.
+const METRES_PER_SECOND_PER_KNOT: f64 = 1852.0 / 3600.0;
+/// The number of metres per second in a kilometre per hour.
+const METRES_PER_SECOND_PER_KMH: f64 = 1000.0 / 3600.0;
+
+#[derive(Debug)]
+/// A fix combined from the sentences of a gps receiver.
+pub struct Fix {
+    /// The line of the first sentence of the fix, starting from 1.
+    pub line: usize,
+    /// The time of the fix.
+    pub time: OffsetDateTime,
+    /// The position of the fix.
+    pub location: Coordinates,
+    /// The type of the fix.
+    pub fix: Option<FixType>,
+    /// The number of satellites used for the fix.
+    pub satellites: Option<i32>,
+    /// The horizontal dilution of precision of the fix.
+    pub hdop: Option<f64>,
+    /// The speed over ground in metres per second.
+    pub speed: Option<f64>,
+    /// The course over ground in degrees clockwise from true north.
+    pub course: Option<f64>,
+}
+
+#[derive(Debug)]
+/// A sentence which could not be used.
+pub struct Malformed {
+    /// The line of the sentence, starting from 1.
+    pub line: usize,
+    /// The reason the sentence could not be used.
+    pub error: String,
+}
+
+#[derive(Debug, Default)]
+/// The fixes and problems of the sentences of a gps receiver.
+pub struct Parsed {
+    /// The fixes, in the order of the sentences.
+    pub fixes: Vec<Fix>,
+    /// The sentences which could not be used.
+    pub errors: Vec<Malformed>,
+    /// The number of sentences of types which are not used.
+    pub ignored: usize,
+}
+
+/// A sentence used for the fixes.
+enum Sentence {
+    /// A GGA sentence, with the position and quality of the fix.
+    Gga {
+        time: Time,
+        location: Option<Coordinates>,
+        fix: FixType,
+        satellites: Option<i32>,
+        hdop: Option<f64>,
+    },
+    /// An RMC sentence, with the position, date and motion of the fix.
+    Rmc {
+        time: Time,
+        date: Date,
+        location: Option<Coordinates>,
+        fix: Option<FixType>,
+        speed: Option<f64>,
+        course: Option<f64>,
+    },
+    /// A VTG sentence, with the motion of the fix.
+    Vtg {
+        speed: Option<f64>,
+        course: Option<f64>,
+    },
+    /// A sentence of a type which is not used.
+    Ignored,
+}
+
+/// A fix while its sentences are combined.
+struct Partial {
+    /// The line of the first sentence of the fix.
+    line: usize,
+    /// The time of day of the fix.
+    time: Time,
+    /// The date of the fix, if it has an RMC sentence.
+    date: Option<Date>,
+    /// The position of the fix.
+    location: Option<Coordinates>,
+    /// The type of the fix.
+    fix: Option<FixType>,
+    /// The number of satellites used for the fix.
+    satellites: Option<i32>,
+    /// The horizontal dilution of precision of the fix.
+    hdop: Option<f64>,
+    /// The speed over ground in metres per second.
+    speed: Option<f64>,
+    /// The course over ground in degrees clockwise from true north.
+    course: Option<f64>,
+}
+
+impl Partial {
+    /// Starts a fix from its first sentence.
+    fn new(line: usize, time: Time) -> Self {
+        Self {
+            line,
+            time,
+            date: None,
+            location: None,
+            fix: None,
+            satellites: None,
+            hdop: None,
+            speed: None,
+            course: None,
+        }
+    }
+}
+
+/// Combines fixes from the sentences, dating the fixes without an RMC sentence from the fix
+/// before them, or today if there is none.
+pub fn parse(text: &str) -> Parsed {
+    let mut parser = Parser {
+        parsed: Parsed::default(),
+        current: None,
+        date: None,
+        previous: None,
+    };
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match sentence(line) {
+            Ok(sentence) => parser.add(i + 1, sentence),
+            Err(error) => parser.parsed.errors.push(Malformed { line: i + 1, error }),
+        }
+    }
+    parser.finish();
+    parser.parsed
+}
+
+/// The state of the combination of the sentences into fixes.
+struct Parser {
+    /// The fixes and problems so far.
+    parsed: Parsed,
+    /// The fix of the latest time of day.
+    current: Option<Partial>,
+    /// The date of the last fix.
+    date: Option<Date>,
+    /// The time of day of the last fix.
+    previous: Option<Time>,
+}
+
+impl Parser {
+    /// Adds a sentence to the fix of its time of day.
+    fn add(&mut self, line: usize, sentence: Sentence) {
+        match sentence {
+            Sentence::Gga {
+                time,
+                location,
+                fix,
+                satellites,
+                hdop,
+            } => {
+                let current = self.at(line, time);
+                current.location = location.or(current.location.take());
+                // The quality of a GGA sentence is more specific than the mode of an RMC one
+                current.fix = Some(fix);
+                current.satellites = satellites;
+                current.hdop = hdop;
+            }
+            Sentence::Rmc {
+                time,
+                date,
+                location,
+                fix,
+                speed,
+                course,
+            } => {
+                let current = self.at(line, time);
+                current.date = Some(date);
+                current.location = current.location.take().or(location);
+                current.fix = current.fix.or(fix);
+                current.speed = speed.or(current.speed);
+                current.course = course.or(current.course);
+            }
+            Sentence::Vtg { speed, course } => match &mut self.current {
+                Some(current) => {
+                    current.speed = current.speed.or(speed);
+                    current.course = current.course.or(course);
+                }
+                None => self.parsed.errors.push(Malformed {
+                    line,
+                    error: "VTG Sentence Without A Fix".to_string(),
+                }),
+            },
+            Sentence::Ignored => self.parsed.ignored += 1,
+        }
+    }
+
+    /// The fix of a time of day, completing the current fix if it is of another time.
+    fn at(&mut self, line: usize, time: Time) -> &mut Partial {
+        if self.current.as_ref().is_some_and(|c| c.time != time) {
+            self.finish();
+        }
+        self.current.get_or_insert_with(|| Partial::new(line, time))
+    }
+
+    /// Completes the current fix, reporting it if it has no position.
+    fn finish(&mut self) {
+        let Some(current) = self.current.take() else {
+            return;
+        };
+        let date = match (current.date, self.date, self.previous) {
+            (Some(date), _, _) => date,
+            // The time of day going back is the day rolling over
+            (None, Some(date), Some(previous)) if current.time < previous => {
+                date.next_day().unwrap_or(date)
+            }
+            (None, Some(date), _) => date,
+            (None, None, _) => OffsetDateTime::now_utc().date(),
+        };
+        self.date = Some(date);
+        self.previous = Some(current.time);
+        let Some(location) = current.location else {
+            self.parsed.errors.push(Malformed {
+                line: current.line,
+                error: "Fix Has No Position".to_string(),
+            });
+            return;
+        };
+        self.parsed.fixes.push(Fix {
+            line: current.line,
+            time: date.with_time(current.time).assume_utc(),
+            location,
+            fix: current.fix,
+            satellites: current.satellites,
+            hdop: current.hdop,
+            speed: current.speed,
+            course: current.course,
+        });
+    }
+}
+
+/// Parses a sentence, checking its checksum.
+fn sentence(line: &str) -> Result<Sentence, String> {
+    let body = line
+        .strip_prefix('$')
+        .ok_or_else(|| "Sentence Does Not Start With $".to_string())?;
+    let (body, checksum) = body
+        .split_once('*')
+        .ok_or_else(|| "Sentence Has No Checksum".to_string())?;
+    let expected = u8::from_str_radix(checksum, 16)
+        .map_err(|_| format!("Checksum {checksum} Is Not Hexadecimal"))?;
+    let computed = body.bytes().fold(0, |sum, b| sum ^ b);
+    if expected != computed {
+        return Err(format!(
+            "Checksum Mismatch, Expected {expected:02X} But Computed {computed:02X}"
+        ));
+    }
+
+    let fields: Vec<&str> = body.split(',').collect();
+    let address = fields[0];
+    // Proprietary sentences have no talker
+    if address.starts_with('P') || address.len() != 5 || !address.is_ascii() {
+        return Ok(Sentence::Ignored);
+    }
+    let field = |i: usize| fields.get(i).copied().unwrap_or("");
+    match &address[2..] {
+        "GGA" => Ok(Sentence::Gga {
+            time: time_of_day(field(1))?,
+            location: position(field(2), field(3), field(4), field(5))?,
+            fix: quality(field(6))?,
+            satellites: optional(field(7), "Satellites")?,
+            hdop: optional(field(8), "HDOP")?,
+        }),
+        "RMC" => {
+            let fix = match (field(12), field(2)) {
+                ("", "A") => None,
+                ("", "V") => Some(FixType::None),
+                ("", status) => return Err(format!("Status {status} Is Not Valid")),
+                (mode, _) => Some(self::mode(mode)?),
+            };
+            Ok(Sentence::Rmc {
+                time: time_of_day(field(1))?,
+                location: position(field(3), field(4), field(5), field(6))?,
+                fix,
+                speed: optional::<f64>(field(7), "Speed")?.map(|s| s * METRES_PER_SECOND_PER_KNOT),
+                course: optional(field(8), "Course")?,
+                date: date(field(9))?,
+            })
+        }
+        "VTG" => {
+            let knots = optional::<f64>(field(5), "Speed")?;
+            let kmh = optional::<f64>(field(7), "Speed")?;
+            Ok(Sentence::Vtg {
+                speed: knots
+                    .map(|s| s * METRES_PER_SECOND_PER_KNOT)
+                    .or(kmh.map(|s| s * METRES_PER_SECOND_PER_KMH)),
+                course: optional(field(1), "Course")?,
+            })
+        }
+        _ => Ok(Sentence::Ignored),
+    }
+}
+
+/// Parses an optional numeric field.
+fn optional<T: std::str::FromStr>(field: &str, name: &str) -> Result<Option<T>, String> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    field
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("{name} {field} Is Not A Number"))
+}
+
+/// Parses a time of day in the `hhmmss.ss` format.
+fn time_of_day(field: &str) -> Result<Time, String> {
+    let invalid = || format!("Time {field} Is Not Valid");
+    // The fields are sliced by byte
+    if field.len() < 6 || !field.is_ascii() || !field[..4].bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let number = |s: &str| s.parse::<u8>().map_err(|_| invalid());
+    let seconds: f64 = field[4..].parse().map_err(|_| invalid())?;
+    if !(0.0..60.0).contains(&seconds) {
+        return Err(invalid());
+    }
+    Time::from_hms_milli(
+        number(&field[..2])?,
+        number(&field[2..4])?,
+        seconds.trunc() as u8,
+        (seconds.fract() * 1000.0).round().min(999.0) as u16,
+    )
+    .map_err(|_| invalid())
+}
+
+/// Parses a date in the `ddmmyy` format.
+fn date(field: &str) -> Result<Date, String> {
+    let invalid = || format!("Date {field} Is Not Valid");
+    if field.len() != 6 || !field.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let number = |s: &str| s.parse::<u8>().map_err(|_| invalid());
+    let month = time::Month::try_from(number(&field[2..4])?).map_err(|_| invalid())?;
+    Date::from_calendar_date(
+        2000 + i32::from(number(&field[4..])?),
+        month,
+        number(&field[..2])?,
+    )
+    .map_err(|_| invalid())
+}
+
+/// Parses a position from the latitude in `ddmm.mmmm` and the longitude in `dddmm.mmmm`, with
+/// their hemispheres, `None` if the fields are empty.
+fn position(
+    latitude: &str,
+    north: &str,
+    longitude: &str,
+    east: &str,
+) -> Result<Option<Coordinates>, String> {
+    if latitude.is_empty() && longitude.is_empty() {
+        return Ok(None);
+    }
+    let latitude = match north {
+        "N" => angle(latitude, 90.0)?,
+        "S" => -angle(latitude, 90.0)?,
+        _ => return Err(format!("Hemisphere {north} Is Not N Or S")),
+    };
+    let longitude = match east {
+        "E" => angle(longitude, 180.0)?,
+        "W" => -angle(longitude, 180.0)?,
+        _ => return Err(format!("Hemisphere {east} Is Not E Or W")),
+    };
+    Ok(Some(Coordinates {
+        latitude,
+        longitude,
+    }))
+}
+
+/// Parses an angle in degrees and minutes to degrees.
+fn angle(field: &str, max: f64) -> Result<f64, String> {
+    let invalid = || format!("Coordinate {field} Is Not Valid");
+    let value: f64 = field.parse().map_err(|_| invalid())?;
+    let degrees = (value / 100.0).trunc();
+    let minutes = value - degrees * 100.0;
+    let angle = degrees + minutes / 60.0;
+    if !angle.is_finite() || value < 0.0 || minutes >= 60.0 || angle > max {
+        return Err(invalid());
+    }
+    Ok(angle)
+}
+
+/// Parses the fix quality of a GGA sentence.
+fn quality(field: &str) -> Result<FixType, String> {
+    field
+        .parse()
+        .ok()
+        .and_then(FixType::from_quality)
+        .ok_or_else(|| format!("Fix Quality {field} Is Not Valid"))
+}
+
+/// Parses the mode indicator of an RMC sentence.
+fn mode(field: &str) -> Result<FixType, String> {
+    Ok(match field {
+        "N" => FixType::None,
+        "A" => FixType::Gps,
+        "D" => FixType::Dgps,
+        "P" => FixType::Pps,
+        "R" => FixType::Rtk,
+        "F" => FixType::FloatRtk,
+        "E" => FixType::Estimated,
+        "M" => FixType::Manual,
+        "S" => FixType::Simulation,
+        _ => return Err(format!("Mode {field} Is Not Valid")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::{date, time};
+
+    use super::{angle, parse, sentence, time_of_day, Sentence};
+    use crate::api::gps::FixType;
+
+    /// Completes the body of a sentence with its checksum.
+    fn checksummed(body: &str) -> String {
+        let checksum = body.bytes().fold(0, |sum, b| sum ^ b);
+        format!("${body}*{checksum:02X}")
+    }
+
+    #[test]
+    fn sentence_checks_the_checksum() {
+        let line = checksummed("GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,");
+        assert!(sentence(&line).is_ok());
+        let corrupted = line.replace("4807", "4808");
+        assert!(sentence(&corrupted)
+            .err()
+            .is_some_and(|e| e.starts_with("Checksum Mismatch")));
+        assert!(sentence("$GPGGA,123519*ZZ")
+            .err()
+            .is_some_and(|e| e.contains("Not Hexadecimal")));
+        assert!(sentence("$GPGGA,123519").is_err());
+        assert!(sentence(&line[1..]).is_err());
+    }
+
+    #[test]
+    fn sentence_parses_a_gga() {
+        let line = checksummed("GPGGA,123519,4807.038,S,01131.000,W,2,08,0.9,545.4,M,46.9,M,,");
+        let Ok(Sentence::Gga {
+            time,
+            location,
+            fix,
+            satellites,
+            hdop,
+        }) = sentence(&line)
+        else {
+            panic!("GGA Sentence Not Parsed");
+        };
+        assert_eq!(time, time!(12:35:19));
+        let location = location.expect("GGA Sentence Has No Position");
+        assert!((location.latitude + 48.1173).abs() < 1e-9);
+        assert!((location.longitude + 11.516_666_666).abs() < 1e-9);
+        assert_eq!(fix, FixType::Dgps);
+        assert_eq!(satellites, Some(8));
+        assert_eq!(hdop, Some(0.9));
+    }
+
+    #[test]
+    fn sentence_ignores_other_types() {
+        for body in ["PGRME,15.0,M,45.0,M,25.0,M", "GPGSV,3,1,11", "GPGG"] {
+            assert!(matches!(
+                sentence(&checksummed(body)),
+                Ok(Sentence::Ignored)
+            ));
+        }
+    }
+
+    #[test]
+    fn time_of_day_parses_the_fraction_of_seconds() {
+        assert_eq!(time_of_day("123519"), Ok(time!(12:35:19)));
+        assert_eq!(time_of_day("000000.25"), Ok(time!(00:00:00.25)));
+        assert_eq!(time_of_day("235959.9999"), Ok(time!(23:59:59.999)));
+    }
+
+    #[test]
+    fn time_of_day_rejects_invalid_times() {
+        for field in [
+            "", "12351", "1é3456", "123é56", "+12345", "243519", "126019", "123560", "1235a9",
+        ] {
+            assert!(time_of_day(field).is_err(), "{field} Accepted");
+        }
+    }
+
+    #[test]
+    fn angle_converts_the_minutes() {
+        assert_eq!(angle("4807.5", 90.0), Ok(48.125));
+        assert_eq!(angle("00030", 180.0), Ok(0.5));
+        assert_eq!(angle("9000", 90.0), Ok(90.0));
+    }
+
+    #[test]
+    fn angle_rejects_invalid_angles() {
+        for (field, max) in [
+            ("", 90.0),
+            ("north", 90.0),
+            ("-4807.5", 90.0),
+            ("4860", 90.0),
+            ("9001", 90.0),
+            ("18100", 180.0),
+            ("NaN", 90.0),
+        ] {
+            assert!(angle(field, max).is_err(), "{field} Accepted");
+        }
+    }
+
+    #[test]
+    fn parse_combines_the_sentences_of_a_fix() {
+        let text = [
+            checksummed("GPRMC,235959,A,4807.038,N,01131.000,E,2.0,84.4,230324,,"),
+            checksummed("GPGGA,235959,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,"),
+            checksummed("GPVTG,90.0,T,,M,,N,,K"),
+            checksummed("GPGGA,000001,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,"),
+            "garbage".to_string(),
+        ]
+        .join("\n");
+        let parsed = parse(&text);
+        assert_eq!(parsed.fixes.len(), 2);
+        assert_eq!(parsed.errors.len(), 1);
+        assert_eq!(parsed.errors[0].line, 5);
+        let (first, second) = (&parsed.fixes[0], &parsed.fixes[1]);
+        assert_eq!(first.line, 1);
+        assert_eq!(
+            first.time,
+            date!(2024 - 03 - 23)
+                .with_time(time!(23:59:59))
+                .assume_utc()
+        );
+        assert_eq!(first.fix, Some(FixType::Gps));
+        assert_eq!(first.course, Some(84.4));
+        // The time of day going back is the next day
+        assert_eq!(
+            second.time,
+            date!(2024 - 03 - 24)
+                .with_time(time!(00:00:01))
+                .assume_utc()
+        );
+        assert_eq!(second.speed, None);
+    }
+}
@@ -1,16 +1,19 @@
 //! Module for Actix services for all the paths.
 
 use actix_web::{
-    error::ErrorBadRequest,
     get, post,
     web::{self, Data, Json, Path, ServiceConfig},
-    Responder, Result,
+    Responder,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::{FromRow, PgPool};
+use utoipa::{OpenApi, ToSchema};
 use uuid::Uuid;
 
-use crate::AppState;
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
 
 use super::Coordinates;
 
@@ -20,11 +23,30 @@ pub fn paths_cfg(cfg: &mut ServiceConfig) {
         web::scope("/paths")
             .service(get_paths)
             .service(register_path)
-            .service(get_path),
+            .service(get_path)
+            .service(set_geofence),
     );
 }
 
-#[derive(Serialize, FromRow)]
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_paths, get_path, register_path, set_geofence),
+    components(schemas(PathValues, PathDataCoords, PathResponse, PathInput, GeofenceInput))
+)]
+/// The OpenAPI specification of the paths API resources.
+pub struct PathsApi;
+
+/// Checks whether a path exists.
+pub async fn path_exists(pool: &PgPool, uuid: Uuid) -> Result<bool> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM paths WHERE uuid = $1) AS "exists!""#,
+        uuid
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
 /// The data format for trips data.
 struct PathValues {
     /// The name of the path.
@@ -33,13 +55,17 @@ struct PathValues {
     uuid: Uuid,
 }
 
+#[utoipa::path(
+    context_path = "/api/paths",
+    tag = "paths",
+    responses((status = 200, description = "All the paths.", body = [PathValues]))
+)]
 #[get("")]
 /// Gets all the trips data.
 async fn get_paths(state: Data<AppState>) -> Result<impl Responder> {
     let paths = sqlx::query_as!(PathValues, "SELECT name, uuid FROM paths")
         .fetch_all(&state.pool)
-        .await
-        .map_err(|e| ErrorBadRequest(e.to_string()))?;
+        .await?;
     Ok(Json(paths))
 }
 
@@ -50,15 +76,27 @@ struct PathData {
     name: String,
     /// The the points on the path.
     path: serde_json::Value,
+    /// The width of the corridor around the path in metres.
+    corridor: f64,
+    /// The points of the operating area.
+    area: Option<serde_json::Value>,
+    /// Whether the devices are commanded to return home when leaving the path or area.
+    return_home: bool,
 }
 
-#[derive(Serialize, FromRow, Debug)]
+#[derive(Serialize, FromRow, ToSchema, Debug)]
 /// The data format for trip data.
 struct PathDataCoords {
     /// The name of the path.
     name: String,
     /// The the points on the path.
     path: Vec<Coordinates>,
+    /// The distance from the path in metres beyond which the devices are off the path.
+    corridor: f64,
+    /// The polygon the devices must stay within, `null` if the area is not restricted.
+    area: Option<Vec<Coordinates>>,
+    /// Whether the devices are commanded to return home when leaving the path or area.
+    return_home: bool,
 }
 
 impl TryFrom<PathData> for PathDataCoords {
@@ -68,26 +106,40 @@ impl TryFrom<PathData> for PathDataCoords {
         Ok(Self {
             name: value.name,
             path: serde_json::from_value(value.path)?,
+            corridor: value.corridor,
+            area: value.area.map(serde_json::from_value).transpose()?,
+            return_home: value.return_home,
         })
     }
 }
 
-#[get("{uuid}")]
+#[utoipa::path(
+    context_path = "/api/paths",
+    tag = "paths",
+    params(("uuid" = Uuid, Path, description = "The UUID of the path.")),
+    responses(
+        (status = 200, description = "The path.", body = PathDataCoords),
+        (status = 404, description = "The path does not exist.", body = ErrorBody),
+    )
+)]
+#[get("/{uuid}")]
 /// Gets the list of coordinates for a path.
 async fn get_path(uuid: Path<Uuid>, state: Data<AppState>) -> Result<impl Responder> {
     let paths = sqlx::query_as!(
         PathData,
-        "SELECT name, path FROM paths WHERE uuid = $1",
+        "SELECT name, array_to_json(path) AS \"path!\", corridor, array_to_json(area) AS area,
+ return_home
+FROM paths WHERE uuid = $1",
         *uuid
     )
-    .fetch_one(&state.pool)
-    .await
-    .map_err(|e| ErrorBadRequest(e.to_string()))?;
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Path {}", *uuid)))?;
     let paths = PathDataCoords::try_from(paths)?;
     Ok(Json(paths))
 }
 
-#[derive(Serialize, FromRow)]
+#[derive(Serialize, FromRow, ToSchema)]
 /// The reponse message for starting a new trip.
 struct PathResponse {
     #[serde(rename = "uuid")]
@@ -95,28 +147,126 @@ struct PathResponse {
     uuid: Uuid,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 /// The input data format for inserting path.
 struct PathInput {
     /// The name of the path.
     name: String,
     /// The list of coordinates to follow.
     path: Vec<Coordinates>,
+    #[serde(flatten)]
+    /// The enforcement of the path.
+    geofence: GeofenceInput,
+}
+
+#[derive(Deserialize, ToSchema)]
+/// The enforcement of a path.
+struct GeofenceInput {
+    #[serde(default = "GeofenceInput::corridor_default")]
+    /// The distance from the path in metres beyond which the devices are off the path.
+    corridor: f64,
+    /// The polygon the devices must stay within, not restricted if not given.
+    area: Option<Vec<Coordinates>>,
+    #[serde(default)]
+    /// Whether the devices are commanded to return home when leaving the path or area.
+    return_home: bool,
+}
+
+impl GeofenceInput {
+    /// Defaults the corridor to 20 metres.
+    fn corridor_default() -> f64 {
+        20.0
+    }
+
+    /// Checks that the corridor is positive and the area is a polygon.
+    fn validate(&self) -> Result<()> {
+        if !self.corridor.is_finite() || self.corridor <= 0.0 {
+            return Err(ApiError::BadRequest(
+                "Corridor Must Be Positive".to_string(),
+            ));
+        }
+        if self.area.as_ref().is_some_and(|area| area.len() < 3) {
+            return Err(ApiError::BadRequest(
+                "Area Must Have At Least 3 Points".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The points of the area as stored in the database.
+    fn area(&self) -> Option<Vec<serde_json::Value>> {
+        self.area
+            .as_ref()
+            .map(|area| area.iter().map(|v| serde_json::json!(v)).collect())
+    }
 }
 
+#[utoipa::path(
+    context_path = "/api/paths",
+    tag = "paths",
+    request_body = PathInput,
+    responses(
+        (status = 200, description = "The path is registered.", body = PathResponse),
+        (status = 400, description = "The body is not valid.", body = ErrorBody),
+    )
+)]
 #[post("")]
 /// Register a new path.
 async fn register_path(path: Json<PathInput>, state: Data<AppState>) -> Result<impl Responder> {
+    path.geofence.validate()?;
+    let area = path.geofence.area();
     let paths: Vec<serde_json::Value> = path.path.iter().map(|v| serde_json::json!(v)).collect();
     let path_id = sqlx::query_as!(
         PathResponse,
-        "INSERT INTO paths (name, path, uuid) VALUES ($1, $2, $3) RETURNING uuid",
+        "INSERT INTO paths (name, path, uuid, corridor, area, return_home)
+VALUES ($1, $2, $3, $4, $5, $6) RETURNING uuid",
         path.name,
         &paths,
-        Uuid::new_v4()
+        Uuid::new_v4(),
+        path.geofence.corridor,
+        area.as_deref(),
+        path.geofence.return_home
     )
     .fetch_one(&state.pool)
-    .await
-    .map_err(|e| ErrorBadRequest(e.to_string()))?;
+    .await?;
+    state.metrics.inserted("paths", 1);
     Ok(Json(path_id))
 }
+
+#[utoipa::path(
+    context_path = "/api/paths",
+    tag = "paths",
+    params(("uuid" = Uuid, Path, description = "The UUID of the path.")),
+    request_body = GeofenceInput,
+    responses(
+        (status = 200, description = "The enforcement of the path is updated."),
+        (status = 400, description = "The body is not valid.", body = ErrorBody),
+        (status = 404, description = "The path does not exist.", body = ErrorBody),
+    )
+)]
+#[post("/{uuid}/geofence")]
+/// Sets the corridor, operating area and return home behaviour of a path.
+///
+/// Only the fixes received afterwards are checked against the new settings.
+async fn set_geofence(
+    uuid: Path<Uuid>,
+    geofence: Json<GeofenceInput>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    geofence.validate()?;
+    let area = geofence.area();
+    let updated = sqlx::query!(
+        "UPDATE paths SET corridor = $2, area = $3, return_home = $4 WHERE uuid = $1",
+        *uuid,
+        geofence.corridor,
+        area.as_deref(),
+        geofence.return_home
+    )
+    .execute(&state.pool)
+    .await?
+    .rows_affected();
+    if updated == 0 {
+        return Err(ApiError::NotFound(format!("Path {}", *uuid)));
+    }
+    Ok("")
+}
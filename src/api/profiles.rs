@@ -0,0 +1,342 @@
+//! Module for Actix services for the vertical temperature profiles.
+
+use actix_web::{
+    get,
+    web::{scope, Data, Json, Query, ServiceConfig},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::{
+    selection::{Sample, Selection},
+    Coordinates,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_profiles),
+    components(schemas(Station, ProfilePoint, Thermocline))
+)]
+/// The OpenAPI specification of the profiles API resources.
+pub struct ProfilesApi;
+
+/// Configuration function for the profiles API resources.
+pub fn profiles_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(scope("/profiles").service(get_profiles));
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+/// The query specification for the profiles.
+struct ProfileQuery {
+    #[serde(default = "ProfileQuery::radius_default")]
+    /// The distance in metres within which readings belong to the same station.
+    radius: f64,
+    #[serde(default = "ProfileQuery::mixed_layer_default")]
+    /// The difference from the shallowest temperature in °C marking the bottom of the mixed
+    /// layer.
+    mixed_layer_threshold: f64,
+    #[serde(default = "ProfileQuery::thermocline_default")]
+    /// The minimum magnitude of the temperature gradient in °C/m for a thermocline.
+    thermocline_gradient: f64,
+}
+
+impl ProfileQuery {
+    fn radius_default() -> f64 {
+        25.0
+    }
+
+    fn mixed_layer_default() -> f64 {
+        0.2
+    }
+
+    fn thermocline_default() -> f64 {
+        0.05
+    }
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone, Copy)]
+/// The mean temperature at a depth.
+struct ProfilePoint {
+    /// The depth in metres.
+    depth: f64,
+    /// The mean temperature at the depth.
+    temperature: f64,
+    /// The number of readings at the depth.
+    count: usize,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+/// The layer with the steepest temperature gradient.
+struct Thermocline {
+    /// The depth of the middle of the layer in metres.
+    depth: f64,
+    /// The temperature gradient in °C/m, negative when the temperature falls with depth.
+    gradient: f64,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+/// The vertical profile of a sampling station.
+struct Station {
+    /// The mean location of the readings of the station.
+    location: Coordinates,
+    #[serde(with = "time::serde::rfc3339")]
+    /// The time of the first reading of the station.
+    start: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    /// The time of the last reading of the station.
+    end: OffsetDateTime,
+    /// The temperatures from the shallowest to the deepest.
+    profile: Vec<ProfilePoint>,
+    /// The depth of the bottom of the mixed layer, `null` if the whole profile is mixed.
+    mixed_layer_depth: Option<f64>,
+    /// The thermocline, `null` if no gradient is steep enough.
+    thermocline: Option<Thermocline>,
+}
+
+impl Station {
+    /// Computes the profile of the readings of a station.
+    fn new(samples: &[&Sample], query: &ProfileQuery) -> Self {
+        let count = samples.len() as f64;
+        let location = Coordinates {
+            latitude: samples.iter().map(|s| s.location.latitude).sum::<f64>() / count,
+            longitude: samples.iter().map(|s| s.location.longitude).sum::<f64>() / count,
+        };
+
+        let mut sorted: Vec<_> = samples.iter().map(|s| (s.depth, s.temperature)).collect();
+        sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut profile: Vec<ProfilePoint> = Vec::new();
+        for (depth, temperature) in sorted {
+            match profile.last_mut() {
+                Some(point) if point.depth == depth => {
+                    let n = point.count as f64;
+                    point.temperature = (point.temperature * n + temperature) / (n + 1.0);
+                    point.count += 1;
+                }
+                _ => profile.push(ProfilePoint {
+                    depth,
+                    temperature,
+                    count: 1,
+                }),
+            }
+        }
+
+        Self {
+            location,
+            start: samples
+                .iter()
+                .map(|s| s.time)
+                .min()
+                .expect("Stations are not empty"),
+            end: samples
+                .iter()
+                .map(|s| s.time)
+                .max()
+                .expect("Stations are not empty"),
+            mixed_layer_depth: mixed_layer_depth(&profile, query.mixed_layer_threshold),
+            thermocline: thermocline(&profile, query.thermocline_gradient),
+            profile,
+        }
+    }
+}
+
+/// Finds the depth where the temperature first differs from the shallowest temperature by
+/// the threshold, interpolating between the readings.
+fn mixed_layer_depth(profile: &[ProfilePoint], threshold: f64) -> Option<f64> {
+    let reference = profile.first()?.temperature;
+    profile.windows(2).find_map(|pair| {
+        let (upper, lower) = (pair[0], pair[1]);
+        let difference = (lower.temperature - reference).abs();
+        (difference >= threshold).then(|| {
+            let above = (upper.temperature - reference).abs();
+            let fraction = (threshold - above) / (difference - above);
+            upper.depth + fraction * (lower.depth - upper.depth)
+        })
+    })
+}
+
+/// Finds the steepest temperature gradient between consecutive depths.
+fn thermocline(profile: &[ProfilePoint], minimum: f64) -> Option<Thermocline> {
+    profile
+        .windows(2)
+        .map(|pair| Thermocline {
+            depth: (pair[0].depth + pair[1].depth) / 2.0,
+            gradient: (pair[1].temperature - pair[0].temperature) / (pair[1].depth - pair[0].depth),
+        })
+        .filter(|t| t.gradient.abs() >= minimum)
+        .max_by(|a, b| a.gradient.abs().total_cmp(&b.gradient.abs()))
+}
+
+/// Groups the readings into stations of readings within a radius of the first reading.
+fn cluster(samples: &[Sample], radius: f64) -> Vec<Vec<&Sample>> {
+    let mut stations: Vec<Vec<&Sample>> = Vec::new();
+    for sample in samples {
+        match stations
+            .iter_mut()
+            .find(|station| station[0].location.distance(&sample.location) <= radius)
+        {
+            Some(station) => station.push(sample),
+            None => stations.push(vec![sample]),
+        }
+    }
+    stations
+}
+
+#[utoipa::path(
+    context_path = "/api/profiles",
+    tag = "profiles",
+    params(Selection, ProfileQuery),
+    responses(
+        (status = 200, description = "The profiles of the stations, in the order they are sampled.", body = [Station]),
+        (status = 400, description = "The parameters are not valid.", body = ErrorBody),
+        (status = 404, description = "The trip or path does not exist.", body = ErrorBody),
+    )
+)]
+#[get("")]
+/// Gets the vertical temperature profiles of the sampling stations.
+///
+/// The readings are grouped into stations by location, and the mixed layer and thermocline
+/// are detected from the temperatures against depth at each station.
+async fn get_profiles(
+    selection: Query<Selection>,
+    query: Query<ProfileQuery>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    let valid = |value: f64| value.is_finite() && value > 0.0;
+    if !valid(query.radius)
+        || !valid(query.mixed_layer_threshold)
+        || !valid(query.thermocline_gradient)
+    {
+        return Err(ApiError::BadRequest(
+            "Radius And Thresholds Must Be Positive".to_string(),
+        ));
+    }
+    let samples = selection.samples(&state, None).await?;
+    let stations: Vec<Station> = cluster(&samples, query.radius)
+        .iter()
+        .map(|station| Station::new(station, &query))
+        .collect();
+    Ok(Json(stations))
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::{cluster, mixed_layer_depth, thermocline, ProfilePoint, ProfileQuery, Station};
+    use crate::api::{geofence::METRES_PER_DEGREE, selection::Sample, Coordinates};
+
+    /// Makes a profile of temperatures at depths.
+    fn profile(points: &[(f64, f64)]) -> Vec<ProfilePoint> {
+        points
+            .iter()
+            .map(|&(depth, temperature)| ProfilePoint {
+                depth,
+                temperature,
+                count: 1,
+            })
+            .collect()
+    }
+
+    /// A profile mixed down to 5 m, with the steepest gradient between 6 m and 7 m.
+    fn stratified() -> Vec<ProfilePoint> {
+        profile(&[
+            (0.0, 28.0),
+            (2.5, 28.05),
+            (5.0, 28.0),
+            (6.0, 27.5),
+            (7.0, 26.0),
+            (8.0, 25.5),
+            (10.0, 25.4),
+        ])
+    }
+
+    /// Makes a sample some metres north of the origin.
+    fn sample(north: f64, depth: f64, temperature: f64) -> Sample {
+        Sample {
+            time: OffsetDateTime::UNIX_EPOCH,
+            location: Coordinates {
+                latitude: north / METRES_PER_DEGREE,
+                longitude: 0.0,
+            },
+            depth,
+            temperature,
+        }
+    }
+
+    #[test]
+    fn mixed_layer_depth_is_interpolated() {
+        // 0.2 °C below the surface is 0.4 of the way from 5 m to 6 m
+        let depth = mixed_layer_depth(&stratified(), 0.2).unwrap();
+        assert!((depth - 5.4).abs() < 1e-9, "{depth}");
+        let depth = mixed_layer_depth(&stratified(), 1.25).unwrap();
+        assert!((depth - 6.5).abs() < 1e-9, "{depth}");
+        assert_eq!(mixed_layer_depth(&stratified(), 5.0), None);
+        assert_eq!(mixed_layer_depth(&profile(&[(0.0, 20.0)]), 0.2), None);
+        assert_eq!(mixed_layer_depth(&[], 0.2), None);
+    }
+
+    #[test]
+    fn thermocline_is_the_steepest_gradient() {
+        let thermocline = thermocline(&stratified(), 0.05).unwrap();
+        assert_eq!(thermocline.depth, 6.5);
+        assert_eq!(thermocline.gradient, -1.5);
+        let warming = profile(&[(0.0, 20.0), (1.0, 20.0), (3.0, 21.0)]);
+        let inversion = super::thermocline(&warming, 0.05).unwrap();
+        assert_eq!((inversion.depth, inversion.gradient), (2.0, 0.5));
+        assert!(super::thermocline(&stratified(), 2.0).is_none());
+        assert!(super::thermocline(&profile(&[(0.0, 20.0)]), 0.0).is_none());
+    }
+
+    #[test]
+    fn cluster_groups_the_readings_near_the_first_of_a_station() {
+        let samples = [
+            sample(0.0, 0.0, 28.0),
+            sample(10.0, 1.0, 27.0),
+            sample(30.0, 0.0, 28.0),
+            sample(50.0, 1.0, 27.0),
+            sample(-20.0, 2.0, 26.0),
+        ];
+        let depths: Vec<Vec<f64>> = cluster(&samples, 25.0)
+            .iter()
+            .map(|station| station.iter().map(|s| s.depth).collect())
+            .collect();
+        assert_eq!(depths, [vec![0.0, 1.0, 2.0], vec![0.0, 1.0]]);
+    }
+
+    #[test]
+    fn station_averages_the_readings_at_a_depth() {
+        let samples = [
+            sample(0.0, 6.0, 27.0),
+            sample(1.0, 0.0, 27.9),
+            sample(2.0, 0.0, 28.1),
+            sample(3.0, 6.0, 26.0),
+        ];
+        let query = ProfileQuery {
+            radius: ProfileQuery::radius_default(),
+            mixed_layer_threshold: ProfileQuery::mixed_layer_default(),
+            thermocline_gradient: ProfileQuery::thermocline_default(),
+        };
+        let station = Station::new(&samples.iter().collect::<Vec<_>>(), &query);
+        let points: Vec<_> = station
+            .profile
+            .iter()
+            .map(|p| (p.depth, p.temperature, p.count))
+            .collect();
+        assert_eq!(points.len(), 2);
+        assert_eq!((points[0].0, points[0].2), (0.0, 2));
+        assert!((points[0].1 - 28.0).abs() < 1e-9);
+        assert_eq!(points[1], (6.0, 26.5, 2));
+        assert!((station.location.latitude * METRES_PER_DEGREE - 1.5).abs() < 1e-6);
+        assert!(station
+            .mixed_layer_depth
+            .is_some_and(|d| (d - 0.8).abs() < 1e-9));
+    }
+}
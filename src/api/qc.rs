@@ -0,0 +1,650 @@
+//! Module for the automated quality control of the collected data.
+//!
+//! The flags follow the QARTOD scheme: each test flags a reading as passing, not evaluated,
+//! suspect or failing, and the worst evaluated flag is the flag of the reading.
+//!
+//! The tests comparing a reading with its neighbours run on the series of each device in each
+//! layer, as the temperatures of the layers differ too much to be compared with each other.
+
+use std::collections::{BTreeMap, HashMap};
+
+use actix_web::{
+    post,
+    web::{scope, Data, Json, Path, ServiceConfig},
+    Responder,
+};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use time::OffsetDateTime;
+use utoipa::{OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::{
+    data::Layer,
+    quantities::TEMPERATURE,
+    sensors::{is_installed, Calibrations},
+    trips::trip_exists,
+    Coordinates,
+};
+
+/// The flag of a test which passed.
+pub const PASS: i16 = 1;
+/// The flag of a test which could not be evaluated.
+pub const NOT_EVALUATED: i16 = 2;
+/// The flag of a test which found the reading suspect.
+pub const SUSPECT: i16 = 3;
+/// The flag of a test which found the reading bad.
+pub const FAIL: i16 = 4;
+
+/// The number of identical consecutive values after which a sensor is suspected to be stuck.
+const STUCK_COUNT: usize = 5;
+/// The maximum speed of the robot in metres per second, faster movements are GPS jumps.
+const MAX_SPEED: f64 = 5.0;
+
+#[derive(OpenApi)]
+#[openapi(paths(run_qc), components(schemas(QcSummary)))]
+/// The OpenAPI specification of the quality control API resources.
+pub struct QcApi;
+
+/// Configuration function for the quality control API resources.
+pub fn qc_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(scope("/qc").service(run_qc));
+}
+
+/// Combines the flags of all the tests into the flag of the reading.
+///
+/// The `qc_flag` function of the schema combines the flags the same way in the queries.
+pub fn aggregate(flags: &BTreeMap<String, i16>) -> i16 {
+    flags
+        .values()
+        .copied()
+        .filter(|&flag| flag != NOT_EVALUATED)
+        .max()
+        .unwrap_or(NOT_EVALUATED)
+}
+
+#[derive(FromRow, Debug)]
+/// A reading as stored in the database.
+struct ReadingRow {
+    /// The ID of the reading.
+    id: i32,
+    /// The device which measured the reading.
+    device: Option<String>,
+    /// The time the reading is measured.
+    time: OffsetDateTime,
+    /// The temperature measured.
+    temperature: f64,
+    /// The depth the reading is measured.
+    depth: f64,
+    /// The layer the reading is measured in.
+    layer: Layer,
+    /// The location the reading is measured.
+    location: serde_json::Value,
+    /// The other quantities measured, keyed by the name of the quantity.
+    measurements: serde_json::Value,
+}
+
+/// A reading prepared for the quality control tests.
+struct Reading {
+    /// The ID of the reading.
+    id: i32,
+    /// The time the reading is measured.
+    time: OffsetDateTime,
+    /// The depth the reading is measured.
+    depth: f64,
+    /// Whether the reading is measured at the sea bed.
+    sea_bed: bool,
+    /// The location the reading is measured.
+    location: Coordinates,
+    /// All the calibrated quantities measured, including the temperature.
+    values: BTreeMap<String, f64>,
+}
+
+/// The thresholds of a quantity.
+#[derive(Default, Clone, Copy)]
+struct Thresholds {
+    /// The range of values expected in the water bodies.
+    expected: (Option<f64>, Option<f64>),
+    /// The range of values the sensor can measure.
+    sensor: (Option<f64>, Option<f64>),
+    /// The deviation above which a value is a spike.
+    spike: Option<f64>,
+}
+
+/// The range of a sensor during one of its installations.
+struct SensorRange {
+    /// The time the sensor is installed from.
+    from: OffsetDateTime,
+    /// The time the sensor is removed, if it is.
+    to: Option<OffsetDateTime>,
+    /// The range of values the sensor can measure.
+    range: (Option<f64>, Option<f64>),
+}
+
+/// The thresholds of all the quantities of the sensors on the devices.
+struct Limits {
+    /// The thresholds of the quantities in the water bodies, keyed by quantity.
+    quantities: HashMap<String, Thresholds>,
+    /// The ranges of the installed sensors, keyed by device and quantity.
+    sensors: HashMap<(String, String), Vec<SensorRange>>,
+}
+
+impl Limits {
+    /// Loads the thresholds from the registered quantities and sensors.
+    async fn load(pool: &PgPool) -> Result<Self> {
+        let quantities = sqlx::query!("SELECT name, minimum, maximum, spike FROM quantities")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|row| {
+                let thresholds = Thresholds {
+                    expected: (row.minimum, row.maximum),
+                    spike: row.spike,
+                    ..Default::default()
+                };
+                (row.name, thresholds)
+            })
+            .collect();
+        let rows = sqlx::query!(
+            "SELECT installations.device, sensors.quantity, installations.installed_from,
+ installations.installed_to, sensors.minimum, sensors.maximum
+FROM installations
+JOIN sensors ON sensors.serial = installations.sensor"
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut sensors: HashMap<_, Vec<_>> = HashMap::new();
+        for row in rows {
+            sensors
+                .entry((row.device, row.quantity))
+                .or_default()
+                .push(SensorRange {
+                    from: row.installed_from,
+                    to: row.installed_to,
+                    range: (row.minimum, row.maximum),
+                });
+        }
+        Ok(Self {
+            quantities,
+            sensors,
+        })
+    }
+
+    /// The thresholds of a quantity measured by a device at a time, with the range of the
+    /// sensor installed on the device at the time.
+    fn get(&self, device: Option<&str>, quantity: &str, time: OffsetDateTime) -> Thresholds {
+        let mut thresholds = self.quantities.get(quantity).copied().unwrap_or_default();
+        if let Some(sensor) = device
+            .and_then(|d| self.sensors.get(&(d.to_string(), quantity.to_string())))
+            .and_then(|sensors| sensors.iter().find(|s| is_installed(s.from, s.to, time)))
+        {
+            thresholds.sensor = sensor.range;
+        }
+        thresholds
+    }
+}
+
+/// Checks whether a value is outside a range.
+fn outside(value: f64, (minimum, maximum): (Option<f64>, Option<f64>)) -> bool {
+    minimum.is_some_and(|m| value < m) || maximum.is_some_and(|m| value > m)
+}
+
+/// Flags a value outside the range of the sensor as bad and outside the expected range as suspect.
+fn range_test(value: f64, thresholds: Thresholds) -> i16 {
+    if thresholds.sensor == (None, None) && thresholds.expected == (None, None) {
+        NOT_EVALUATED
+    } else if outside(value, thresholds.sensor) {
+        FAIL
+    } else if outside(value, thresholds.expected) {
+        SUSPECT
+    } else {
+        PASS
+    }
+}
+
+/// Flags a value deviating from both of its neighbours as a spike.
+fn spike_test(previous: Option<f64>, value: f64, next: Option<f64>, threshold: Option<f64>) -> i16 {
+    let (Some(previous), Some(next), Some(threshold)) = (previous, next, threshold) else {
+        return NOT_EVALUATED;
+    };
+    let spike = (value - (previous + next) / 2.0).abs() - (next - previous).abs() / 2.0;
+    if spike > 2.0 * threshold {
+        FAIL
+    } else if spike > threshold {
+        SUSPECT
+    } else {
+        PASS
+    }
+}
+
+/// Flags a value identical to the previous values as coming from a stuck sensor.
+fn stuck_test(previous: &[Option<f64>], value: f64) -> i16 {
+    if previous.len() < STUCK_COUNT - 1 {
+        NOT_EVALUATED
+    } else if previous.iter().all(|&v| v == Some(value)) {
+        SUSPECT
+    } else {
+        PASS
+    }
+}
+
+/// Flags impossible depths.
+fn depth_test(reading: &Reading) -> i16 {
+    if reading.depth < 0.0 || (reading.sea_bed && reading.depth == 0.0) {
+        FAIL
+    } else {
+        PASS
+    }
+}
+
+/// Flags invalid coordinates and movements faster than the robot can travel.
+fn location_test(previous: Option<&Reading>, reading: &Reading) -> i16 {
+    let location = &reading.location;
+    if !(-90.0..=90.0).contains(&location.latitude)
+        || !(-180.0..=180.0).contains(&location.longitude)
+    {
+        return FAIL;
+    }
+    let Some(previous) = previous else {
+        return NOT_EVALUATED;
+    };
+    let metres = previous.location.distance(location);
+    let seconds = (reading.time - previous.time).as_seconds_f64();
+    if metres > MAX_SPEED * seconds.max(1.0) {
+        FAIL
+    } else {
+        PASS
+    }
+}
+
+/// Runs all the tests on the consecutive readings of a device in a layer, oldest first.
+fn evaluate(
+    device: Option<&str>,
+    readings: &[Reading],
+    limits: &Limits,
+) -> Vec<(i32, BTreeMap<String, i16>)> {
+    readings
+        .iter()
+        .enumerate()
+        .map(|(i, reading)| {
+            let mut flags = BTreeMap::new();
+            flags.insert("depth".to_string(), depth_test(reading));
+            let previous = i.checked_sub(1).map(|p| &readings[p]);
+            flags.insert("location".to_string(), location_test(previous, reading));
+            for (quantity, &value) in &reading.values {
+                let thresholds = limits.get(device, quantity, reading.time);
+                let value_at = |j: usize| readings.get(j).and_then(|r| r.values.get(quantity));
+                let history: Vec<Option<f64>> = readings[i.saturating_sub(STUCK_COUNT - 1)..i]
+                    .iter()
+                    .map(|r| r.values.get(quantity).copied())
+                    .collect();
+                flags.insert(format!("{quantity}_range"), range_test(value, thresholds));
+                flags.insert(
+                    format!("{quantity}_spike"),
+                    spike_test(
+                        i.checked_sub(1).and_then(value_at).copied(),
+                        value,
+                        value_at(i + 1).copied(),
+                        thresholds.spike,
+                    ),
+                );
+                flags.insert(format!("{quantity}_stuck"), stuck_test(&history, value));
+            }
+            (reading.id, flags)
+        })
+        .collect()
+}
+
+/// The readings of a trip, grouped by device and layer and oldest first.
+type Series = BTreeMap<(Option<String>, Layer), Vec<Reading>>;
+
+/// Loads the readings of a trip, or only the readings of the series of a reading within
+/// `STUCK_COUNT` readings of it, which are all the readings its tests depend on.
+async fn load_readings(pool: &PgPool, trip: Uuid, around: Option<i32>) -> Result<Series> {
+    let rows = sqlx::query_as!(
+        ReadingRow,
+        r#"SELECT data.id, data.device, data.time, data.temperature, data.depth,
+ data.layer AS "layer: Layer", data.location,
+ COALESCE(json_object_agg(measurements.quantity, measurements.value)
+ FILTER (WHERE measurements.quantity IS NOT NULL), '{}') AS "measurements!"
+FROM data
+LEFT JOIN measurements ON measurements.data = data.id
+WHERE data.trip = $1 AND ($2::INTEGER IS NULL OR data.id IN (
+  (SELECT series.id FROM data AS series JOIN data AS reading ON reading.id = $2
+  WHERE series.trip = reading.trip AND series.device IS NOT DISTINCT FROM reading.device
+   AND series.layer = reading.layer AND (series.time, series.id) < (reading.time, reading.id)
+  ORDER BY series.time DESC, series.id DESC LIMIT $3)
+  UNION ALL
+  (SELECT series.id FROM data AS series JOIN data AS reading ON reading.id = $2
+  WHERE series.trip = reading.trip AND series.device IS NOT DISTINCT FROM reading.device
+   AND series.layer = reading.layer AND (series.time, series.id) >= (reading.time, reading.id)
+  ORDER BY series.time, series.id LIMIT $3 + 1)
+))
+GROUP BY data.id
+ORDER BY data.time, data.id"#,
+        trip,
+        around,
+        STUCK_COUNT as i64
+    )
+    .fetch_all(pool)
+    .await?;
+    let calibrations = Calibrations::for_trip(pool, trip).await?;
+
+    let mut readings = Series::new();
+    for row in rows {
+        let mut temperature = row.temperature;
+        let mut values: BTreeMap<String, f64> = serde_json::from_value(row.measurements)?;
+        calibrations.apply_reading(
+            row.device.as_deref(),
+            row.time,
+            &mut temperature,
+            &mut values,
+        );
+        values.insert(TEMPERATURE.to_string(), temperature);
+        let sea_bed = row.layer == Layer::SeaBed;
+        readings
+            .entry((row.device, row.layer))
+            .or_default()
+            .push(Reading {
+                id: row.id,
+                time: row.time,
+                depth: row.depth,
+                sea_bed,
+                location: serde_json::from_value(row.location)?,
+                values,
+            });
+    }
+    Ok(readings)
+}
+
+/// Stores the flags of the readings, replacing the previous flags of the same tests.
+async fn store_flags(state: &AppState, flags: &[(i32, BTreeMap<String, i16>)]) -> Result<()> {
+    let (ids, (tests, values)): (Vec<i32>, (Vec<String>, Vec<i16>)) = flags
+        .iter()
+        .flat_map(|(id, flags)| {
+            flags
+                .iter()
+                .map(|(test, flag)| (*id, (test.clone(), *flag)))
+        })
+        .unzip();
+    sqlx::query!(
+        "INSERT INTO qc_flags (data, test, flag)
+SELECT * FROM UNNEST($1::INTEGER[], $2::TEXT[], $3::SMALLINT[])
+ON CONFLICT (data, test) DO UPDATE SET flag = EXCLUDED.flag",
+        &ids,
+        &tests,
+        &values
+    )
+    .execute(&state.pool)
+    .await?;
+    state.metrics.inserted("qc_flags", ids.len() as u64);
+    Ok(())
+}
+
+/// Runs the quality control on a new reading and the readings whose tests depend on it.
+///
+/// The previous reading is flagged again for its spike test, and the following readings for
+/// their location, spike and stuck tests, as the reading can be inserted out of order.
+pub async fn check_reading(state: &AppState, trip: Uuid, id: i32) -> Result<()> {
+    let limits = Limits::load(&state.pool).await?;
+    let readings = load_readings(&state.pool, trip, Some(id)).await?;
+    let mut flags = Vec::new();
+    for ((device, _), readings) in &readings {
+        let Some(position) = readings.iter().position(|reading| reading.id == id) else {
+            continue;
+        };
+        let affected = position.saturating_sub(1)..position + STUCK_COUNT;
+        let evaluated = evaluate(device.as_deref(), readings, &limits);
+        flags.extend(
+            evaluated
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| affected.contains(i))
+                .map(|(_, flags)| flags),
+        );
+    }
+    store_flags(state, &flags).await
+}
+
+/// Runs the quality control on all the readings of a trip, returning their flags.
+pub async fn check_trip(state: &AppState, trip: Uuid) -> Result<Vec<(i32, BTreeMap<String, i16>)>> {
+    let limits = Limits::load(&state.pool).await?;
+    let readings = load_readings(&state.pool, trip, None).await?;
+    let flags: Vec<_> = readings
+        .iter()
+        .flat_map(|((device, _), readings)| evaluate(device.as_deref(), readings, &limits))
+        .collect();
+    store_flags(state, &flags).await?;
+    Ok(flags)
+}
+
+#[derive(Serialize, ToSchema, Default)]
+/// The number of readings with each flag after running the quality control.
+struct QcSummary {
+    /// The number of readings which passed all the tests.
+    pass: usize,
+    /// The number of readings which could not be evaluated.
+    not_evaluated: usize,
+    /// The number of suspect readings.
+    suspect: usize,
+    /// The number of bad readings.
+    fail: usize,
+}
+
+#[utoipa::path(
+    context_path = "/api/qc",
+    tag = "qc",
+    params(("uuid" = Uuid, Path, description = "The UUID of the trip.")),
+    responses(
+        (status = 200, description = "The quality control is run on all the data of the trip.", body = QcSummary),
+        (status = 404, description = "The trip does not exist.", body = ErrorBody),
+    )
+)]
+#[post("/{uuid}")]
+/// Runs the quality control on all the data collected in a trip.
+///
+/// This should be run after the thresholds or calibrations of the sensors change.
+async fn run_qc(trip: Path<Uuid>, state: Data<AppState>) -> Result<impl Responder> {
+    if !trip_exists(&state.pool, *trip).await? {
+        return Err(ApiError::NotFound(format!("Trip {}", *trip)));
+    }
+    let flags = check_trip(&state, *trip).await?;
+
+    let mut summary = QcSummary::default();
+    for (_, flags) in &flags {
+        match aggregate(flags) {
+            PASS => summary.pass += 1,
+            SUSPECT => summary.suspect += 1,
+            FAIL => summary.fail += 1,
+            _ => summary.not_evaluated += 1,
+        }
+    }
+    tracing::info!(trip = %*trip, readings = flags.len(), "Quality Control Run");
+    Ok(Json(summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use time::{Duration, OffsetDateTime};
+
+    use sqlx::PgPool;
+
+    use super::{
+        aggregate, depth_test, location_test, range_test, spike_test, stuck_test, Reading,
+        Thresholds, FAIL, NOT_EVALUATED, PASS, SUSPECT,
+    };
+    use crate::api::{geofence::METRES_PER_DEGREE, Coordinates};
+
+    /// Makes a reading at a location, some seconds after the epoch.
+    fn reading(latitude: f64, longitude: f64, seconds: i64) -> Reading {
+        Reading {
+            id: 0,
+            time: OffsetDateTime::UNIX_EPOCH + Duration::seconds(seconds),
+            depth: 1.0,
+            sea_bed: false,
+            location: Coordinates {
+                latitude,
+                longitude,
+            },
+            values: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn range_test_flags_the_values_outside_the_ranges() {
+        assert_eq!(range_test(50.0, Thresholds::default()), NOT_EVALUATED);
+        let thresholds = Thresholds {
+            expected: (Some(0.0), Some(35.0)),
+            sensor: (Some(-5.0), Some(40.0)),
+            spike: None,
+        };
+        for (value, flag) in [
+            (-5.1, FAIL),
+            (-5.0, SUSPECT),
+            (-0.1, SUSPECT),
+            (0.0, PASS),
+            (35.0, PASS),
+            (35.1, SUSPECT),
+            (40.0, SUSPECT),
+            (40.1, FAIL),
+        ] {
+            assert_eq!(range_test(value, thresholds), flag, "{value}");
+        }
+        let open = Thresholds {
+            expected: (None, Some(35.0)),
+            ..Default::default()
+        };
+        assert_eq!(range_test(-100.0, open), PASS);
+        assert_eq!(range_test(35.5, open), SUSPECT);
+    }
+
+    #[test]
+    fn spike_test_flags_the_values_deviating_from_both_neighbours() {
+        assert_eq!(spike_test(None, 10.0, Some(10.0), Some(1.0)), NOT_EVALUATED);
+        assert_eq!(spike_test(Some(10.0), 10.0, None, Some(1.0)), NOT_EVALUATED);
+        assert_eq!(
+            spike_test(Some(10.0), 10.0, Some(10.0), None),
+            NOT_EVALUATED
+        );
+        for (value, flag) in [
+            (11.0, PASS),
+            (11.5, SUSPECT),
+            (12.0, SUSPECT),
+            (12.5, FAIL),
+            (7.5, FAIL),
+        ] {
+            assert_eq!(
+                spike_test(Some(10.0), value, Some(10.0), Some(1.0)),
+                flag,
+                "{value}"
+            );
+        }
+        // A steep but steady gradient is not a spike
+        assert_eq!(spike_test(Some(10.0), 14.0, Some(14.0), Some(1.0)), PASS);
+        assert_eq!(spike_test(Some(10.0), 15.5, Some(14.0), Some(1.0)), SUSPECT);
+    }
+
+    #[test]
+    fn stuck_test_flags_repeated_values() {
+        let repeated = [Some(20.0); 4];
+        assert_eq!(stuck_test(&repeated[..3], 20.0), NOT_EVALUATED);
+        assert_eq!(stuck_test(&repeated, 20.0), SUSPECT);
+        assert_eq!(stuck_test(&repeated, 20.1), PASS);
+        assert_eq!(
+            stuck_test(&[Some(20.0), None, Some(20.0), Some(20.0)], 20.0),
+            PASS
+        );
+        assert_eq!(
+            stuck_test(&[Some(19.9), Some(20.0), Some(20.0), Some(20.0)], 20.0),
+            PASS
+        );
+    }
+
+    #[test]
+    fn depth_test_flags_impossible_depths() {
+        let mut reading = reading(0.0, 0.0, 0);
+        for (depth, sea_bed, flag) in [
+            (-0.1, false, FAIL),
+            (0.0, false, PASS),
+            (0.0, true, FAIL),
+            (0.1, true, PASS),
+        ] {
+            reading.depth = depth;
+            reading.sea_bed = sea_bed;
+            assert_eq!(depth_test(&reading), flag, "{depth} {sea_bed}");
+        }
+    }
+
+    #[test]
+    fn location_test_flags_invalid_coordinates() {
+        for (latitude, longitude, flag) in [
+            (90.0, 180.0, NOT_EVALUATED),
+            (-90.0, -180.0, NOT_EVALUATED),
+            (90.1, 0.0, FAIL),
+            (-90.1, 0.0, FAIL),
+            (0.0, 180.1, FAIL),
+            (0.0, -180.1, FAIL),
+        ] {
+            let reading = reading(latitude, longitude, 0);
+            assert_eq!(
+                location_test(None, &reading),
+                flag,
+                "{latitude} {longitude}"
+            );
+        }
+    }
+
+    #[test]
+    fn location_test_flags_jumps_faster_than_the_robot() {
+        let previous = reading(0.0, 0.0, 0);
+        let north = |metres: f64, seconds| reading(metres / METRES_PER_DEGREE, 0.0, seconds);
+        for (metres, seconds, flag) in [
+            (49.0, 10, PASS),
+            (51.0, 10, FAIL),
+            // The readings in the same second are allowed the distance of a second
+            (4.9, 0, PASS),
+            (5.1, 0, FAIL),
+        ] {
+            let reading = north(metres, seconds);
+            assert_eq!(
+                location_test(Some(&previous), &reading),
+                flag,
+                "{metres} m in {seconds} s"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires a database at DATABASE_URL"]
+    async fn qc_flag_of_the_schema_matches_aggregate() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL Not Set");
+        let pool = PgPool::connect(&url).await.unwrap();
+        for (flags, flag) in [
+            (vec![], NOT_EVALUATED),
+            (vec![NOT_EVALUATED, NOT_EVALUATED], NOT_EVALUATED),
+            (vec![PASS, NOT_EVALUATED], PASS),
+            (vec![PASS, SUSPECT, NOT_EVALUATED], SUSPECT),
+            (vec![FAIL, PASS, SUSPECT], FAIL),
+        ] {
+            let tests = flags
+                .iter()
+                .enumerate()
+                .map(|(i, &flag)| (format!("test_{i}"), flag))
+                .collect();
+            assert_eq!(aggregate(&tests), flag, "{flags:?}");
+            let combined: i16 = sqlx::query_scalar("SELECT qc_flag($1)")
+                .bind(&flags)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(combined, flag, "{flags:?}");
+        }
+    }
+}
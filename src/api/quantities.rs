@@ -0,0 +1,124 @@
+//! Module for Actix services for the quantities measured by the sensors.
+
+use actix_web::{
+    get, post,
+    web::{scope, Data, Json, ServiceConfig},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+/// The name of the quantity stored in the dedicated `temperature` column.
+pub const TEMPERATURE: &str = "temperature";
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_quantities, register_quantity),
+    components(schemas(Quantity))
+)]
+/// The OpenAPI specification of the quantities API resources.
+pub struct QuantitiesApi;
+
+/// Configuration function for the quantities API resources.
+pub fn quantities_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/quantities")
+            .service(get_quantities)
+            .service(register_quantity),
+    );
+}
+
+#[derive(Serialize, Deserialize, FromRow, ToSchema)]
+/// A quantity which can be measured by the sensors.
+struct Quantity {
+    /// The name of the quantity, used as the key in the measurements.
+    name: String,
+    /// The unit the quantity is measured in.
+    unit: String,
+    /// The minimum value expected in the water bodies, values below are suspect.
+    minimum: Option<f64>,
+    /// The maximum value expected in the water bodies, values above are suspect.
+    maximum: Option<f64>,
+    /// The deviation from the neighbouring readings above which a value is a suspect spike.
+    spike: Option<f64>,
+}
+
+/// Checks that all the given quantities are registered.
+pub async fn validate_quantities(pool: &PgPool, quantities: &[String]) -> Result<()> {
+    if quantities.is_empty() {
+        return Ok(());
+    }
+    let registered = sqlx::query_scalar!(
+        "SELECT name FROM quantities WHERE name = ANY($1)",
+        quantities
+    )
+    .fetch_all(pool)
+    .await?;
+    let unknown: Vec<&str> = quantities
+        .iter()
+        .filter(|q| !registered.contains(q))
+        .map(String::as_str)
+        .collect();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::Unprocessable(format!(
+            "Unknown Quantities: {}",
+            unknown.join(", ")
+        )))
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/quantities",
+    tag = "quantities",
+    responses((status = 200, description = "All the registered quantities.", body = [Quantity]))
+)]
+#[get("")]
+/// Gets all the registered quantities.
+async fn get_quantities(state: Data<AppState>) -> Result<impl Responder> {
+    let quantities = sqlx::query_as!(
+        Quantity,
+        "SELECT name, unit, minimum, maximum, spike FROM quantities ORDER BY name"
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(quantities))
+}
+
+#[utoipa::path(
+    context_path = "/api/quantities",
+    tag = "quantities",
+    request_body = Quantity,
+    responses(
+        (status = 200, description = "The quantity is registered.", body = Quantity),
+        (status = 409, description = "The quantity is already registered.", body = ErrorBody),
+    )
+)]
+#[post("")]
+/// Registers a new quantity which can be reported in the data.
+async fn register_quantity(
+    quantity: Json<Quantity>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    let quantity = sqlx::query_as!(
+        Quantity,
+        "INSERT INTO quantities (name, unit, minimum, maximum, spike) VALUES ($1, $2, $3, $4, $5)
+RETURNING name, unit, minimum, maximum, spike",
+        quantity.name,
+        quantity.unit,
+        quantity.minimum,
+        quantity.maximum,
+        quantity.spike
+    )
+    .fetch_one(&state.pool)
+    .await?;
+    state.metrics.inserted("quantities", 1);
+    Ok(Json(quantity))
+}
@@ -0,0 +1,152 @@
+//! Selection of the temperature readings used by the analyses of the data.
+
+use serde::Deserialize;
+use sqlx::{FromRow, PgPool};
+use time::OffsetDateTime;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::{
+    data::{Layer, QualityType, ValuesType},
+    paths::path_exists,
+    qc::{aggregate, PASS},
+    quantities::TEMPERATURE,
+    sensors::Calibrations,
+    trips::trip_exists,
+    Coordinates,
+};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+/// The query specification for selecting the readings to analyse.
+pub struct Selection {
+    /// The trip the readings are collected in.
+    pub(super) trip: Option<Uuid>,
+    /// The path followed by the trips the readings are collected in.
+    pub(super) path: Option<Uuid>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    #[param(value_type = Option<String>, format = DateTime)]
+    /// The earliest time of the readings, inclusive.
+    pub(super) from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    #[param(value_type = Option<String>, format = DateTime)]
+    /// The latest time of the readings, exclusive.
+    pub(super) to: Option<OffsetDateTime>,
+    #[serde(default)]
+    #[param(inline)]
+    /// Whether the raw or calibrated temperatures are analysed.
+    pub(super) values: ValuesType,
+    #[serde(default)]
+    #[param(inline)]
+    /// Whether only the readings which passed the quality control are analysed.
+    pub(super) quality: QualityType,
+}
+
+#[derive(FromRow)]
+/// A reading as stored in the database.
+struct SampleRow {
+    /// The device which measured the reading.
+    device: Option<String>,
+    /// The time the reading is measured.
+    time: OffsetDateTime,
+    /// The location the reading is measured.
+    location: serde_json::Value,
+    /// The depth the reading is measured.
+    depth: f64,
+    /// The temperature measured.
+    temperature: f64,
+    /// The quality control flags of the reading, keyed by test.
+    qc: serde_json::Value,
+}
+
+/// A temperature reading selected for an analysis.
+pub struct Sample {
+    /// The time the reading is measured.
+    pub time: OffsetDateTime,
+    /// The location the reading is measured.
+    pub location: Coordinates,
+    /// The depth the reading is measured.
+    pub depth: f64,
+    /// The temperature measured.
+    pub temperature: f64,
+}
+
+impl Selection {
+    /// Checks that the trip and path selected exist.
+    pub async fn check(&self, pool: &PgPool) -> Result<()> {
+        if let Some(trip) = self.trip {
+            if !trip_exists(pool, trip).await? {
+                return Err(ApiError::NotFound(format!("Trip {trip}")));
+            }
+        }
+        if let Some(path) = self.path {
+            if !path_exists(pool, path).await? {
+                return Err(ApiError::NotFound(format!("Path {path}")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the temperature readings selected, optionally only from a layer, oldest first.
+    pub async fn samples(&self, state: &AppState, layer: Option<Layer>) -> Result<Vec<Sample>> {
+        self.check(&state.pool).await?;
+        let rows = sqlx::query_as!(
+            SampleRow,
+            r#"SELECT data.device, data.time, data.location, data.depth, data.temperature,
+ (SELECT COALESCE(json_object_agg(qc_flags.test, qc_flags.flag), '{}') FROM qc_flags
+ WHERE qc_flags.data = data.id) AS "qc!"
+FROM data
+JOIN trips ON trips.uuid = data.trip
+WHERE ($1::UUID IS NULL OR data.trip = $1)
+ AND ($2::UUID IS NULL OR trips.path = $2)
+ AND ($3::TIMESTAMPTZ IS NULL OR data.time >= $3)
+ AND ($4::TIMESTAMPTZ IS NULL OR data.time < $4)
+ AND ($5::layer IS NULL OR data.layer = $5)
+ORDER BY data.time"#,
+            self.trip,
+            self.path,
+            self.from,
+            self.to,
+            layer as Option<Layer>
+        )
+        .fetch_all(&state.pool)
+        .await?;
+
+        let calibrations = match self.values {
+            ValuesType::Raw => Calibrations::default(),
+            ValuesType::Calibrated => {
+                let mut devices: Vec<String> =
+                    rows.iter().filter_map(|row| row.device.clone()).collect();
+                devices.sort();
+                devices.dedup();
+                Calibrations::for_devices(&state.pool, &devices).await?
+            }
+        };
+        let mut samples = Vec::with_capacity(rows.len());
+        for row in rows {
+            if self.quality == QualityType::Good
+                && aggregate(&serde_json::from_value(row.qc)?) != PASS
+            {
+                continue;
+            }
+            let temperature = calibrations.apply(
+                row.device.as_deref(),
+                TEMPERATURE,
+                row.time,
+                row.temperature,
+            );
+            samples.push(Sample {
+                time: row.time,
+                location: serde_json::from_value(row.location)?,
+                depth: row.depth,
+                temperature,
+            });
+        }
+        Ok(samples)
+    }
+}
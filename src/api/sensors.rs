@@ -0,0 +1,715 @@
+//! Module for Actix services for the sensors, their installations and their calibrations.
+//!
+//! A sensor is installed on a device for a period, and the readings of a device are calibrated
+//! with the calibrations of the sensor installed on it when they are measured, so swapping or
+//! moving a sensor does not change the readings measured before.
+
+use std::collections::{BTreeMap, HashMap};
+
+use actix_web::{
+    get, post,
+    web::{scope, Data, Json, Path, ServiceConfig},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use time::OffsetDateTime;
+use utoipa::{OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::quantities::TEMPERATURE;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get_sensors,
+        register_sensor,
+        get_installations,
+        install_sensor,
+        uninstall_sensor,
+        get_calibrations,
+        calibrate_sensor
+    ),
+    components(schemas(
+        SensorValues,
+        SensorInput,
+        InstallationValues,
+        InstallationInput,
+        UninstallInput,
+        CalibrationValues
+    ))
+)]
+/// The OpenAPI specification of the sensors API resources.
+pub struct SensorsApi;
+
+/// Configuration function for the sensors API resources.
+pub fn sensors_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/sensors")
+            .service(get_sensors)
+            .service(register_sensor)
+            .service(get_installations)
+            .service(install_sensor)
+            .service(uninstall_sensor)
+            .service(get_calibrations)
+            .service(calibrate_sensor),
+    );
+}
+
+/// Checks whether a sensor exists.
+async fn sensor_exists(pool: &PgPool, serial: &str) -> Result<bool> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM sensors WHERE serial = $1) AS "exists!""#,
+        serial
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+/// The data format for sensors data.
+struct SensorValues {
+    /// The serial number of the sensor.
+    serial: String,
+    /// The quantity measured by the sensor.
+    quantity: String,
+    /// The unit of the quantity measured by the sensor.
+    unit: String,
+    /// The device the sensor is currently installed on.
+    device: Option<String>,
+    /// The minimum value the sensor can measure.
+    minimum: Option<f64>,
+    /// The maximum value the sensor can measure.
+    maximum: Option<f64>,
+}
+
+/// Gets a sensor with the device it is currently installed on.
+async fn get_sensor(conn: &mut PgConnection, serial: &str) -> Result<SensorValues> {
+    Ok(sqlx::query_as!(
+        SensorValues,
+        r#"SELECT sensors.serial, sensors.quantity, quantities.unit,
+ installations.device AS "device?", sensors.minimum, sensors.maximum
+FROM sensors
+JOIN quantities ON quantities.name = sensors.quantity
+LEFT JOIN installations ON installations.sensor = sensors.serial
+ AND installations.installed_to IS NULL
+WHERE sensors.serial = $1"#,
+        serial
+    )
+    .fetch_one(conn)
+    .await?)
+}
+
+#[utoipa::path(
+    context_path = "/api/sensors",
+    tag = "sensors",
+    responses((status = 200, description = "All the registered sensors.", body = [SensorValues]))
+)]
+#[get("")]
+/// Gets all the registered sensors.
+async fn get_sensors(state: Data<AppState>) -> Result<impl Responder> {
+    let sensors = sqlx::query_as!(
+        SensorValues,
+        r#"SELECT sensors.serial, sensors.quantity, quantities.unit,
+ installations.device AS "device?", sensors.minimum, sensors.maximum
+FROM sensors
+JOIN quantities ON quantities.name = sensors.quantity
+LEFT JOIN installations ON installations.sensor = sensors.serial
+ AND installations.installed_to IS NULL
+ORDER BY sensors.serial"#
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(sensors))
+}
+
+#[derive(Deserialize, ToSchema)]
+/// The input data format for registering a sensor.
+struct SensorInput {
+    /// The serial number of the sensor.
+    serial: String,
+    /// The quantity measured by the sensor.
+    quantity: String,
+    /// The device the sensor is installed on, if any.
+    device: Option<String>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    /// The time the sensor is installed on the device from, now if not given.
+    installed_from: Option<OffsetDateTime>,
+    /// The minimum value the sensor can measure.
+    minimum: Option<f64>,
+    /// The maximum value the sensor can measure.
+    maximum: Option<f64>,
+}
+
+#[utoipa::path(
+    context_path = "/api/sensors",
+    tag = "sensors",
+    request_body = SensorInput,
+    responses(
+        (status = 200, description = "The sensor is registered.", body = SensorValues),
+        (status = 400, description = "The valid range is empty.", body = ErrorBody),
+        (status = 409, description = "The sensor or a sensor of the quantity on the device is already registered.", body = ErrorBody),
+        (status = 422, description = "The quantity or device does not exist.", body = ErrorBody),
+    )
+)]
+#[post("")]
+/// Registers a new sensor, installing it on the device if given.
+async fn register_sensor(
+    sensor: Json<SensorInput>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    if let (Some(minimum), Some(maximum)) = (sensor.minimum, sensor.maximum) {
+        if minimum > maximum {
+            return Err(ApiError::BadRequest(format!(
+                "Minimum {minimum} Is Greater Than Maximum {maximum}"
+            )));
+        }
+    }
+    let mut tx = state.pool.begin().await?;
+    sqlx::query!(
+        "INSERT INTO sensors (serial, quantity, minimum, maximum) VALUES ($1, $2, $3, $4)",
+        sensor.serial,
+        sensor.quantity,
+        sensor.minimum,
+        sensor.maximum
+    )
+    .execute(&mut *tx)
+    .await?;
+    if let Some(device) = &sensor.device {
+        let time = sensor
+            .installed_from
+            .unwrap_or_else(OffsetDateTime::now_utc);
+        install(&mut tx, &sensor.serial, device, time).await?;
+    }
+    let values = get_sensor(&mut tx, &sensor.serial).await?;
+    tx.commit().await?;
+    state.metrics.inserted("sensors", 1);
+    Ok(Json(values))
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+/// The data format for the installations of the sensors.
+struct InstallationValues {
+    /// The device the sensor is installed on.
+    device: String,
+    #[serde(with = "time::serde::rfc3339")]
+    /// The time the sensor is installed from.
+    installed_from: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    /// The time the sensor is removed, `null` while it is installed.
+    installed_to: Option<OffsetDateTime>,
+}
+
+#[derive(Deserialize, ToSchema)]
+/// The input data format for installing a sensor on a device.
+struct InstallationInput {
+    /// The device the sensor is installed on.
+    device: String,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    /// The time the sensor is installed from, now if not given.
+    installed_from: Option<OffsetDateTime>,
+}
+
+#[derive(Deserialize, ToSchema)]
+/// The input data format for removing a sensor from its device.
+struct UninstallInput {
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    /// The time the sensor is removed, now if not given.
+    installed_to: Option<OffsetDateTime>,
+}
+
+/// Ends the current installation of a sensor at a time, returning whether it was installed.
+async fn uninstall(conn: &mut PgConnection, serial: &str, time: OffsetDateTime) -> Result<bool> {
+    let installed_from = sqlx::query_scalar!(
+        "SELECT installed_from FROM installations
+WHERE sensor = $1 AND installed_to IS NULL FOR UPDATE",
+        serial
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(installed_from) = installed_from else {
+        return Ok(false);
+    };
+    if time <= installed_from {
+        return Err(ApiError::BadRequest(format!(
+            "Sensor {serial} Is Installed After The Given Time"
+        )));
+    }
+    sqlx::query!(
+        "UPDATE installations SET installed_to = $2 WHERE sensor = $1 AND installed_to IS NULL",
+        serial,
+        time
+    )
+    .execute(conn)
+    .await?;
+    Ok(true)
+}
+
+/// Installs a sensor on a device from a time.
+///
+/// The sensor must not be installed anywhere after the time, and the device must not have
+/// another sensor of the same quantity after the time.
+async fn install(
+    conn: &mut PgConnection,
+    serial: &str,
+    device: &str,
+    time: OffsetDateTime,
+) -> Result<()> {
+    let overlapping = sqlx::query_scalar!(
+        "SELECT installations.sensor FROM installations
+JOIN sensors ON sensors.serial = installations.sensor
+WHERE (installations.sensor = $1 OR (installations.device = $2
+  AND sensors.quantity = (SELECT quantity FROM sensors WHERE serial = $1)))
+ AND (installations.installed_to IS NULL OR installations.installed_to > $3)
+LIMIT 1",
+        serial,
+        device,
+        time
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    match overlapping {
+        Some(sensor) if sensor == serial => Err(ApiError::Conflict(format!(
+            "Sensor {serial} Is Installed After The Given Time"
+        ))),
+        Some(sensor) => Err(ApiError::Conflict(format!(
+            "Sensor {sensor} Of The Same Quantity Is Installed On {device} After The Given Time"
+        ))),
+        None => {
+            sqlx::query!(
+                "INSERT INTO installations (sensor, device, installed_from) VALUES ($1, $2, $3)",
+                serial,
+                device,
+                time
+            )
+            .execute(conn)
+            .await?;
+            Ok(())
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+/// The calibration of a sensor from a point in time onwards.
+///
+/// The calibrated value is `offset + gain * raw + polynomial[0] * raw^2 + polynomial[1] * raw^3 ...`.
+struct CalibrationValues {
+    #[serde(with = "time::serde::rfc3339")]
+    /// The time from which the calibration applies.
+    valid_from: OffsetDateTime,
+    #[serde(default)]
+    /// The offset added to the raw value.
+    offset: f64,
+    #[serde(default = "CalibrationValues::gain_default")]
+    /// The gain multiplied with the raw value.
+    gain: f64,
+    #[serde(default)]
+    /// The coefficients of the higher order terms, starting from the square of the raw value.
+    polynomial: Vec<f64>,
+}
+
+impl CalibrationValues {
+    fn gain_default() -> f64 {
+        1.0
+    }
+
+    /// Creates the calibration from the coefficients stored in the database.
+    fn from_coefficients(valid_from: OffsetDateTime, coefficients: Vec<f64>) -> Self {
+        let mut coefficients = coefficients.into_iter();
+        Self {
+            valid_from,
+            offset: coefficients.next().unwrap_or(0.0),
+            gain: coefficients.next().unwrap_or(1.0),
+            polynomial: coefficients.collect(),
+        }
+    }
+
+    /// The coefficients stored in the database, in increasing order of power.
+    fn coefficients(&self) -> Vec<f64> {
+        [self.offset, self.gain]
+            .into_iter()
+            .chain(self.polynomial.iter().copied())
+            .collect()
+    }
+
+    /// Calibrates a raw value, as the `calibrate` function of the schema does in the database.
+    fn apply(&self, raw: f64) -> f64 {
+        let higher = self
+            .polynomial
+            .iter()
+            .rev()
+            .fold(0.0, |acc, c| acc * raw + c);
+        self.offset + self.gain * raw + higher * raw * raw
+    }
+}
+
+#[derive(Deserialize)]
+/// The sensor specification for the calibrations.
+struct SensorPath {
+    /// The serial number of the sensor.
+    serial: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/sensors",
+    tag = "sensors",
+    params(("serial" = String, Path, description = "The serial number of the sensor.")),
+    responses(
+        (status = 200, description = "All the installations of the sensor, oldest first.", body = [InstallationValues]),
+        (status = 404, description = "The sensor does not exist.", body = ErrorBody),
+    )
+)]
+#[get("/{serial}/installations")]
+/// Gets the devices a sensor has been installed on.
+async fn get_installations(
+    sensor: Path<SensorPath>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    if !sensor_exists(&state.pool, &sensor.serial).await? {
+        return Err(ApiError::NotFound(format!("Sensor {}", sensor.serial)));
+    }
+    let installations = sqlx::query_as!(
+        InstallationValues,
+        "SELECT device, installed_from, installed_to FROM installations
+WHERE sensor = $1 ORDER BY installed_from",
+        sensor.serial
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(installations))
+}
+
+#[utoipa::path(
+    context_path = "/api/sensors",
+    tag = "sensors",
+    params(("serial" = String, Path, description = "The serial number of the sensor.")),
+    request_body = InstallationInput,
+    responses(
+        (status = 200, description = "The sensor is installed on the device.", body = SensorValues),
+        (status = 400, description = "The sensor is installed after the time.", body = ErrorBody),
+        (status = 404, description = "The sensor does not exist.", body = ErrorBody),
+        (status = 409, description = "The device has another sensor of the quantity after the time.", body = ErrorBody),
+        (status = 422, description = "The device does not exist.", body = ErrorBody),
+    )
+)]
+#[post("/{serial}/installations")]
+/// Installs a sensor on a device, removing it from its current device at the same time.
+///
+/// The readings measured before the time keep the calibrations of the sensors installed then.
+async fn install_sensor(
+    sensor: Path<SensorPath>,
+    installation: Json<InstallationInput>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    if !sensor_exists(&state.pool, &sensor.serial).await? {
+        return Err(ApiError::NotFound(format!("Sensor {}", sensor.serial)));
+    }
+    let time = installation
+        .installed_from
+        .unwrap_or_else(OffsetDateTime::now_utc);
+    let mut tx = state.pool.begin().await?;
+    uninstall(&mut tx, &sensor.serial, time).await?;
+    install(&mut tx, &sensor.serial, &installation.device, time).await?;
+    let values = get_sensor(&mut tx, &sensor.serial).await?;
+    tx.commit().await?;
+    state.metrics.inserted("installations", 1);
+    Ok(Json(values))
+}
+
+#[utoipa::path(
+    context_path = "/api/sensors",
+    tag = "sensors",
+    params(("serial" = String, Path, description = "The serial number of the sensor.")),
+    request_body = UninstallInput,
+    responses(
+        (status = 200, description = "The sensor is removed from its device.", body = SensorValues),
+        (status = 400, description = "The sensor is installed after the time.", body = ErrorBody),
+        (status = 404, description = "The sensor does not exist.", body = ErrorBody),
+        (status = 409, description = "The sensor is not installed.", body = ErrorBody),
+    )
+)]
+#[post("/{serial}/uninstall")]
+/// Removes a sensor from the device it is installed on.
+async fn uninstall_sensor(
+    sensor: Path<SensorPath>,
+    removal: Json<UninstallInput>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    if !sensor_exists(&state.pool, &sensor.serial).await? {
+        return Err(ApiError::NotFound(format!("Sensor {}", sensor.serial)));
+    }
+    let time = removal.installed_to.unwrap_or_else(OffsetDateTime::now_utc);
+    let mut tx = state.pool.begin().await?;
+    if !uninstall(&mut tx, &sensor.serial, time).await? {
+        return Err(ApiError::Conflict(format!(
+            "Sensor {} Is Not Installed",
+            sensor.serial
+        )));
+    }
+    let values = get_sensor(&mut tx, &sensor.serial).await?;
+    tx.commit().await?;
+    Ok(Json(values))
+}
+
+#[utoipa::path(
+    context_path = "/api/sensors",
+    tag = "sensors",
+    params(("serial" = String, Path, description = "The serial number of the sensor.")),
+    responses(
+        (status = 200, description = "All the calibrations of the sensor, oldest first.", body = [CalibrationValues]),
+        (status = 404, description = "The sensor does not exist.", body = ErrorBody),
+    )
+)]
+#[get("/{serial}/calibrations")]
+/// Gets the calibration history of a sensor.
+async fn get_calibrations(
+    sensor: Path<SensorPath>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    if !sensor_exists(&state.pool, &sensor.serial).await? {
+        return Err(ApiError::NotFound(format!("Sensor {}", sensor.serial)));
+    }
+    let calibrations: Vec<_> = sqlx::query!(
+        "SELECT valid_from, coefficients FROM calibrations WHERE sensor = $1 ORDER BY valid_from",
+        sensor.serial
+    )
+    .fetch_all(&state.pool)
+    .await?
+    .into_iter()
+    .map(|row| CalibrationValues::from_coefficients(row.valid_from, row.coefficients))
+    .collect();
+    Ok(Json(calibrations))
+}
+
+#[utoipa::path(
+    context_path = "/api/sensors",
+    tag = "sensors",
+    params(("serial" = String, Path, description = "The serial number of the sensor.")),
+    request_body = CalibrationValues,
+    responses(
+        (status = 200, description = "The calibration is recorded.", body = CalibrationValues),
+        (status = 404, description = "The sensor does not exist.", body = ErrorBody),
+    )
+)]
+#[post("/{serial}/calibrations")]
+/// Records a calibration of a sensor, replacing the one from the same time if any.
+///
+/// The stored readings are kept raw, so a calibration can be recorded after a trip with
+/// a time before the trip to recalibrate its readings.
+async fn calibrate_sensor(
+    sensor: Path<SensorPath>,
+    calibration: Json<CalibrationValues>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    if !sensor_exists(&state.pool, &sensor.serial).await? {
+        return Err(ApiError::NotFound(format!("Sensor {}", sensor.serial)));
+    }
+    sqlx::query!(
+        "INSERT INTO calibrations (sensor, valid_from, coefficients) VALUES ($1, $2, $3)
+ON CONFLICT (sensor, valid_from) DO UPDATE SET coefficients = EXCLUDED.coefficients",
+        sensor.serial,
+        calibration.valid_from,
+        &calibration.coefficients()
+    )
+    .execute(&state.pool)
+    .await?;
+    state.metrics.inserted("calibrations", 1);
+    Ok(calibration)
+}
+
+#[derive(FromRow)]
+/// A calibration of a sensor during one of its installations.
+struct CalibrationRow {
+    /// The device the sensor is installed on.
+    device: String,
+    /// The quantity measured by the sensor.
+    quantity: String,
+    /// The time the sensor is installed from.
+    installed_from: OffsetDateTime,
+    /// The time the sensor is removed, if it is.
+    installed_to: Option<OffsetDateTime>,
+    /// The time from which the calibration applies.
+    valid_from: OffsetDateTime,
+    /// The coefficients of the calibration, in increasing order of power.
+    coefficients: Vec<f64>,
+}
+
+/// The calibrations of a sensor during one of its installations.
+struct Installation {
+    /// The time the sensor is installed from.
+    from: OffsetDateTime,
+    /// The time the sensor is removed, if it is.
+    to: Option<OffsetDateTime>,
+    /// The calibrations of the sensor, oldest first.
+    calibrations: Vec<CalibrationValues>,
+}
+
+/// Whether a time is within an installation from a time and to a time if it is removed.
+pub fn is_installed(
+    from: OffsetDateTime,
+    to: Option<OffsetDateTime>,
+    time: OffsetDateTime,
+) -> bool {
+    match to {
+        Some(to) => from <= time && time < to,
+        None => from <= time,
+    }
+}
+
+#[derive(Default)]
+/// The calibrations of the sensors used in a trip.
+pub struct Calibrations {
+    /// The installations of the calibrated sensors keyed by device and quantity, oldest first.
+    sensors: HashMap<(String, String), Vec<Installation>>,
+}
+
+impl Calibrations {
+    /// Loads the calibrations of the sensors installed on the devices which collected data in a
+    /// trip.
+    pub async fn for_trip(pool: &PgPool, trip: Uuid) -> Result<Self> {
+        let rows = sqlx::query_as!(
+            CalibrationRow,
+            "SELECT installations.device, sensors.quantity, installations.installed_from,
+ installations.installed_to, calibrations.valid_from, calibrations.coefficients
+FROM installations
+JOIN sensors ON sensors.serial = installations.sensor
+JOIN calibrations ON calibrations.sensor = installations.sensor
+WHERE installations.device IN (SELECT DISTINCT device FROM data WHERE trip = $1)
+ORDER BY installations.installed_from, calibrations.valid_from",
+            trip
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(Self::from_rows(rows))
+    }
+
+    /// Loads the calibrations of the sensors installed on the given devices.
+    pub async fn for_devices(pool: &PgPool, devices: &[String]) -> Result<Self> {
+        let rows = sqlx::query_as!(
+            CalibrationRow,
+            "SELECT installations.device, sensors.quantity, installations.installed_from,
+ installations.installed_to, calibrations.valid_from, calibrations.coefficients
+FROM installations
+JOIN sensors ON sensors.serial = installations.sensor
+JOIN calibrations ON calibrations.sensor = installations.sensor
+WHERE installations.device = ANY($1)
+ORDER BY installations.installed_from, calibrations.valid_from",
+            devices
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(Self::from_rows(rows))
+    }
+
+    /// Groups the calibrations by device, quantity and installation.
+    fn from_rows(rows: Vec<CalibrationRow>) -> Self {
+        let mut calibrations = Self::default();
+        for row in rows {
+            let installations = calibrations
+                .sensors
+                .entry((row.device, row.quantity))
+                .or_default();
+            // A device has a single sensor of a quantity at a time, so the installations are
+            // told apart by their start
+            if !matches!(installations.last(), Some(last) if last.from == row.installed_from) {
+                installations.push(Installation {
+                    from: row.installed_from,
+                    to: row.installed_to,
+                    calibrations: Vec::new(),
+                });
+            }
+            if let Some(installation) = installations.last_mut() {
+                installation
+                    .calibrations
+                    .push(CalibrationValues::from_coefficients(
+                        row.valid_from,
+                        row.coefficients,
+                    ));
+            }
+        }
+        calibrations
+    }
+
+    /// Calibrates a raw value measured by a device at a time, with the sensor installed on the
+    /// device at the time.
+    ///
+    /// The value is returned as is if no sensor with a calibration at the time is installed.
+    pub fn apply(
+        &self,
+        device: Option<&str>,
+        quantity: &str,
+        time: OffsetDateTime,
+        raw: f64,
+    ) -> f64 {
+        let Some(device) = device else {
+            return raw;
+        };
+        self.sensors
+            .get(&(device.to_string(), quantity.to_string()))
+            .and_then(|installations| {
+                installations
+                    .iter()
+                    .find(|i| is_installed(i.from, i.to, time))
+            })
+            .and_then(|i| i.calibrations.iter().rev().find(|c| c.valid_from <= time))
+            .map_or(raw, |c| c.apply(raw))
+    }
+
+    /// Calibrates the temperature and the other quantities of a reading.
+    pub fn apply_reading(
+        &self,
+        device: Option<&str>,
+        time: OffsetDateTime,
+        temperature: &mut f64,
+        measurements: &mut BTreeMap<String, f64>,
+    ) {
+        *temperature = self.apply(device, TEMPERATURE, time, *temperature);
+        for (quantity, value) in measurements.iter_mut() {
+            *value = self.apply(device, quantity, time, *value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+    use time::OffsetDateTime;
+
+    use super::CalibrationValues;
+
+    #[actix_web::test]
+    #[ignore = "requires a database at DATABASE_URL"]
+    async fn calibrate_in_the_database_matches_the_server() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL Not Set");
+        let pool = PgPool::connect(&url).await.unwrap();
+        for coefficients in [
+            vec![],
+            vec![0.5],
+            vec![-1.0, 2.0],
+            vec![0.1, 0.98, 0.002, -1e-4],
+        ] {
+            let calibration = CalibrationValues::from_coefficients(
+                OffsetDateTime::UNIX_EPOCH,
+                coefficients.clone(),
+            );
+            for raw in [-3.5, 0.0, 1.0, 12.25, 31.0] {
+                let calibrated: f64 = sqlx::query_scalar("SELECT calibrate($1, $2)")
+                    .bind(&coefficients)
+                    .bind(raw)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+                let expected = calibration.apply(raw);
+                assert!(
+                    (calibrated - expected).abs() < 1e-9,
+                    "{calibrated} {expected}"
+                );
+            }
+        }
+    }
+}
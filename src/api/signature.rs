@@ -0,0 +1,360 @@
+//! Module for verifying HMAC signed payloads from the robots.
+//!
+//! Every signed request carries the following headers:
+//!
+//! - `X-Device-Id`: The ID of the registered device sending the request.
+//! - `X-Timestamp`: The UNIX timestamp (in seconds) when the request is signed.
+//! - `X-Nonce`: A random string that is never reused by the device.
+//! - `X-Signature`: The hex encoded HMAC-SHA256 of `{timestamp}\n{nonce}\n{body}`
+//!   using the shared secret of the device.
+//!
+//! Requests signed more than [`MAX_CLOCK_SKEW`] seconds away from the server time, or
+//! reusing a nonce within that window, are rejected as replays.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    future::Future,
+    pin::Pin,
+};
+
+use actix_web::{
+    dev::Payload,
+    web::{Bytes, Data},
+    FromRequest, HttpRequest,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use time::OffsetDateTime;
+use utoipa::IntoParams;
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::encoding::{self, Decode};
+
+/// The header containing the ID of the device.
+pub const DEVICE_HEADER: &str = "X-Device-Id";
+/// The header containing the time the request is signed.
+pub const TIMESTAMP_HEADER: &str = "X-Timestamp";
+/// The header containing the nonce of the request.
+pub const NONCE_HEADER: &str = "X-Nonce";
+/// The header containing the signature of the request.
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// The maximum difference in seconds allowed between the signed timestamp and the server.
+pub const MAX_CLOCK_SKEW: i64 = 300;
+/// The maximum length of a nonce.
+const MAX_NONCE_LENGTH: usize = 64;
+
+#[derive(Default, Debug)]
+/// A cache of the nonces seen within the replay window.
+pub struct NonceCache {
+    /// The nonces seen, keyed by the device and the nonce.
+    seen: HashSet<(String, String)>,
+    /// The nonces seen with the time they are signed, oldest first.
+    signed: BTreeSet<(i64, String, String)>,
+}
+
+impl NonceCache {
+    /// Records a nonce, returning `false` if it has already been used.
+    ///
+    /// Nonces signed before the replay window are pruned as they can no longer be accepted,
+    /// oldest first so only the expired nonces are visited.
+    fn insert(&mut self, device: &str, nonce: &str, timestamp: i64, now: i64) -> bool {
+        while let Some((time, ..)) = self.signed.first() {
+            if now - time <= MAX_CLOCK_SKEW {
+                break;
+            }
+            if let Some((_, device, nonce)) = self.signed.pop_first() {
+                self.seen.remove(&(device, nonce));
+            }
+        }
+        let key = (device.to_string(), nonce.to_string());
+        if self.seen.contains(&key) {
+            return false;
+        }
+        self.signed
+            .insert((timestamp, key.0.clone(), key.1.clone()));
+        self.seen.insert(key);
+        true
+    }
+}
+
+/// Computes the signature of a payload.
+pub fn sign(secret: &[u8], timestamp: i64, nonce: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(format!("{timestamp}\n{nonce}\n").as_bytes());
+    mac.update(body);
+    mac
+}
+
+/// Gets the value of a header as a string.
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Result<&'a str> {
+    req.headers()
+        .get(name)
+        .ok_or_else(|| ApiError::Unauthorized(format!("Missing {name} Header")))?
+        .to_str()
+        .map_err(|_| ApiError::Unauthorized(format!("Invalid {name} Header")))
+}
+
+#[derive(IntoParams, Debug)]
+#[into_params(parameter_in = Header)]
+/// The headers of a signed request.
+pub struct SignatureHeaders {
+    #[param(rename = "X-Device-Id")]
+    /// The ID of the registered device sending the request.
+    device: String,
+    #[param(rename = "X-Timestamp")]
+    /// The UNIX timestamp (in seconds) when the request is signed.
+    timestamp: i64,
+    #[param(rename = "X-Nonce")]
+    /// A random string that is never reused by the device.
+    nonce: String,
+    #[param(rename = "X-Signature", value_type = String)]
+    /// The hex encoded HMAC-SHA256 of `{timestamp}\n{nonce}\n{body}`.
+    signature: Vec<u8>,
+}
+
+impl SignatureHeaders {
+    /// Parses the signature headers of a request.
+    fn from_request(req: &HttpRequest) -> Result<Self> {
+        let device = header(req, DEVICE_HEADER)?.to_string();
+        let nonce = header(req, NONCE_HEADER)?.to_string();
+        let timestamp = header(req, TIMESTAMP_HEADER)?
+            .parse()
+            .map_err(|_| ApiError::Unauthorized(format!("Invalid {TIMESTAMP_HEADER} Header")))?;
+        let signature = hex::decode(header(req, SIGNATURE_HEADER)?)
+            .map_err(|_| ApiError::Unauthorized(format!("Invalid {SIGNATURE_HEADER} Header")))?;
+        if nonce.is_empty() || nonce.len() > MAX_NONCE_LENGTH {
+            return Err(ApiError::Unauthorized(format!(
+                "Invalid {NONCE_HEADER} Header"
+            )));
+        }
+        Ok(Self {
+            device,
+            timestamp,
+            nonce,
+            signature,
+        })
+    }
+
+    /// Checks that the request is signed within the allowed clock skew of the server.
+    fn check_time(&self, now: i64) -> Result<()> {
+        if (now - self.timestamp).abs() > MAX_CLOCK_SKEW {
+            return Err(ApiError::Unauthorized("Request Expired".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Checks the signature of a body with the secret of the device.
+    fn check_signature(&self, secret: &[u8], body: &[u8]) -> Result<()> {
+        sign(secret, self.timestamp, &self.nonce, body)
+            .verify_slice(&self.signature)
+            .map_err(|_| ApiError::Unauthorized("Invalid Signature".to_string()))
+    }
+
+    /// Records the nonce of the request, rejecting it if it has already been used.
+    fn check_nonce(&self, nonces: &mut NonceCache, now: i64) -> Result<()> {
+        if !nonces.insert(&self.device, &self.nonce, self.timestamp, now) {
+            return Err(ApiError::Unauthorized("Nonce Already Used".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Verifies the signature of a request, returning the ID of the device which signed it.
+async fn verify(req: &HttpRequest, body: &[u8]) -> Result<String> {
+    let headers = SignatureHeaders::from_request(req)?;
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    headers.check_time(now)?;
+
+    let state = req
+        .app_data::<Data<AppState>>()
+        .expect("AppState should be registered");
+    let secret = sqlx::query_scalar!("SELECT secret FROM devices WHERE id = $1", headers.device)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or_else(|| ApiError::Unauthorized("Unknown Device".to_string()))?;
+    headers.check_signature(secret.as_bytes(), body)?;
+    headers.check_nonce(&mut state.nonces.lock().unwrap(), now)?;
+    Ok(headers.device)
+}
+
+#[derive(Debug)]
+/// A body which has been verified to be signed by a registered device.
+pub struct Signed<T> {
+    /// The ID of the device which signed the body.
+    pub device: String,
+    /// The deserialized body.
+    pub body: T,
+}
+
+impl<T> std::ops::Deref for Signed<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.body
+    }
+}
+
+impl<T: Decode + 'static> FromRequest for Signed<T> {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let body = Bytes::from_request(&req, payload);
+        Box::pin(async move {
+            let body = body
+                .await
+                .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+            let device = verify(&req, &body).await?;
+            let body = encoding::decode(&req, &body)?;
+            Ok(Signed { device, body })
+        })
+    }
+}
+
+#[derive(Debug)]
+/// A text body which has been verified to be signed by a registered device.
+pub struct SignedText {
+    /// The ID of the device which signed the body.
+    pub device: String,
+    /// The text of the body.
+    pub body: String,
+}
+
+impl FromRequest for SignedText {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let body = Bytes::from_request(&req, payload);
+        Box::pin(async move {
+            let body = body
+                .await
+                .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+            let device = verify(&req, &body).await?;
+            let body = String::from_utf8(body.to_vec())
+                .map_err(|_| ApiError::BadRequest("Body Is Not UTF-8 Text".to_string()))?;
+            Ok(SignedText { device, body })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test::TestRequest, HttpRequest};
+    use hmac::Mac;
+
+    use super::{
+        sign, NonceCache, SignatureHeaders, DEVICE_HEADER, MAX_CLOCK_SKEW, NONCE_HEADER,
+        SIGNATURE_HEADER, TIMESTAMP_HEADER,
+    };
+    use crate::error::ApiError;
+
+    /// The secret of the device of the tests.
+    const SECRET: &[u8] = b"secret";
+    /// The time of the server in the tests.
+    const NOW: i64 = 1_700_000_000;
+
+    /// Makes a request of a device signed with the secret.
+    fn signed(device: &str, timestamp: i64, nonce: &str, body: &[u8]) -> HttpRequest {
+        let signature = hex::encode(sign(SECRET, timestamp, nonce, body).finalize().into_bytes());
+        TestRequest::default()
+            .insert_header((DEVICE_HEADER, device))
+            .insert_header((TIMESTAMP_HEADER, timestamp.to_string()))
+            .insert_header((NONCE_HEADER, nonce))
+            .insert_header((SIGNATURE_HEADER, signature))
+            .to_http_request()
+    }
+
+    /// The message of an unauthorized request.
+    fn unauthorized(result: Result<(), ApiError>) -> String {
+        match result {
+            Err(ApiError::Unauthorized(message)) => message,
+            result => panic!("Request Not Rejected: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn signature_is_verified() {
+        let headers = SignatureHeaders::from_request(&signed("d1", NOW, "n1", b"{}")).unwrap();
+        assert!(headers.check_signature(SECRET, b"{}").is_ok());
+        assert_eq!(
+            unauthorized(headers.check_signature(SECRET, b"{ }")),
+            "Invalid Signature"
+        );
+        assert_eq!(
+            unauthorized(headers.check_signature(b"other", b"{}")),
+            "Invalid Signature"
+        );
+        let mut forged = headers;
+        forged.timestamp += 1;
+        assert_eq!(
+            unauthorized(forged.check_signature(SECRET, b"{}")),
+            "Invalid Signature"
+        );
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        let missing = TestRequest::default().to_http_request();
+        assert!(SignatureHeaders::from_request(&missing).is_err());
+        let long = "n".repeat(65);
+        assert!(SignatureHeaders::from_request(&signed("d1", NOW, &long, b"")).is_err());
+        let not_hex = TestRequest::default()
+            .insert_header((DEVICE_HEADER, "d1"))
+            .insert_header((TIMESTAMP_HEADER, NOW.to_string()))
+            .insert_header((NONCE_HEADER, "n1"))
+            .insert_header((SIGNATURE_HEADER, "zz"))
+            .to_http_request();
+        assert!(SignatureHeaders::from_request(&not_hex).is_err());
+    }
+
+    #[test]
+    fn skewed_requests_are_rejected() {
+        for (timestamp, accepted) in [
+            (NOW - MAX_CLOCK_SKEW, true),
+            (NOW + MAX_CLOCK_SKEW, true),
+            (NOW - MAX_CLOCK_SKEW - 1, false),
+            (NOW + MAX_CLOCK_SKEW + 1, false),
+        ] {
+            let request = signed("d1", timestamp, "n1", b"");
+            let headers = SignatureHeaders::from_request(&request).unwrap();
+            assert_eq!(headers.check_time(NOW).is_ok(), accepted, "{timestamp}");
+        }
+    }
+
+    #[test]
+    fn replayed_nonces_are_rejected() {
+        let mut nonces = NonceCache::default();
+        let headers = SignatureHeaders::from_request(&signed("d1", NOW, "n1", b"")).unwrap();
+        assert!(headers.check_nonce(&mut nonces, NOW).is_ok());
+        assert_eq!(
+            unauthorized(headers.check_nonce(&mut nonces, NOW + MAX_CLOCK_SKEW)),
+            "Nonce Already Used"
+        );
+        // The nonces are per device
+        let other = SignatureHeaders::from_request(&signed("d2", NOW, "n1", b"")).unwrap();
+        assert!(other.check_nonce(&mut nonces, NOW).is_ok());
+    }
+
+    #[test]
+    fn expired_nonces_are_pruned() {
+        let mut nonces = NonceCache::default();
+        for i in 0..100 {
+            assert!(nonces.insert("d1", &format!("n{i}"), NOW + i, NOW + i));
+        }
+        // A nonce signed later is kept until the nonces signed before it are pruned
+        assert!(nonces.insert("d1", "late", NOW + MAX_CLOCK_SKEW, NOW));
+        assert!(!nonces.insert("d1", "n99", NOW + 99, NOW + 99 + MAX_CLOCK_SKEW));
+        assert_eq!(nonces.seen.len(), 2);
+        assert_eq!(nonces.signed.len(), 2);
+        assert!(nonces.insert("d1", "n0", NOW, NOW + 100 + MAX_CLOCK_SKEW));
+        assert!(!nonces.insert("d1", "late", NOW + MAX_CLOCK_SKEW, NOW + 2 * MAX_CLOCK_SKEW));
+    }
+}
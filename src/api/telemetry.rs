@@ -0,0 +1,143 @@
+//! Module for Actix services for the telemetry of the devices.
+use actix_web::{
+    get, post,
+    web::{scope, Data, Json, Query, ServiceConfig},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::{
+    alerts::{self, Observation},
+    encoding::Decode,
+    signature::{SignatureHeaders, Signed},
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_telemetry, add_telemetry),
+    components(schemas(TelemetryValues, TelemetryInput))
+)]
+/// The OpenAPI specification of the telemetry API resources.
+pub struct TelemetryApi;
+
+/// Configuration function for the telemetry API resources.
+pub fn telemetry_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/telemetry")
+            .service(get_telemetry)
+            .service(add_telemetry),
+    );
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+/// The query specification for getting telemetry.
+struct TelemetryQuery {
+    /// The device to get the telemetry of, all devices if not given.
+    device: Option<String>,
+    #[serde(default = "TelemetryQuery::count_default")]
+    /// The amount of telemetry to get.
+    count: i64,
+}
+
+impl TelemetryQuery {
+    /// Defaults count to 100 reports.
+    fn count_default() -> i64 {
+        100
+    }
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+/// The data format for telemetry.
+struct TelemetryValues {
+    /// The device which reported the telemetry.
+    device: String,
+    #[serde(with = "time::serde::rfc3339")]
+    /// The time the telemetry is reported.
+    time: OffsetDateTime,
+    /// The charge of the battery in percent.
+    battery: f64,
+}
+
+#[utoipa::path(
+    context_path = "/api/telemetry",
+    tag = "telemetry",
+    params(TelemetryQuery),
+    responses(
+        (status = 200, description = "The latest telemetry.", body = [TelemetryValues]),
+        (status = 400, description = "The query is not valid.", body = ErrorBody),
+    )
+)]
+#[get("")]
+/// Gets the latest telemetry of the devices.
+async fn get_telemetry(
+    query: Query<TelemetryQuery>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    let telemetry = sqlx::query_as!(
+        TelemetryValues,
+        "SELECT device, time, battery FROM telemetry
+WHERE ($1::TEXT IS NULL OR device = $1)
+ORDER BY time DESC LIMIT $2",
+        query.device,
+        query.count
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(telemetry))
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+/// The telemetry reported by a device.
+struct TelemetryInput {
+    #[schema(example = 87.5)]
+    /// The charge of the battery in percent.
+    battery: f64,
+}
+
+impl Decode for TelemetryInput {}
+
+#[utoipa::path(
+    context_path = "/api/telemetry",
+    tag = "telemetry",
+    params(SignatureHeaders),
+    request_body = TelemetryInput,
+    responses(
+        (status = 200, description = "The telemetry is inserted."),
+        (status = 400, description = "The body is not valid.", body = ErrorBody),
+        (status = 401, description = "The signature is not valid.", body = ErrorBody),
+    )
+)]
+#[post("")]
+/// Inserts the telemetry signed by a device into the database.
+async fn add_telemetry(
+    data: Signed<TelemetryInput>,
+    state: Data<AppState>,
+) -> Result<impl Responder> {
+    if !(0.0..=100.0).contains(&data.battery) {
+        return Err(ApiError::BadRequest(format!(
+            "Battery {} Is Not A Percentage",
+            data.battery
+        )));
+    }
+    sqlx::query!(
+        "INSERT INTO telemetry (device, battery) VALUES ($1, $2)",
+        data.device,
+        data.battery
+    )
+    .execute(&state.pool)
+    .await?;
+    tracing::info!(device = data.device, "Telemetry Inserted");
+    state.metrics.inserted("telemetry", 1);
+    alerts::observe(&state, &data.device, Observation::Battery(data.battery)).await;
+    alerts::seen(&state, &data.device).await;
+    Ok("")
+}
@@ -0,0 +1,104 @@
+//! Smoothing and simplification of the gps tracks for rendering.
+
+use super::{geofence::distance_to_segment, Coordinates};
+
+/// Smooths a track with a centred moving average of the fixes within a number of fixes on
+/// either side.
+pub fn smooth(track: &[Coordinates], neighbours: usize) -> Vec<Coordinates> {
+    (0..track.len())
+        .map(|i| {
+            let window = &track[i.saturating_sub(neighbours)
+                ..i.saturating_add(neighbours)
+                    .saturating_add(1)
+                    .min(track.len())];
+            let count = window.len() as f64;
+            Coordinates {
+                latitude: window.iter().map(|c| c.latitude).sum::<f64>() / count,
+                longitude: window.iter().map(|c| c.longitude).sum::<f64>() / count,
+            }
+        })
+        .collect()
+}
+
+/// Marks the fixes kept when simplifying a track with the Douglas–Peucker algorithm, the first
+/// and last fixes are always kept.
+pub fn simplify(track: &[Coordinates], tolerance: f64) -> Vec<bool> {
+    let mut keep = vec![track.len() <= 2; track.len()];
+    if track.len() <= 2 {
+        return keep;
+    }
+    keep[0] = true;
+    keep[track.len() - 1] = true;
+    // Iterative to not overflow the stack on long tracks
+    let mut sections = vec![(0, track.len() - 1)];
+    while let Some((start, end)) = sections.pop() {
+        let farthest = (start + 1..end)
+            .map(|i| {
+                let distance = distance_to_segment(&track[i], &track[start], &track[end]);
+                (i, distance)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, distance)) = farthest {
+            if distance > tolerance {
+                keep[i] = true;
+                sections.push((start, i));
+                sections.push((i, end));
+            }
+        }
+    }
+    keep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{simplify, smooth};
+    use crate::api::Coordinates;
+
+    /// Creates a coordinate.
+    fn at(latitude: f64, longitude: f64) -> Coordinates {
+        Coordinates {
+            latitude,
+            longitude,
+        }
+    }
+
+    #[test]
+    fn simplify_keeps_the_ends() {
+        let track = [at(0.0, 0.0), at(0.0, 0.001), at(0.0, 0.002), at(0.0, 0.003)];
+        assert_eq!(simplify(&track, 1.0), [true, false, false, true]);
+        assert_eq!(simplify(&track[..2], 1000.0), [true, true]);
+        assert_eq!(simplify(&track[..1], 1000.0), [true]);
+        assert!(simplify(&[], 1.0).is_empty());
+    }
+
+    #[test]
+    fn simplify_keeps_the_fixes_beyond_the_tolerance() {
+        // The middle fix is about 111 m from the line between the ends
+        let track = [at(0.0, 0.0), at(0.001, 0.001), at(0.0, 0.002)];
+        assert_eq!(simplify(&track, 100.0), [true, true, true]);
+        assert_eq!(simplify(&track, 120.0), [true, false, true]);
+    }
+
+    #[test]
+    fn smooth_averages_the_neighbours() {
+        let track = [at(0.0, 0.0), at(3.0, 3.0), at(0.0, 6.0), at(3.0, 9.0)];
+        let smoothed = smooth(&track, 1);
+        assert_eq!(smoothed.len(), track.len());
+        let latitudes: Vec<f64> = smoothed.iter().map(|c| c.latitude).collect();
+        let longitudes: Vec<f64> = smoothed.iter().map(|c| c.longitude).collect();
+        assert_eq!(latitudes, [1.5, 1.0, 2.0, 1.5]);
+        assert_eq!(longitudes, [1.5, 3.0, 6.0, 7.5]);
+    }
+
+    #[test]
+    fn smooth_with_wide_windows() {
+        let track = [at(0.0, 0.0), at(2.0, 4.0)];
+        for neighbours in [1, usize::MAX] {
+            let smoothed = smooth(&track, neighbours);
+            assert!(smoothed
+                .iter()
+                .all(|c| c.latitude == 1.0 && c.longitude == 2.0));
+        }
+        assert!(smooth(&[], 1).is_empty());
+    }
+}
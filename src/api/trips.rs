@@ -1,24 +1,52 @@
 //! Module for Actix services for all the trips.
 
 use actix_web::{
-    error::ErrorBadRequest,
     get, post,
-    web::{self, Data, Json, ServiceConfig},
-    Responder, Result,
+    web::{self, Data, Json, Path, ServiceConfig},
+    Responder,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::{FromRow, PgPool};
 use time::OffsetDateTime;
+use utoipa::{OpenApi, ToSchema};
 use uuid::Uuid;
 
-use crate::AppState;
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+use super::paths::path_exists;
 
 /// Configuration function for the trips API resources.
 pub fn trips_cfg(cfg: &mut ServiceConfig) {
-    cfg.service(web::scope("/trips").service(get_trips).service(start_trip));
+    cfg.service(
+        web::scope("/trips")
+            .service(get_trips)
+            .service(start_trip)
+            .service(get_trip),
+    );
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_trips, get_trip, start_trip),
+    components(schemas(TripValues, TripSummary, TripResponse, TripInput))
+)]
+/// The OpenAPI specification of the trips API resources.
+pub struct TripsApi;
+
+/// Checks whether a trip exists.
+pub async fn trip_exists(pool: &PgPool, uuid: Uuid) -> Result<bool> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM trips WHERE uuid = $1) AS "exists!""#,
+        uuid
+    )
+    .fetch_one(pool)
+    .await?)
 }
 
-#[derive(Serialize, FromRow)]
+#[derive(Serialize, FromRow, ToSchema)]
 /// The data format for trips data.
 struct TripValues {
     /// The UUID of the trip.
@@ -30,33 +58,105 @@ struct TripValues {
     path: Uuid,
 }
 
+#[utoipa::path(
+    context_path = "/api/trips",
+    tag = "trips",
+    responses((status = 200, description = "All the trips.", body = [TripValues]))
+)]
 #[get("")]
 /// Gets all the trips data.
 async fn get_trips(state: Data<AppState>) -> Result<impl Responder> {
     let trips = sqlx::query_as!(TripValues, "SELECT uuid, time, path FROM trips")
         .fetch_all(&state.pool)
-        .await
-        .map_err(|e| ErrorBadRequest(e.to_string()))?;
+        .await?;
     Ok(Json(trips))
 }
 
-#[derive(Serialize, FromRow)]
+#[derive(Serialize, FromRow, ToSchema)]
+/// The summary of a trip.
+struct TripSummary {
+    /// The UUID of the trip.
+    uuid: Uuid,
+    #[serde(with = "time::serde::rfc3339")]
+    /// When the trip started.
+    time: OffsetDateTime,
+    /// The path the trip is following.
+    path: Uuid,
+    /// The number of gps fixes recorded in the trip, without the poor fixes.
+    fixes: i64,
+    /// The number of gps fixes further from the path than its corridor.
+    off_path_fixes: i64,
+    /// The largest distance from the path in metres, `null` without fixes.
+    max_deviation: Option<f64>,
+    /// The mean distance from the path in metres, `null` without fixes.
+    mean_deviation: Option<f64>,
+    /// The number of times the path or operating area is breached.
+    events: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/trips",
+    tag = "trips",
+    params(("uuid" = Uuid, Path, description = "The UUID of the trip.")),
+    responses(
+        (status = 200, description = "The summary of the trip.", body = TripSummary),
+        (status = 404, description = "The trip does not exist.", body = ErrorBody),
+    )
+)]
+#[get("/{uuid}")]
+/// Gets the summary of a trip, including how closely the path is followed.
+async fn get_trip(uuid: Path<Uuid>, state: Data<AppState>) -> Result<impl Responder> {
+    let trip = sqlx::query_as!(
+        TripSummary,
+        r#"SELECT trips.uuid, trips.time, trips.path, COUNT(history.time) AS "fixes!",
+ COUNT(*) FILTER (WHERE history.deviation > paths.corridor) AS "off_path_fixes!",
+ MAX(history.deviation) AS max_deviation, AVG(history.deviation) AS mean_deviation,
+ (SELECT COUNT(*) FROM events WHERE events.trip = trips.uuid) AS "events!"
+FROM trips
+JOIN paths ON paths.uuid = trips.path
+LEFT JOIN history ON history.trip = trips.uuid AND NOT history.poor
+WHERE trips.uuid = $1
+GROUP BY trips.uuid, paths.uuid"#,
+        *uuid
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Trip {}", *uuid)))?;
+    Ok(Json(trip))
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
 /// The reponse message for starting a new trip.
 struct TripResponse {
     /// The UUID of the new trip.
     uuid: Uuid,
 }
 
-#[derive(Deserialize, FromRow)]
+#[derive(Deserialize, FromRow, ToSchema)]
 /// The input data format for inserting trip.
 struct TripInput {
     /// The ptath the trip is following.
     path: Uuid,
 }
 
+#[utoipa::path(
+    context_path = "/api/trips",
+    tag = "trips",
+    request_body = TripInput,
+    responses(
+        (status = 200, description = "The trip is started.", body = TripResponse),
+        (status = 422, description = "The path does not exist.", body = ErrorBody),
+    )
+)]
 #[post("")]
 /// Starts a new trip.
 async fn start_trip(trip: Json<TripInput>, state: Data<AppState>) -> Result<impl Responder> {
+    if !path_exists(&state.pool, trip.path).await? {
+        return Err(ApiError::Unprocessable(format!(
+            "Path {} Does Not Exist",
+            trip.path
+        )));
+    }
     let trip = sqlx::query_as!(
         TripResponse,
         "INSERT INTO trips (uuid, time, path) VALUES ($1, CURRENT_TIMESTAMP, $2) RETURNING uuid",
@@ -64,7 +164,7 @@ async fn start_trip(trip: Json<TripInput>, state: Data<AppState>) -> Result<impl
         trip.path
     )
     .fetch_one(&state.pool)
-    .await
-    .map_err(|e| ErrorBadRequest(e.to_string()))?;
+    .await?;
+    state.metrics.inserted("trips", 1);
     Ok(Json(trip))
 }
@@ -0,0 +1,123 @@
+//! Error type shared by all the API handlers.
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use derive_more::Display;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Debug, Display)]
+/// An enumeration of all the errors the API responds with.
+pub enum ApiError {
+    #[display(fmt = "{}", _0)]
+    /// The request is malformed.
+    BadRequest(String),
+    #[display(fmt = "Bad JSON Data: {}", _0)]
+    /// The request body is not valid JSON for the resource.
+    InvalidJson(String),
+    #[display(fmt = "{}", _0)]
+    /// The request is not properly authenticated.
+    Unauthorized(String),
+    #[display(fmt = "{} Not Found", _0)]
+    /// The requested resource does not exist.
+    NotFound(String),
+    #[display(fmt = "{}", _0)]
+    /// The resource conflicts with an existing resource.
+    Conflict(String),
+    #[display(fmt = "{}", _0)]
+    /// The request body is in an encoding the resource does not accept.
+    UnsupportedMediaType(String),
+    #[display(fmt = "{}", _0)]
+    /// The request is well-formed but refers to missing or invalid resources.
+    Unprocessable(String),
+    #[display(fmt = "An Internal Error Occurred")]
+    /// An unexpected server error, the details are only logged.
+    Internal(String),
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+/// The JSON body of an error response.
+pub struct ErrorBody<'a> {
+    /// The machine readable code of the error.
+    code: &'a str,
+    /// The human readable message of the error.
+    message: String,
+    /// The ID of the request which caused the error.
+    request_id: Option<&'a str>,
+}
+
+/// A specialized `Result` type for the API handlers.
+pub type Result<T, E = ApiError> = std::result::Result<T, E>;
+
+impl ApiError {
+    /// The machine readable code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::InvalidJson(_) => "invalid_json",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::Unprocessable(_) => "unprocessable_entity",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Creates the JSON response for the error, tagged with the ID of the request.
+    pub fn response(&self, request_id: Option<&str>) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            request_id,
+        })
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::InvalidJson(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.response(None)
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(value: sqlx::Error) -> Self {
+        match value {
+            sqlx::Error::RowNotFound => ApiError::NotFound("Resource".to_string()),
+            sqlx::Error::Database(e) if e.is_foreign_key_violation() => {
+                ApiError::Unprocessable(format!(
+                    "Referenced Resource Does Not Exist ({})",
+                    e.constraint().unwrap_or("unknown")
+                ))
+            }
+            sqlx::Error::Database(e) if e.is_unique_violation() => ApiError::Conflict(format!(
+                "Resource Already Exists ({})",
+                e.constraint().unwrap_or("unknown")
+            )),
+            e => ApiError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(value: serde_json::Error) -> Self {
+        ApiError::Internal(value.to_string())
+    }
+}
+
+impl From<csv::Error> for ApiError {
+    fn from(value: csv::Error) -> Self {
+        ApiError::Internal(value.to_string())
+    }
+}
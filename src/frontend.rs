@@ -8,12 +8,26 @@ use actix_web::{
     HttpRequest, HttpResponse, Responder,
 };
 
+/// The directory the static frontend is served from.
+pub const FRONTEND_DIR: &str = "./frontend/src";
+
+/// The directory the Swagger UI assets of the documentation are served from.
+pub const SWAGGER_UI_DIR: &str = "./swagger-ui";
+
 #[get("/")]
 /// Route to redirect web root to index.html
 async fn index() -> impl Responder {
     Redirect::to("/index.html").permanent()
 }
 
+#[get("/docs")]
+/// Route to the interactive documentation of the API.
+async fn docs() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("docs.html"))
+}
+
 /// Service handler for NotFound Response.
 fn directory(_: &fs::Directory, req: &HttpRequest) -> Result<ServiceResponse, std::io::Error> {
     let response = HttpResponse::NotFound().finish();
@@ -22,9 +36,12 @@ fn directory(_: &fs::Directory, req: &HttpRequest) -> Result<ServiceResponse, st
 
 /// Configuration function for the application.
 pub fn frontend_cfg(cfg: &mut ServiceConfig) {
-    cfg.service(index).service(
-        fs::Files::new("/", "./frontend/src")
-            .files_listing_renderer(directory)
-            .show_files_listing(),
-    );
+    cfg.service(index)
+        .service(docs)
+        .service(fs::Files::new("/docs/assets", SWAGGER_UI_DIR))
+        .service(
+            fs::Files::new("/", FRONTEND_DIR)
+                .files_listing_renderer(directory)
+                .show_files_listing(),
+        );
 }
@@ -0,0 +1,167 @@
+//! Health and readiness checks for the process supervisor.
+
+use std::{collections::BTreeMap, path::Path, time::Instant};
+
+use actix_web::{
+    get,
+    web::{Data, ServiceConfig},
+    HttpResponse, Responder,
+};
+use serde::Serialize;
+
+use crate::{frontend::FRONTEND_DIR, AppState};
+
+/// The schema applied when the server starts.
+pub const SCHEMA: &str = include_str!("../schema.sql");
+
+/// Configuration function for the health check resources.
+pub fn health_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(healthz).service(readyz);
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// An enumeration of the status of a component.
+enum Status {
+    /// The component is working.
+    Ok,
+    /// The component is not working.
+    Unavailable,
+}
+
+#[derive(Serialize, Debug)]
+/// The status of a single component.
+struct ComponentStatus {
+    /// Whether the component is working.
+    status: Status,
+    /// How long the check took in milliseconds.
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The reason the component is not working.
+    error: Option<String>,
+}
+
+impl ComponentStatus {
+    /// Creates the status of a component from the result of its check.
+    fn new(start: Instant, result: Result<(), String>) -> Self {
+        let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+        match result {
+            Ok(()) => Self {
+                status: Status::Ok,
+                latency_ms,
+                error: None,
+            },
+            Err(error) => Self {
+                status: Status::Unavailable,
+                latency_ms,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+/// The response of the health checks.
+struct HealthResponse {
+    /// The overall status of the server.
+    status: Status,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    /// The status of each component.
+    components: BTreeMap<&'static str, ComponentStatus>,
+}
+
+#[get("/healthz")]
+/// Checks whether the process is alive.
+async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(HealthResponse {
+        status: Status::Ok,
+        components: BTreeMap::new(),
+    })
+}
+
+/// The tables created by the schema.
+fn schema_tables() -> Vec<String> {
+    SCHEMA
+        .lines()
+        .filter_map(|line| line.strip_prefix("CREATE TABLE IF NOT EXISTS "))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Checks whether the database is reachable.
+async fn check_database(state: &AppState) -> Result<(), String> {
+    sqlx::query!("SELECT 1 AS ping")
+        .fetch_one(&state.pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Checks whether all the tables in the schema exist.
+async fn check_migrations(state: &AppState) -> Result<(), String> {
+    let tables = schema_tables();
+    let existing = sqlx::query_scalar!(
+        "SELECT tablename::TEXT FROM pg_tables WHERE schemaname = current_schema() AND tablename = ANY($1)",
+        &tables
+    )
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    let missing: Vec<_> = tables
+        .iter()
+        .filter(|table| {
+            !existing
+                .iter()
+                .any(|e| e.as_deref() == Some(table.as_str()))
+        })
+        .map(String::as_str)
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Missing Tables: {}", missing.join(", ")))
+    }
+}
+
+/// Checks whether the frontend directory is present.
+fn check_frontend() -> Result<(), String> {
+    if Path::new(FRONTEND_DIR).is_dir() {
+        Ok(())
+    } else {
+        Err(format!("{FRONTEND_DIR} Is Not A Directory"))
+    }
+}
+
+#[get("/readyz")]
+/// Checks whether the server is ready to serve requests.
+async fn readyz(state: Data<AppState>) -> impl Responder {
+    let mut components = BTreeMap::new();
+
+    let start = Instant::now();
+    let database = ComponentStatus::new(start, check_database(&state).await);
+    let database_ok = database.status == Status::Ok;
+    components.insert("database", database);
+
+    let start = Instant::now();
+    let migrations = if database_ok {
+        check_migrations(&state).await
+    } else {
+        Err("Database Is Unavailable".to_string())
+    };
+    components.insert("migrations", ComponentStatus::new(start, migrations));
+
+    let start = Instant::now();
+    components.insert("frontend", ComponentStatus::new(start, check_frontend()));
+
+    let status = if components.values().all(|c| c.status == Status::Ok) {
+        Status::Ok
+    } else {
+        Status::Unavailable
+    };
+    let response = HealthResponse { status, components };
+    match status {
+        Status::Ok => HttpResponse::Ok().json(response),
+        Status::Unavailable => HttpResponse::ServiceUnavailable().json(response),
+    }
+}
@@ -0,0 +1,25 @@
+//! Logging configuration of the server.
+
+use tracing_subscriber::EnvFilter;
+
+/// The environment variable selecting the log format, either `json` or `text`.
+pub const LOG_FORMAT_ENV: &str = "LOG_FORMAT";
+
+/// Installs the global tracing subscriber.
+///
+/// The log level is configured through `RUST_LOG`, defaulting to `info`.
+pub fn init_logging() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match std::env::var(LOG_FORMAT_ENV).as_deref() {
+        Ok("json") => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .try_init(),
+        _ => builder.try_init(),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to install the tracing subscriber: {e}");
+    }
+}
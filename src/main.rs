@@ -3,21 +3,37 @@
 //! Copyright: ecyht2
 
 mod api;
+mod error;
 mod frontend;
+mod health;
+mod logging;
+mod metrics;
+mod request_id;
 
 use std::sync::Mutex;
 
 use actix_web::web;
 use actix_web::{web::ServiceConfig, HttpResponse, Responder};
-use api::{api_cfg, Colour};
+use api::{
+    api_cfg, check_silence, deliver_alerts, start_bridge, AlertSender, Colour, NonceCache,
+    ALERT_QUEUE_SIZE, WEBHOOK_TIMEOUT,
+};
 use frontend::frontend_cfg;
+use health::{health_cfg, SCHEMA};
+use logging::init_logging;
+use metrics::{metrics_cfg, track_requests, Metrics};
+use request_id::request_id;
 use shuttle_actix_web::ShuttleActixWeb;
 use shuttle_runtime::CustomError;
 use sqlx::{Executor, PgPool};
+use tokio::sync::mpsc;
 
 pub struct AppState {
     pub pool: PgPool,
     pub colour: Mutex<Colour>,
+    pub nonces: Mutex<NonceCache>,
+    pub metrics: Metrics,
+    pub alerts: AlertSender,
 }
 
 /// Service handler for NotFound Response.
@@ -29,20 +45,38 @@ async fn not_found() -> impl Responder {
 async fn actix_web(
     #[shuttle_shared_db::Postgres(local_uri = "{secrets.DB}")] pool: PgPool,
 ) -> ShuttleActixWeb<impl FnOnce(&mut ServiceConfig) + Send + Clone + 'static> {
+    init_logging();
+
     // Creating tables
-    pool.execute(include_str!("../schema.sql"))
-        .await
-        .map_err(CustomError::new)?;
+    pool.execute(SCHEMA).await.map_err(CustomError::new)?;
 
+    let metrics = Metrics::new().map_err(CustomError::new)?;
+    // Redirects are not followed, as they could lead the webhooks to internal networks
+    let client = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(CustomError::new)?;
+    let (alerts, deliveries) = mpsc::channel(ALERT_QUEUE_SIZE);
+    tokio::spawn(deliver_alerts(pool.clone(), client, deliveries));
     let state = web::Data::new(AppState {
         pool,
         colour: Mutex::new(Colour::Red),
+        nonces: Mutex::new(NonceCache::default()),
+        metrics: metrics.clone(),
+        alerts,
     });
+    tokio::spawn(check_silence(state.clone()));
+    start_bridge(state.clone()).map_err(CustomError::new)?;
 
     let config = move |cfg: &mut ServiceConfig| {
         cfg.service(
             web::scope("")
+                .wrap_fn(move |req, srv| track_requests(&metrics, req, srv))
+                .wrap_fn(request_id)
                 .configure(api_cfg)
+                .configure(health_cfg)
+                .configure(metrics_cfg)
                 .configure(frontend_cfg)
                 .app_data(state)
                 .default_service(web::route().to(not_found)),
@@ -0,0 +1,218 @@
+//! Prometheus metrics of the server.
+
+use std::{future::Future, time::Instant};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse},
+    get,
+    web::{Data, ServiceConfig},
+    Error, HttpResponse, Responder,
+};
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use time::OffsetDateTime;
+
+use crate::{
+    error::{ApiError, Result},
+    AppState,
+};
+
+/// Configuration function for the metrics resources.
+pub fn metrics_cfg(cfg: &mut ServiceConfig) {
+    cfg.service(get_metrics);
+}
+
+#[derive(Clone)]
+/// All the metrics collected by the server.
+pub struct Metrics {
+    /// The registry all the metrics are registered in.
+    registry: Registry,
+    /// The number of requests handled, by route and status.
+    requests: IntCounterVec,
+    /// The time taken to handle requests, by route.
+    latency: HistogramVec,
+    /// The number of connections in the database pool, by state.
+    pool_connections: IntGaugeVec,
+    /// The maximum number of connections in the database pool.
+    pool_max_connections: IntGauge,
+    /// The number of rows inserted, by table.
+    rows_inserted: IntCounterVec,
+    /// The number of rejected payloads, by route and reason.
+    rejected_payloads: IntCounterVec,
+    /// The time of the last GPS fix, by trip and device.
+    last_gps_fix: IntGaugeVec,
+    /// The time of the last data reading, by trip and device.
+    last_data_reading: IntGaugeVec,
+}
+
+impl Metrics {
+    /// Creates and registers all the metrics.
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("awtc".to_string()), None)?;
+        let metrics = Self {
+            requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "Number of HTTP requests handled."),
+                &["method", "route", "status"],
+            )?,
+            latency: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "Time taken to handle HTTP requests.",
+                ),
+                &["method", "route"],
+            )?,
+            pool_connections: IntGaugeVec::new(
+                Opts::new(
+                    "db_pool_connections",
+                    "Number of connections in the database pool.",
+                ),
+                &["state"],
+            )?,
+            pool_max_connections: IntGauge::new(
+                "db_pool_max_connections",
+                "Maximum number of connections in the database pool.",
+            )?,
+            rows_inserted: IntCounterVec::new(
+                Opts::new("rows_inserted_total", "Number of rows inserted."),
+                &["table"],
+            )?,
+            rejected_payloads: IntCounterVec::new(
+                Opts::new("rejected_payloads_total", "Number of rejected payloads."),
+                &["route", "reason"],
+            )?,
+            last_gps_fix: IntGaugeVec::new(
+                Opts::new(
+                    "last_gps_fix_timestamp_seconds",
+                    "UNIX timestamp of the last GPS fix.",
+                ),
+                &["trip", "device"],
+            )?,
+            last_data_reading: IntGaugeVec::new(
+                Opts::new(
+                    "last_data_reading_timestamp_seconds",
+                    "UNIX timestamp of the last data reading.",
+                ),
+                &["trip", "device"],
+            )?,
+            registry,
+        };
+        metrics
+            .registry
+            .register(Box::new(metrics.requests.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.latency.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.pool_connections.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.pool_max_connections.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.rows_inserted.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.rejected_payloads.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.last_gps_fix.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.last_data_reading.clone()))?;
+        Ok(metrics)
+    }
+
+    /// Records rows inserted into a table.
+    pub fn inserted(&self, table: &str, rows: u64) {
+        self.rows_inserted.with_label_values(&[table]).inc_by(rows);
+    }
+
+    /// Records a GPS fix from a device, in a trip or with an empty trip if it is not in one.
+    pub fn gps_fix(&self, trip: &str, device: &str, time: OffsetDateTime) {
+        self.last_gps_fix
+            .with_label_values(&[trip, device])
+            .set(time.unix_timestamp());
+    }
+
+    /// Records a data reading from a device in a trip.
+    pub fn data_reading(&self, trip: &str, device: &str, time: OffsetDateTime) {
+        self.last_data_reading
+            .with_label_values(&[trip, device])
+            .set(time.unix_timestamp());
+    }
+}
+
+/// Middleware function recording the count and latency of requests.
+///
+/// Failed requests with a body are also recorded as rejected payloads, by [`ApiError`] code.
+pub fn track_requests<S, B>(
+    metrics: &Metrics,
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let metrics = metrics.clone();
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let has_payload = matches!(req.method().as_str(), "POST" | "PUT" | "PATCH");
+    let response = srv.call(req);
+
+    async move {
+        let response = response.await?;
+        let status = response.status();
+        metrics
+            .requests
+            .with_label_values(&[&method, &route, status.as_str()])
+            .inc();
+        metrics
+            .latency
+            .with_label_values(&[&method, &route])
+            .observe(start.elapsed().as_secs_f64());
+        if has_payload && status.is_client_error() {
+            let reason = response
+                .response()
+                .error()
+                .and_then(|e| e.as_error::<ApiError>())
+                .map_or("other", ApiError::code);
+            metrics
+                .rejected_payloads
+                .with_label_values(&[&route, reason])
+                .inc();
+        }
+        Ok(response)
+    }
+}
+
+#[get("/metrics")]
+/// Gets the metrics in the Prometheus text format.
+async fn get_metrics(state: Data<AppState>) -> Result<impl Responder> {
+    let metrics = &state.metrics;
+    let idle = state.pool.num_idle() as i64;
+    metrics
+        .pool_connections
+        .with_label_values(&["idle"])
+        .set(idle);
+    metrics
+        .pool_connections
+        .with_label_values(&["active"])
+        .set(i64::from(state.pool.size()) - idle);
+    metrics
+        .pool_max_connections
+        .set(i64::from(state.pool.options().get_max_connections()));
+
+    let body = TextEncoder::new()
+        .encode_to_string(&metrics.registry.gather())
+        .map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
+}
@@ -0,0 +1,81 @@
+//! Middleware tagging every request with a unique ID.
+
+use std::{future::Future, time::Instant};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+
+/// The header the request ID is read from and echoed in.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+/// The maximum length of a request ID supplied by the client.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Gets the request ID supplied by the client, if it is usable.
+fn incoming_id(req: &ServiceRequest) -> Option<String> {
+    let id = req.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let valid = !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    valid.then(|| id.to_string())
+}
+
+/// Middleware function assigning an ID to the request.
+///
+/// The ID of the client is honoured if supplied, otherwise a new one is generated. The
+/// request is handled within a tracing span carrying the ID, and the ID is echoed in the
+/// response headers and in the body of [`ApiError`] responses.
+pub fn request_id<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<BoxBody>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody + 'static,
+{
+    let id = incoming_id(&req).unwrap_or_else(|| Uuid::new_v4().to_string());
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        path = %req.path(),
+    );
+    let start = Instant::now();
+    let response = span.in_scope(|| srv.call(req));
+
+    async move {
+        let mut response = response.await?.map_into_boxed_body();
+        if let Some(err) = response
+            .response()
+            .error()
+            .and_then(|e| e.as_error::<ApiError>())
+        {
+            match err {
+                ApiError::Internal(e) => tracing::error!(code = err.code(), "{e}"),
+                _ => tracing::warn!(code = err.code(), "{err}"),
+            }
+            let body = err.response(Some(&id));
+            response = response.into_response(body);
+        }
+        tracing::info!(
+            status = response.status().as_u16(),
+            latency_ms = start.elapsed().as_secs_f64() * 1000.0,
+            "Request Completed"
+        );
+        response.headers_mut().insert(
+            REQUEST_ID_HEADER,
+            HeaderValue::from_str(&id).expect("Request IDs are valid header values"),
+        );
+        Ok(response)
+    }
+    .instrument(span)
+}